@@ -31,3 +31,11 @@ echo.
 echo === Testing A007 (If Condition Bool) ===
 cargo run --release -- run examples\a007_if_condition_bool.a
 echo.
+
+echo.
+echo === Testing Legacy Bytecode (v1 to v4 should all print "Hello three 3" then "done") ===
+cargo run --release -- run examples\legacy\legacy_program.v1.a.byte
+cargo run --release -- run examples\legacy\legacy_program.v2.a.byte
+cargo run --release -- run examples\legacy\legacy_program.v3.a.byte
+cargo run --release -- run examples\legacy\legacy_program.v4.a.byte
+echo.
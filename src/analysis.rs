@@ -1,18 +1,44 @@
 // src/analysis.rs
 
 use std::collections::HashMap;
+use std::fmt;
 
-use crate::ast::{expr_span, Expr, IfBranch, Program, Span, Stmt};
+use crate::builtins::{self, Builtin, Param, Ret};
+use crate::i18n;
+use crate::ast::{block_span, expr_span, stmt_span, Expr, FuncDecl, IfBranch, MatchArm, Program, Span, Stmt};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AType {
     Int,
+    Float,
     Bool,
     Char,
     Str,
+    /// List of items that all have the given type.
+    List(Box<AType>),
+    /// Map from keys of the first type to values of the second.
+    Map(Box<AType>, Box<AType>),
+    /// Value of the named `Struct`.
+    Record(String),
+    /// Value of the named `Enum`.
+    Enum(String),
+    /// What a call gives back when its function has no result (`Print`, or
+    /// a `Func` without `-> Type`). Using it as a value is A033.
+    Nothing,
     Unknown,
 }
 
+impl fmt::Display for AType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AType::List(item) => write!(f, "List({})", item),
+            AType::Map(k, v) => write!(f, "Map({}, {})", k, v),
+            AType::Record(name) | AType::Enum(name) => write!(f, "{}", name),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AError {
     pub span: Span,
@@ -21,268 +47,2045 @@ pub struct AError {
     pub mental_model: String,
     pub help: Vec<String>,
     pub example: String,
+    /// Other places worth pointing at (e.g. a declaration), with what to say about each.
+    pub labels: Vec<(Span, String)>,
     pub backend: Option<String>,
 }
 
+impl AError {
+    /// The lesson `key` of the message catalog (see `i18n.rs`): its title,
+    /// explanation, help and example, filled in with `args`. Its code is the
+    /// start of the key (`a001_...` is A001).
+    pub fn lesson(span: Span, key: &str, args: &[(&str, &dyn fmt::Display)]) -> AError {
+        let example = if i18n::has(key, "example") {
+            i18n::text(key, "example", args)
+        } else {
+            String::new()
+        };
+        AError {
+            span,
+            code: key.split('_').next().unwrap_or(key).to_ascii_uppercase(),
+            title: i18n::text(key, "title", args),
+            mental_model: i18n::text(key, "why", args),
+            help: i18n::lines(key, "help", args),
+            example,
+            labels: Vec::new(),
+            backend: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The program can't be built.
+    Error,
+    /// The program builds, but something in it is probably a mistake.
+    Warning,
+}
+
+/// A lesson shown as an error or as a warning (see `lint.rs`).
+#[derive(Debug, Clone)]
+pub struct ADiagnostic {
+    pub severity: Severity,
+    pub lesson: AError,
+}
+
+/// Parameter types and result type (`None`: gives nothing back) of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FnSig {
+    pub params: Vec<AType>,
+    pub ret: Option<AType>,
+}
+
+/// What a file offers the files that import it.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleInterface {
+    pub funcs: HashMap<String, FnSig>,
+}
+
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct AnalysisResult {
     pub locals: HashMap<String, usize>,
     pub local_types: Vec<AType>,
+    /// Operand type of each `+` and comparison that works on Floats or
+    /// Strings, so the compiler can pick the matching instruction.
+    pub op_types: HashMap<Span, AType>,
+    /// This file's functions, for the files that import it.
+    pub interface: ModuleInterface,
 }
 
-pub fn analyze(program: &Program) -> Result<AnalysisResult, Vec<AError>> {
-    // We'll walk statements sequentially, collecting locals and inferred types.
-    let mut locals: HashMap<String, usize> = HashMap::new();
-    let mut local_types: Vec<AType> = Vec::new();
+/// `imports`: the interface of each file this one imports, by module name.
+/// `prelude`: the functions of `prelude.a` (`None` while analyzing it).
+pub fn analyze(
+    program: &Program,
+    imports: &HashMap<String, ModuleInterface>,
+    prelude: Option<&ModuleInterface>,
+) -> Result<AnalysisResult, Vec<AError>> {
+    // We walk statements sequentially (into nested blocks too), collecting
+    // locals and inferred types. Blocks don't open a new scope; each function
+    // body gets its own locals.
+    let mut ctx = Ctx {
+        locals: HashMap::new(),
+        local_types: Vec::new(),
+        local_spans: Vec::new(),
+        maybe_unset: HashMap::new(),
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        variants: HashMap::new(),
+        funcs: HashMap::new(),
+        modules: imports.clone(),
+        prelude: prelude.cloned().unwrap_or_default(),
+        returns: None,
+        op_types: HashMap::new(),
+        errors: Vec::new(),
+    };
 
-    let mut errors: Vec<AError> = Vec::new();
+    check_decls(program, &mut ctx);
+    check_stmts(&program.stmts, &mut ctx);
+    for f in &program.funcs {
+        check_func(f, &mut ctx);
+    }
 
-    for s in &program.stmts {
-        match s {
-            Stmt::Let { name, expr, .. } | Stmt::Mute { name, expr, .. } => {
-                // infer the expression type using the current symbol table
-                let ty = infer_expr_type(expr, &locals, &local_types);
-                let idx = local_types.len();
-                locals.insert(name.clone(), idx);
-                local_types.push(ty);
+    let Ctx {
+        locals,
+        local_types,
+        funcs,
+        op_types,
+        errors,
+        ..
+    } = ctx;
+    if errors.is_empty() {
+        Ok(AnalysisResult {
+            locals,
+            local_types,
+            op_types,
+            interface: ModuleInterface { funcs },
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// What is set at one point of a body: variable name -> index into
+/// `Ctx::local_types`, and which of them may still be unset.
+#[derive(Clone)]
+struct Scope {
+    locals: HashMap<String, usize>,
+    maybe_unset: HashMap<String, Vec<(Span, String)>>,
+}
+
+/// How the program can get past an If, For or Match.
+#[derive(Debug, Clone, Copy)]
+enum Way {
+    /// Through one of its branches (or the loop body).
+    Branch,
+    /// Through one case of a Match.
+    Case,
+    /// Past an If without `Else` when no condition holds.
+    NoElse,
+    /// Past a For whose list is empty.
+    EmptyLoop,
+}
+
+/// Where one way ended: what was set then, and what to point at when it
+/// misses a variable.
+struct End {
+    scope: Scope,
+    span: Span,
+    way: Way,
+}
 
-                // still run deeper checks inside the expression
-                check_expr(expr, &locals, &local_types, &mut errors);
+struct Ctx {
+    locals: HashMap<String, usize>,
+    local_types: Vec<AType>,
+    /// Where each local was declared (same order as `local_types`).
+    local_spans: Vec<Span>,
+    /// Variables set on some ways to this point but not on others, with
+    /// labels for the ways that miss them (for A031).
+    maybe_unset: HashMap<String, Vec<(Span, String)>>,
+    /// Declared structs: field names and types in declaration order.
+    structs: HashMap<String, Vec<(String, AType)>>,
+    /// Declared enums: variant names and payload types in declaration order.
+    enums: HashMap<String, Vec<(String, Vec<AType>)>>,
+    /// Which enum each variant belongs to.
+    variants: HashMap<String, String>,
+    /// Functions declared in this file.
+    funcs: HashMap<String, FnSig>,
+    /// Imported files, by module name.
+    modules: HashMap<String, ModuleInterface>,
+    /// Functions of the prelude, called like builtins.
+    prelude: ModuleInterface,
+    /// Inside a function: what its `Return`s must give back.
+    returns: Option<Option<AType>>,
+    op_types: HashMap<Span, AType>,
+    errors: Vec<AError>,
+}
+
+impl Ctx {
+    /// `span`: where the variable is declared (for "declared here" labels).
+    fn declare(&mut self, name: &str, ty: AType, span: Span) {
+        let idx = self.local_types.len();
+        self.maybe_unset.remove(name);
+        self.locals.insert(name.to_string(), idx);
+        self.local_types.push(ty);
+        self.local_spans.push(span);
+    }
+
+    /// A001 for `name`, suggesting the closest declared variable (any letter
+    /// case counts as close) and pointing at its declaration.
+    fn undeclared(&self, span: Span, name: &str) -> AError {
+        let known = self.locals.keys().map(String::as_str);
+        let suggestion = did_you_mean(name, known).and_then(|s| {
+            let decl = *self.local_spans.get(*self.locals.get(&s)?)?;
+            Some((s, decl))
+        });
+        a001_undeclared_variable(span, name, suggestion)
+    }
+
+    fn scope(&self) -> Scope {
+        Scope {
+            locals: self.locals.clone(),
+            maybe_unset: self.maybe_unset.clone(),
+        }
+    }
+
+    fn restore(&mut self, scope: &Scope) {
+        self.locals = scope.locals.clone();
+        self.maybe_unset = scope.maybe_unset.clone();
+    }
+
+    fn end(&self, span: Span, way: Way) -> End {
+        End {
+            scope: self.scope(),
+            span,
+            way,
+        }
+    }
+
+    /// Continue after a branching statement that ended in one of `ends`
+    /// (ways that always `Return` are left out). A variable set on only some
+    /// of them may be unset from here on; one that got a different type on
+    /// different ways is A032.
+    fn join(&mut self, before: Scope, ends: Vec<End>) {
+        if ends.is_empty() {
+            // Nothing after this runs; keep checking it as if it did.
+            self.restore(&before);
+            return;
+        }
+
+        let mut names: Vec<&String> = ends.iter().flat_map(|e| e.scope.locals.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        let mut locals = HashMap::new();
+        let mut maybe_unset = HashMap::new();
+        for name in names {
+            let mut first: Option<usize> = None;
+            let mut conflict = false;
+            let mut labels: Vec<(Span, String)> = Vec::new();
+            for end in &ends {
+                let Some(&idx) = end.scope.locals.get(name) else {
+                    labels.push((end.span, way_label(end.way, name)));
+                    continue;
+                };
+                labels.extend(end.scope.maybe_unset.get(name).into_iter().flatten().cloned());
+                let Some(f) = first else {
+                    first = Some(idx);
+                    continue;
+                };
+                if f == idx || conflict {
+                    continue;
+                }
+                // Set separately on two ways: both must agree on the type.
+                let (a, b) = (self.local_types[f].clone(), self.local_types[idx].clone());
+                if compatible(&a, &b) {
+                    self.local_types[f] = merge(&a, &b);
+                } else {
+                    conflict = true;
+                    let spans = (self.local_spans[f], self.local_spans[idx]);
+                    self.errors.push(a032_type_depends_on_way(name, spans, (a, b)));
+                }
+            }
+            let Some(idx) = first else {
+                continue;
+            };
+            locals.insert(name.clone(), idx);
+            labels.dedup();
+            if !labels.is_empty() {
+                maybe_unset.insert(name.clone(), labels);
             }
+        }
+        self.locals = locals;
+        self.maybe_unset = maybe_unset;
+    }
 
-            Stmt::Assign { name, expr, .. } => {
-                // In A, a bare assignment `x = <expr>` declares `x` if it doesn't exist yet.
-                if let Some(&idx) = locals.get(name) {
-                    // existing variable: type-check the assignment
-                    let expected = &local_types[idx];
-                    let found = infer_expr_type(expr, &locals, &local_types);
-                    if *expected != AType::Unknown && found != AType::Unknown && *expected != found {
-                        errors.push(a002_assign_type_mismatch(expr_span(expr), expected.clone(), found));
-                    }
+    /// A031 when `name` is read where it may not have been set. Reported
+    /// once; afterwards it counts as set.
+    fn check_set(&mut self, name: &str, span: Span) {
+        if let Some(labels) = self.maybe_unset.remove(name) {
+            self.errors.push(a031_maybe_unset(span, name, labels));
+        }
+    }
+
+    fn type_of(&self, name: &str) -> Option<&AType> {
+        self.locals.get(name).and_then(|&i| self.local_types.get(i))
+    }
+
+    fn infer(&self, e: &Expr) -> AType {
+        infer_expr_type(e, self)
+    }
+
+    fn field_type(&self, record: &str, field: &str) -> Option<&AType> {
+        self.structs
+            .get(record)?
+            .iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, t)| t)
+    }
+
+    /// Enum name and payload types of a variant, e.g. `Circle` -> (Shape, [Int]).
+    fn variant(&self, name: &str) -> Option<(&str, &[AType])> {
+        let enum_name = self.variants.get(name)?;
+        let (_, payload) = self.enums.get(enum_name)?.iter().find(|(v, _)| v == name)?;
+        Some((enum_name.as_str(), payload.as_slice()))
+    }
+
+    /// A bare name that means a variant rather than a variable.
+    fn is_variant_name(&self, name: &str) -> bool {
+        self.type_of(name).is_none() && self.variants.contains_key(name)
+    }
+
+    /// Signature of a function of this file (`total`), an imported one
+    /// (`math.double`) or one of the prelude (`Max`, any letter case).
+    fn func(&self, name: &str) -> Option<&FnSig> {
+        match name.split_once('.') {
+            Some((module, f)) => self.modules.get(module)?.funcs.get(f),
+            None => self.funcs.get(name).or_else(|| self.prelude_func(name)),
+        }
+    }
+
+    fn prelude_func(&self, name: &str) -> Option<&FnSig> {
+        self.prelude
+            .funcs
+            .iter()
+            .find(|(f, _)| f.eq_ignore_ascii_case(name))
+            .map(|(_, sig)| sig)
+    }
+
+    /// A030 for a call of a function nobody declared, suggesting the closest
+    /// builtin, prelude or user function (or variant) name.
+    fn unknown_function(&self, span: Span, name: &str) -> AError {
+        let known = builtins::BUILTINS
+            .iter()
+            .map(|b| b.name)
+            .chain(self.prelude.funcs.keys().map(String::as_str))
+            .chain(self.funcs.keys().map(String::as_str))
+            .chain(self.variants.keys().map(String::as_str));
+        let suggestion = did_you_mean(name, known);
+        let suggestion = suggestion.map(|s| match self.func(&s) {
+            Some(sig) => signature(&s, sig),
+            None => match builtins::lookup(&s) {
+                Some(b) => b.usage.to_string(),
+                None => s,
+            },
+        });
+        a030_unknown_function(span, name, suggestion)
+    }
+
+    /// A name that already means one of A's own functions.
+    fn is_builtin(&self, name: &str) -> bool {
+        builtins::lookup(name).is_some() || self.prelude_func(name).is_some()
+    }
+
+    /// A015 for `record.field` when the struct has no such field.
+    fn no_such_field(&self, span: Span, record: &str, field: &str) -> AError {
+        let fields = self.structs.get(record).map(Vec::as_slice).unwrap_or(&[]);
+        let suggestion = did_you_mean(field, fields.iter().map(|(f, _)| f.as_str()));
+        a015_no_such_field(span, record, field, suggestion)
+    }
+
+    /// Learn more about a variable's type, e.g. `[]` (List of Unknown) once
+    /// something is pushed into it.
+    fn refine(&mut self, name: &str, ty: AType) {
+        if let Some(&i) = self.locals.get(name) {
+            self.local_types[i] = merge(&self.local_types[i], &ty);
+        }
+    }
+}
+
+/// Register every struct and enum first, so declarations can refer to each
+/// other in any order.
+fn check_decls(program: &Program, ctx: &mut Ctx) {
+    for decl in &program.structs {
+        ctx.structs.insert(decl.name.clone(), Vec::new());
+    }
+    for decl in &program.enums {
+        ctx.enums.insert(decl.name.clone(), Vec::new());
+    }
+
+    for decl in &program.structs {
+        let mut fields: Vec<(String, AType)> = Vec::new();
+        for (name, ty_word, span) in &decl.fields {
+            if fields.iter().any(|(f, _)| f == name) {
+                ctx.errors.push(a037_field_listed_twice(*span, &decl.name, name));
+                continue;
+            }
+            let ty = resolve_type(ty_word, *span, ctx);
+            fields.push((name.clone(), ty));
+        }
+        ctx.structs.insert(decl.name.clone(), fields);
+    }
+
+    for decl in &program.enums {
+        let mut variants: Vec<(String, Vec<AType>)> = Vec::new();
+        for (name, payload, span) in &decl.variants {
+            if let Some(other) = ctx.variants.get(name) {
+                ctx.errors.push(a042_variant_declared_twice(*span, name, other));
+                continue;
+            }
+            let payload = payload
+                .iter()
+                .map(|w| resolve_type(w, *span, ctx))
+                .collect();
+            ctx.variants.insert(name.clone(), decl.name.clone());
+            variants.push((name.clone(), payload));
+        }
+        ctx.enums.insert(decl.name.clone(), variants);
+    }
+
+    for f in &program.funcs {
+        if f.name == "main" {
+            ctx.errors.push(a048_bad_main(f.span, program.main.is_some()));
+            continue;
+        }
+        if ctx.is_builtin(&f.name) {
+            ctx.errors.push(a047_builtin_name(f.span, &f.name));
+            continue;
+        }
+        if ctx.funcs.contains_key(&f.name) {
+            ctx.errors.push(a046_declared_twice(f.span, &f.name));
+            continue;
+        }
+        let params = f
+            .params
+            .iter()
+            .map(|(_, ty, span)| resolve_type(ty, *span, ctx))
+            .collect();
+        let ret = f.ret.as_ref().map(|ty| resolve_type(ty, f.span, ctx));
+        ctx.funcs.insert(f.name.clone(), FnSig { params, ret });
+    }
+}
+
+/// A function body sees only its parameters, and must end in a `Return` if
+/// it promises a value.
+fn check_func(f: &FuncDecl, ctx: &mut Ctx) {
+    let Some(sig) = ctx.funcs.get(&f.name).cloned() else {
+        return;
+    };
+    let outer_locals = std::mem::take(&mut ctx.locals);
+    let outer_types = std::mem::take(&mut ctx.local_types);
+    let outer_spans = std::mem::take(&mut ctx.local_spans);
+    let outer_unset = std::mem::take(&mut ctx.maybe_unset);
+    for ((name, _, span), ty) in f.params.iter().zip(&sig.params) {
+        ctx.declare(name, ty.clone(), *span);
+    }
+    ctx.returns = Some(sig.ret.clone());
+
+    check_stmts(&f.body, ctx);
+    if let Some(ret) = &sig.ret {
+        if !always_returns(&f.body) {
+            ctx.errors.push(a028_missing_return(f.span, &f.name, ret));
+        }
+    }
+
+    ctx.returns = None;
+    ctx.locals = outer_locals;
+    ctx.local_types = outer_types;
+    ctx.local_spans = outer_spans;
+    ctx.maybe_unset = outer_unset;
+}
+
+/// Does every path through `stmts` end in a `Return`?
+fn always_returns(stmts: &[Stmt]) -> bool {
+    match stmts.last() {
+        Some(Stmt::Return { .. }) => true,
+        Some(Stmt::If {
+            first,
+            elseifs,
+            else_body: Some(else_body),
+            ..
+        }) => {
+            std::iter::once(first)
+                .chain(elseifs)
+                .all(|br| always_returns(&br.body))
+                && always_returns(else_body)
+        }
+        // Analysis already made sure the arms cover every variant.
+        Some(Stmt::Match { arms, .. }) => arms.iter().all(|arm| always_returns(&arm.body)),
+        _ => false,
+    }
+}
+
+/// The type a declaration names, or A014 (with a suggestion) and Unknown.
+fn resolve_type(word: &str, span: Span, ctx: &mut Ctx) -> AType {
+    if let Some(inner) = word.strip_prefix("List(").and_then(|w| w.strip_suffix(')')) {
+        return AType::List(Box::new(resolve_type(inner, span, ctx)));
+    }
+    if let Some(inner) = word.strip_prefix("Map(").and_then(|w| w.strip_suffix(')')) {
+        if let Some((key, value)) = split_type_args(inner) {
+            let key = resolve_type(key, span, ctx);
+            return AType::Map(Box::new(key), Box::new(resolve_type(value, span, ctx)));
+        }
+    }
+    if let Some(t) = type_from_word(word, ctx) {
+        return t;
+    }
+    let known = TYPE_WORDS
+        .iter()
+        .copied()
+        .chain(ctx.structs.keys().map(String::as_str))
+        .chain(ctx.enums.keys().map(String::as_str));
+    let suggestion = did_you_mean(word, known);
+    ctx.errors.push(a014_unknown_type(span, word, suggestion));
+    AType::Unknown
+}
+
+/// `String, List(Int)` -> (`String`, `List(Int)`): split at the comma that
+/// isn't inside parentheses.
+fn split_type_args(args: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => return Some((args[..i].trim(), args[i + 1..].trim())),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Built-in type names a declaration can use, besides structs and enums.
+const TYPE_WORDS: &[&str] = &["Int", "Float", "Bool", "Char", "String", "List", "Map"];
+
+fn type_from_word(word: &str, ctx: &Ctx) -> Option<AType> {
+    match word {
+        "Int" | "int" | "i32" | "i64" => Some(AType::Int),
+        "Float" | "float" | "f32" | "f64" => Some(AType::Float),
+        "Bool" | "bool" => Some(AType::Bool),
+        "Char" | "char" => Some(AType::Char),
+        "String" | "string" | "Str" | "str" | "&str" => Some(AType::Str),
+        _ if ctx.structs.contains_key(word) => Some(AType::Record(word.to_string())),
+        _ if ctx.enums.contains_key(word) => Some(AType::Enum(word.to_string())),
+        _ => None,
+    }
+}
+
+fn check_stmts(stmts: &[Stmt], ctx: &mut Ctx) {
+    for s in stmts {
+        check_stmt(s, ctx);
+    }
+}
+
+fn check_stmt(s: &Stmt, ctx: &mut Ctx) {
+    match s {
+        Stmt::Let { name, expr, .. } | Stmt::Mute { name, expr, .. } => {
+            // check the expression before the name exists (`Let x = x + 1`)
+            check_expr(expr, ctx);
+
+            // infer the expression type using the current symbol table
+            let ty = ctx.infer(expr);
+            ctx.declare(name, ty, stmt_span(s));
+        }
+
+        Stmt::Assign { name, expr, .. } => {
+            check_expr(expr, ctx);
+            if let Some(expected) = ctx.type_of(name).cloned() {
+                // existing variable: type-check the assignment
+                let found = ctx.infer(expr);
+                if !compatible(&expected, &found) {
+                    ctx.errors
+                        .push(a002_assign_type_mismatch(expr_span(expr), expected, found));
                 } else {
-                    // treat as declaration: infer type and register the variable
-                    let ty = infer_expr_type(expr, &locals, &local_types);
-                    let idx = local_types.len();
-                    locals.insert(name.clone(), idx);
-                    local_types.push(ty);
+                    ctx.refine(name, found);
                 }
+                // From here on it is set, whichever way the program came.
+                ctx.maybe_unset.remove(name);
+            } else {
+                // In A, a bare assignment `x = <expr>` declares `x` if it doesn't exist yet.
+                let ty = ctx.infer(expr);
+                ctx.declare(name, ty, stmt_span(s));
+            }
+        }
 
-                check_expr(expr, &locals, &local_types, &mut errors);
+        Stmt::SetIndex {
+            name, index, expr, ..
+        } => {
+            check_expr(index, ctx);
+            check_expr(expr, ctx);
+            ctx.check_set(name, stmt_span(s));
+            let Some(target) = ctx.type_of(name).cloned() else {
+                let e = ctx.undeclared(expr_span(index), name);
+                ctx.errors.push(e);
+                return;
+            };
+            let ti = ctx.infer(index);
+            let tv = ctx.infer(expr);
+            match &target {
+                AType::List(item) => {
+                    if !matches!(ti, AType::Int | AType::Unknown) {
+                        ctx.errors.push(a010_index_must_be_int(expr_span(index), ti));
+                    } else if !compatible(item, &tv) {
+                        ctx.errors.push(a008_list_items_same_type(
+                            expr_span(expr),
+                            (**item).clone(),
+                            tv,
+                        ));
+                    }
+                }
+                AType::Map(key, value) => {
+                    if !compatible(key, &ti) {
+                        ctx.errors
+                            .push(a012_map_key_type(expr_span(index), (**key).clone(), ti));
+                    } else if !compatible(value, &tv) {
+                        ctx.errors.push(a013_map_entries_same_type(
+                            expr_span(expr),
+                            "values",
+                            (**value).clone(),
+                            tv,
+                        ));
+                    } else {
+                        ctx.refine(name, AType::Map(Box::new(ti), Box::new(tv)));
+                    }
+                }
+                AType::Unknown => {}
+                other => ctx.errors.push(a009_needs_a_list(
+                    expr_span(index),
+                    &i18n::text("a009_needs_a_list", "what_set_item", &[]),
+                    "list_or_map",
+                    other.clone(),
+                )),
             }
+        }
 
-            Stmt::If {
-                first,
-                elseifs,
-                else_body,
-                ..
-            } => {
-                check_branch_with_ctx(first, &locals, &local_types, &mut errors);
-                for br in elseifs {
-                    check_branch_with_ctx(br, &locals, &local_types, &mut errors);
+        Stmt::SetField {
+            name, field, expr, ..
+        } => {
+            check_expr(expr, ctx);
+            let span = stmt_span(s);
+            ctx.check_set(name, span);
+            match ctx.type_of(name).cloned() {
+                None => {
+                    let e = ctx.undeclared(span, name);
+                    ctx.errors.push(e);
                 }
-                if let Some(body) = else_body {
-                    for s in body {
-                        // recursively analyze inner statements (simple approach)
-                        match s {
-                            Stmt::Let { name, expr, .. } | Stmt::Mute { name, expr, .. } => {
-                                let ty = infer_expr_type(expr, &locals, &local_types);
-                                let idx = local_types.len();
-                                locals.insert(name.clone(), idx);
-                                local_types.push(ty);
-                                check_expr(expr, &locals, &local_types, &mut errors);
-                            }
-                            Stmt::Assign { name, expr, .. } => {
-                                if let Some(&idx) = locals.get(name) {
-                                    let expected = &local_types[idx];
-                                    let found = infer_expr_type(expr, &locals, &local_types);
-                                    if *expected != AType::Unknown && found != AType::Unknown && *expected != found {
-                                        errors.push(a002_assign_type_mismatch(expr_span(expr), expected.clone(), found));
-                                    }
-                                } else {
-                                    errors.push(a001_undeclared_variable(expr_span(expr), name.clone()));
-                                }
-                                check_expr(expr, &locals, &local_types, &mut errors);
-                            }
-                            Stmt::Expr(e) => check_expr(e, &locals, &local_types, &mut errors),
-                            _ => {}
+                Some(AType::Record(record)) => match ctx.field_type(&record, field).cloned() {
+                    None => {
+                        let e = ctx.no_such_field(span, &record, field);
+                        ctx.errors.push(e);
+                    }
+                    Some(expected) => {
+                        let found = ctx.infer(expr);
+                        if !compatible(&expected, &found) {
+                            ctx.errors.push(a017_field_type(
+                                expr_span(expr),
+                                &record,
+                                field,
+                                expected,
+                                found,
+                            ));
                         }
                     }
+                },
+                Some(AType::Unknown) => {}
+                Some(other) => ctx.errors.push(a018_not_a_record(span, field, other)),
+            }
+        }
+
+        Stmt::If {
+            first,
+            elseifs,
+            else_body,
+            span,
+        } => {
+            // Each branch starts from what was set before the If.
+            let before = ctx.scope();
+            let mut ends = Vec::new();
+            for br in std::iter::once(first).chain(elseifs) {
+                ctx.restore(&before);
+                check_branch(br, ctx);
+                if !always_returns(&br.body) {
+                    ends.push(ctx.end(br.span, Way::Branch));
+                }
+            }
+            ctx.restore(&before);
+            match else_body {
+                Some(body) => {
+                    check_stmts(body, ctx);
+                    if !always_returns(body) {
+                        let at = block_span(body).unwrap_or(*span);
+                        ends.push(ctx.end(at, Way::Branch));
+                    }
                 }
+                None => ends.push(ctx.end(expr_span(&first.cond), Way::NoElse)),
             }
+            ctx.join(before, ends);
+        }
+
+        Stmt::For {
+            var,
+            iter,
+            body,
+            span,
+        } => {
+            check_expr(iter, ctx);
+            let item = match ctx.infer(iter) {
+                AType::List(t) => *t,
+                AType::Unknown => AType::Unknown,
+                other => {
+                    ctx.errors
+                        .push(a009_needs_a_list(expr_span(iter), &i18n::text("a009_needs_a_list", "what_for", &[]), "list", other));
+                    AType::Unknown
+                }
+            };
+            // Point at `For x in xs`, not the whole loop.
+            let header = Span {
+                start: span.start,
+                end: expr_span(iter).end,
+            };
+            // The body may run no times at all.
+            let before = ctx.scope();
+            let skipped = ctx.end(header, Way::EmptyLoop);
+            ctx.declare(var, item, header);
+            check_stmts(body, ctx);
+            let ran = ctx.end(header, Way::Branch);
+            ctx.join(before, vec![ran, skipped]);
+        }
 
-            Stmt::Expr(e) => {
-                check_expr(e, &locals, &local_types, &mut errors);
+        Stmt::Match { expr, arms, .. } => check_match(expr, arms, ctx),
+
+        Stmt::Return { expr, span } => {
+            if let Some(e) = expr {
+                check_expr(e, ctx);
+            }
+            let found = expr.as_ref().map(|e| ctx.infer(e));
+            match (ctx.returns.clone(), found) {
+                // main: a bare `Return` ends the program early
+                (None, None) => {}
+                (None, Some(_)) => ctx.errors.push(a050_return_value_from_main(*span)),
+                (Some(None), None) => {}
+                (Some(Some(expected)), Some(found)) if compatible(&expected, &found) => {}
+                (Some(expected), found) => {
+                    ctx.errors.push(a049_return_type(*span, expected, found));
+                }
             }
         }
+
+        // A call on its own line may give back nothing (`Print`, `Push`).
+        Stmt::Expr(call @ Expr::Call(..)) => check_call(call, false, ctx),
+        Stmt::Expr(e) => check_expr(e, ctx),
     }
+}
 
-    if errors.is_empty() {
-        Ok(AnalysisResult { locals, local_types })
-    } else {
-        Err(errors)
+/// Every arm names a variant of the scrutinee's enum with the right number of
+/// bindings, no arm can be shadowed, and together they cover every variant.
+fn check_match(expr: &Expr, arms: &[MatchArm], ctx: &mut Ctx) {
+    check_expr(expr, ctx);
+    let enum_name = match ctx.infer(expr) {
+        AType::Enum(name) => Some(name),
+        // Fall back on the first variant the arms mention.
+        AType::Unknown => arms
+            .iter()
+            .filter_map(|arm| arm.variant.as_deref())
+            .find_map(|v| ctx.variants.get(v).cloned()),
+        other => {
+            ctx.errors.push(a020_match_needs_enum(expr_span(expr), other));
+            None
+        }
+    };
+    let variants = enum_name
+        .as_ref()
+        .and_then(|e| ctx.enums.get(e).cloned())
+        .unwrap_or_default();
+
+    let mut seen: Vec<&str> = Vec::new();
+    let mut catch_all = false;
+    // Each arm starts from what was set before the Match.
+    let before = ctx.scope();
+    let mut ends = Vec::new();
+    for arm in arms {
+        ctx.restore(&before);
+        if catch_all {
+            ctx.errors
+                .push(a041_case_never_runs(arm.span, "after_catch_all"));
+        }
+        let Some(variant) = arm.variant.as_deref() else {
+            catch_all = true;
+            check_stmts(&arm.body, ctx);
+            if !always_returns(&arm.body) {
+                ends.push(ctx.end(arm.span, Way::Case));
+            }
+            continue;
+        };
+
+        let mut payload = vec![AType::Unknown; arm.bindings.len()];
+        match (&enum_name, variants.iter().find(|(v, _)| v == variant)) {
+            (Some(_), Some((_, types))) => {
+                if seen.contains(&variant) {
+                    ctx.errors
+                        .push(a041_case_never_runs(arm.span, "handled_above"));
+                }
+                seen.push(variant);
+                if types.len() != arm.bindings.len() {
+                    ctx.errors.push(a040_wrong_bindings(arm.span, variant, types.len()));
+                } else {
+                    payload = types.clone();
+                }
+            }
+            (Some(e), None) => {
+                let e = match ctx.variants.get(variant) {
+                    Some(other) => a039_variant_of_other_enum(arm.span, variant, other, e),
+                    None => {
+                        let known = variants.iter().map(|(v, _)| v.as_str());
+                        a038_unknown_variant(arm.span, variant, e, did_you_mean(variant, known))
+                    }
+                };
+                ctx.errors.push(e);
+            }
+            (None, _) => {}
+        }
+
+        for (name, ty) in arm.bindings.iter().zip(payload) {
+            if name != "_" {
+                ctx.declare(name, ty, arm.span);
+            }
+        }
+        check_stmts(&arm.body, ctx);
+        if !always_returns(&arm.body) {
+            ends.push(ctx.end(arm.span, Way::Case));
+        }
+    }
+    ctx.join(before, ends);
+
+    if let (Some(e), false) = (&enum_name, catch_all) {
+        let missing: Vec<(String, usize)> = variants
+            .iter()
+            .filter(|(v, _)| !seen.contains(&v.as_str()))
+            .map(|(v, types)| (v.clone(), types.len()))
+            .collect();
+        if !missing.is_empty() {
+            ctx.errors
+                .push(a019_match_missing_cases(expr_span(expr), e, &missing));
+        }
     }
 }
 
-fn check_branch_with_ctx(
-    br: &IfBranch,
-    locals: &HashMap<String, usize>,
-    local_types: &Vec<AType>,
-    errors: &mut Vec<AError>,
-) {
-    let ty = infer_expr_type(&br.cond, locals, local_types);
+fn check_branch(br: &IfBranch, ctx: &mut Ctx) {
+    let ty = ctx.infer(&br.cond);
 
     if !matches!(ty, AType::Bool | AType::Unknown) {
         let sp = expr_span(&br.cond);
-        errors.push(a007_if_condition_must_be_bool(sp));
+        ctx.errors.push(a007_if_condition_must_be_bool(sp));
     }
+    check_expr(&br.cond, ctx);
+
+    check_stmts(&br.body, ctx);
+}
 
-    for s in &br.body {
-        match s {
-            Stmt::Let { expr, .. } | Stmt::Mute { expr, .. } => {
-                check_expr(expr, locals, local_types, errors)
+fn check_expr(e: &Expr, ctx: &mut Ctx) {
+    match e {
+        Expr::Add(a, b, span) => {
+            check_expr(a, ctx);
+            check_expr(b, ctx);
+            let ta = ctx.infer(a);
+            let tb = ctx.infer(b);
+            let number = |t: &AType| matches!(t, AType::Int | AType::Float | AType::Unknown);
+            match (&ta, &tb) {
+                // Joining text
+                (AType::Str, AType::Str | AType::Unknown) | (AType::Unknown, AType::Str) => {
+                    ctx.op_types.insert(*span, AType::Str);
+                }
+                _ if number(&ta) && number(&tb) => check_number_kinds(*span, "add", ta, tb, ctx),
+                _ => ctx.errors.push(a003_add_operands(expr_span(e), ta, tb)),
+            }
+        }
+        Expr::Cmp(a, _, b, span) => {
+            check_expr(a, ctx);
+            check_expr(b, ctx);
+            let ta = ctx.infer(a);
+            let tb = ctx.infer(b);
+            match (&ta, &tb) {
+                (AType::Str, AType::Str | AType::Unknown) | (AType::Unknown, AType::Str) => {}
+                (AType::Str, _) | (_, AType::Str) => {
+                    ctx.errors.push(a025_compare_text_with_other(*span, ta, tb));
+                }
+                _ => check_number_kinds(*span, "compare", ta, tb, ctx),
+            }
+        }
+        Expr::Call(..) => check_call(e, true, ctx),
+        Expr::List(items, _) => {
+            for item in items {
+                check_expr(item, ctx);
+            }
+            check_same_type(items.iter(), ctx, a008_list_items_same_type);
+        }
+        Expr::Map(entries, _) => {
+            for (k, v) in entries {
+                check_expr(k, ctx);
+                check_expr(v, ctx);
+            }
+            check_same_type(entries.iter().map(|(k, _)| k), ctx, |sp, want, got| {
+                a013_map_entries_same_type(sp, "keys", want, got)
+            });
+            check_same_type(entries.iter().map(|(_, v)| v), ctx, |sp, want, got| {
+                a013_map_entries_same_type(sp, "values", want, got)
+            });
+        }
+        Expr::Record(name, fields, span) => {
+            for (_, value, _) in fields {
+                check_expr(value, ctx);
+            }
+            check_record(name, fields, *span, ctx);
+        }
+        Expr::Field(target, field, span) => {
+            check_expr(target, ctx);
+            match ctx.infer(target) {
+                AType::Record(record) => {
+                    if ctx.field_type(&record, field).is_none() {
+                        let e = ctx.no_such_field(*span, &record, field);
+                        ctx.errors.push(e);
+                    }
+                }
+                AType::Unknown => {}
+                other => ctx.errors.push(a018_not_a_record(*span, field, other)),
+            }
+        }
+        Expr::Index(target, idx, _) => {
+            check_expr(target, ctx);
+            check_expr(idx, ctx);
+            let ti = ctx.infer(idx);
+            match ctx.infer(target) {
+                AType::List(_) | AType::Str => {
+                    if !matches!(ti, AType::Int | AType::Unknown) {
+                        ctx.errors.push(a010_index_must_be_int(expr_span(idx), ti));
+                    }
+                }
+                AType::Map(key, _) => {
+                    if !compatible(&key, &ti) {
+                        ctx.errors.push(a012_map_key_type(expr_span(idx), *key, ti));
+                    }
+                }
+                AType::Unknown => {}
+                other => ctx.errors.push(a009_needs_a_list(
+                    expr_span(target),
+                    &i18n::text("a009_needs_a_list", "what_index", &[]),
+                    "sized",
+                    other,
+                )),
             }
-            Stmt::Assign { expr, .. } => check_expr(expr, locals, local_types, errors),
-            Stmt::Expr(e) => check_expr(e, locals, local_types, errors),
-            _ => {}
         }
+        Expr::Var(name, span) if ctx.is_variant_name(name) => {
+            check_variant_values(name, &[], *span, ctx);
+        }
+        Expr::Var(name, span) if ctx.type_of(name).is_none() => {
+            let e = ctx.undeclared(*span, name);
+            ctx.errors.push(e);
+        }
+        Expr::Var(name, span) => ctx.check_set(name, *span),
+        _ => {}
     }
 }
 
-fn check_expr(
-    e: &Expr,
-    locals: &HashMap<String, usize>,
-    local_types: &Vec<AType>,
-    errors: &mut Vec<AError>,
-) {
-    match e {
-        Expr::Add(a, b, _) => {
-            check_expr(a, locals, local_types, errors);
-            check_expr(b, locals, local_types, errors);
-            let ta = infer_expr_type(a, locals, local_types);
-            let tb = infer_expr_type(b, locals, local_types);
-            if !(matches!(ta, AType::Int | AType::Unknown)
-                && matches!(tb, AType::Int | AType::Unknown))
-            {
-                errors.push(a003_add_operands_must_be_int(expr_span(e), ta, tb));
+/// `math.double(x)`: `math` is imported and has a function `double`.
+fn check_qualified_call(module: &str, f: &str, span: Span, ctx: &mut Ctx) {
+    let Some(interface) = ctx.modules.get(module) else {
+        let known = ctx.modules.keys().map(String::as_str);
+        let suggestion = did_you_mean(module, known);
+        ctx.errors.push(a043_unknown_module(span, module, suggestion));
+        return;
+    };
+    if !interface.funcs.contains_key(f) {
+        let suggestion = did_you_mean(f, interface.funcs.keys().map(String::as_str));
+        ctx.errors.push(a044_no_such_function(span, module, f, suggestion));
+    }
+}
+
+/// One value of the declared type per parameter.
+fn check_call_args(name: &str, args: &[Expr], span: Span, ctx: &mut Ctx) {
+    let Some(sig) = ctx.func(name).cloned() else {
+        return;
+    };
+    if sig.params.len() != args.len() {
+        ctx.errors.push(a027_call_values(span, name, &sig, None));
+        return;
+    }
+    for (arg, expected) in args.iter().zip(&sig.params) {
+        let found = ctx.infer(arg);
+        if !compatible(expected, &found) {
+            ctx.errors
+                .push(a027_call_values(expr_span(arg), name, &sig, Some(found)));
+        }
+    }
+}
+
+/// Int and Float never mix without a conversion. Operations on Floats are
+/// remembered for the compiler.
+fn check_number_kinds(span: Span, what: &str, ta: AType, tb: AType, ctx: &mut Ctx) {
+    match (&ta, &tb) {
+        (AType::Int, AType::Float) | (AType::Float, AType::Int) => {
+            ctx.errors.push(a022_int_float_mix(span, what, ta, tb));
+        }
+        (AType::Float, _) | (_, AType::Float) => {
+            ctx.op_types.insert(span, AType::Float);
+        }
+        _ => {}
+    }
+}
+
+/// `Circle(5)`: one value of the declared type per payload slot.
+fn check_variant_values(name: &str, args: &[Expr], span: Span, ctx: &mut Ctx) {
+    let Some((_, payload)) = ctx.variant(name) else {
+        return;
+    };
+    let payload = payload.to_vec();
+    if payload.len() != args.len() {
+        ctx.errors.push(a021_variant_values(span, name, &payload, None));
+        return;
+    }
+    for (arg, expected) in args.iter().zip(&payload) {
+        let found = ctx.infer(arg);
+        if !compatible(expected, &found) {
+            ctx.errors
+                .push(a021_variant_values(expr_span(arg), name, &payload, Some(found)));
+        }
+    }
+}
+
+/// `Point { x: 1, y: 2 }`: known struct, known fields, each given once, right types.
+fn check_record(name: &str, fields: &[(String, Expr, Span)], span: Span, ctx: &mut Ctx) {
+    let Some(decl) = ctx.structs.get(name).cloned() else {
+        let known = ctx.structs.keys().map(String::as_str);
+        ctx.errors
+            .push(a014_unknown_type(span, name, did_you_mean(name, known)));
+        return;
+    };
+
+    let mut given: Vec<&str> = Vec::new();
+    for (field, value, fspan) in fields {
+        if given.contains(&field.as_str()) {
+            ctx.errors.push(a037_field_listed_twice(*fspan, name, field));
+            continue;
+        }
+        given.push(field);
+        match decl.iter().find(|(f, _)| f == field) {
+            None => {
+                let e = ctx.no_such_field(*fspan, name, field);
+                ctx.errors.push(e);
+            }
+            Some((_, expected)) => {
+                let found = ctx.infer(value);
+                if !compatible(expected, &found) {
+                    ctx.errors.push(a017_field_type(
+                        expr_span(value),
+                        name,
+                        field,
+                        expected.clone(),
+                        found,
+                    ));
+                }
             }
         }
-        Expr::Cmp(a, _, b, _) => {
-            check_expr(a, locals, local_types, errors);
-            check_expr(b, locals, local_types, errors);
+    }
+
+    let missing: Vec<&str> = decl
+        .iter()
+        .map(|(f, _)| f.as_str())
+        .filter(|f| !given.contains(f))
+        .collect();
+    if !missing.is_empty() {
+        ctx.errors.push(a016_missing_fields(span, name, &missing));
+    }
+}
+
+/// The closest candidate to a misspelled `name`, if any is close enough to be a typo.
+fn did_you_mean<'c>(name: &str, candidates: impl Iterator<Item = &'c str>) -> Option<String> {
+    // Allow roughly one typo per three letters (none for one-letter names).
+    let max = (name.chars().count() + 1) / 3;
+    candidates
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(&name.to_lowercase(), &c.to_lowercase()), c))
+        .filter(|(d, _)| *d <= max)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c.to_string())
+}
+
+/// Edit distance counting insertions, deletions, substitutions and swaps of
+/// two neighbouring letters (`Itn` -> `Int` is one edit).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j - 1] + cost)
+                .min(d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
         }
-        Expr::Call(_, args, _) => {
-            for a in args {
-                check_expr(a, locals, local_types, errors);
+    }
+    d[a.len()][b.len()]
+}
+
+/// Report every item whose type differs from the first item with a known type.
+fn check_same_type<'e>(
+    items: impl Iterator<Item = &'e Expr>,
+    ctx: &mut Ctx,
+    lesson: impl Fn(Span, AType, AType) -> AError,
+) {
+    let mut first: Option<AType> = None;
+    for item in items {
+        let ty = ctx.infer(item);
+        match &first {
+            Some(expected) if !compatible(expected, &ty) => {
+                ctx.errors.push(lesson(expr_span(item), expected.clone(), ty));
             }
+            None if ty != AType::Unknown => first = Some(ty),
+            _ => {}
         }
-        Expr::Var(_, _) => {}
-        _ => {}
     }
 }
 
-fn infer_expr_type(e: &Expr, locals: &HashMap<String, usize>, local_types: &Vec<AType>) -> AType {
+/// Check a call of a VM-run builtin against its entry in the registry.
+/// `as_value`: the call's result is used (assigned, passed on, ...), so its
+/// function must give one back.
+fn check_call(call: &Expr, as_value: bool, ctx: &mut Ctx) {
+    let Expr::Call(name, args, span) = call else {
+        return check_expr(call, ctx);
+    };
+    let span = *span;
+    for a in args {
+        check_expr(a, ctx);
+    }
+    if ctx.is_variant_name(name) {
+        check_variant_values(name, args, span, ctx);
+    } else if let Some((module, f)) = name.split_once('.') {
+        check_qualified_call(module, f, span, ctx);
+        check_call_args(name, args, span, ctx);
+    } else if let Some(b) = builtins::lookup(name) {
+        check_builtin_call(b, args, span, ctx);
+    } else if ctx.func(name).is_some() {
+        check_call_args(name, args, span, ctx);
+    } else {
+        let e = ctx.unknown_function(span, name);
+        ctx.errors.push(e);
+        return;
+    }
+    if as_value && ctx.infer(call) == AType::Nothing {
+        let name = builtins::lookup(name).map_or(name.as_str(), |b| b.name);
+        ctx.errors.push(a033_no_value(span, name));
+    }
+}
+
+fn check_builtin_call(b: &Builtin, args: &[Expr], span: Span, ctx: &mut Ctx) {
+    if !b.accepts(args.len()) {
+        ctx.errors.push(a045_builtin_values(span, b));
+        return;
+    }
+    let what = format!("`{}`", b.name);
+    let first = args.first().map(|a| ctx.infer(a));
+
+    for (i, arg) in args.iter().enumerate() {
+        let t = ctx.infer(arg);
+        let sp = expr_span(arg);
+        match b.param(i) {
+            Param::Any => {}
+            Param::Int => {
+                if !matches!(t, AType::Int | AType::Unknown) {
+                    ctx.errors.push(a010_index_must_be_int(sp, t));
+                }
+            }
+            Param::Str => {
+                if !matches!(t, AType::Str | AType::Unknown) {
+                    ctx.errors.push(a009_needs_a_list(sp, &what, "string", t));
+                }
+            }
+            Param::Template => match arg {
+                Expr::Str(template, _) => {
+                    let holes = template.matches("{}").count();
+                    if holes != args.len() - i - 1 {
+                        ctx.errors
+                            .push(a029_format_values(span, holes, args.len() - i - 1));
+                    }
+                }
+                _ if !matches!(t, AType::Str | AType::Unknown) => {
+                    ctx.errors.push(a009_needs_a_list(sp, &what, "string", t));
+                }
+                _ => {}
+            },
+            Param::Number => {
+                if !matches!(t, AType::Int | AType::Float | AType::Unknown) {
+                    ctx.errors.push(a024_needs_a_number(sp, b.name, t));
+                }
+            }
+            Param::Sized => {
+                if !matches!(t, AType::List(_) | AType::Map(..) | AType::Str | AType::Unknown) {
+                    ctx.errors
+                        .push(a009_needs_a_list(sp, &what, "sized", t));
+                }
+            }
+            Param::ListVar => {
+                if !matches!(t, AType::List(_) | AType::Unknown) {
+                    ctx.errors.push(a009_needs_a_list(sp, &what, "list", t));
+                    return;
+                }
+                if !matches!(arg, Expr::Var(..)) {
+                    ctx.errors.push(a011_push_needs_variable(sp));
+                    return;
+                }
+            }
+            Param::Map => {
+                if !matches!(t, AType::Map(..) | AType::Unknown) {
+                    ctx.errors.push(a009_needs_a_list(sp, &what, "map", t));
+                    return;
+                }
+            }
+            Param::Item => {
+                if let Some(AType::List(item)) = &first {
+                    if !compatible(item, &t) {
+                        ctx.errors
+                            .push(a008_list_items_same_type(sp, (**item).clone(), t));
+                    } else if let Some(Expr::Var(var, _)) = args.first() {
+                        // `[]` learns its item type from the first Push.
+                        ctx.refine(var, AType::List(Box::new(t)));
+                    }
+                }
+            }
+            Param::Key => {
+                if let Some(AType::Map(key, _)) = &first {
+                    if !compatible(key, &t) {
+                        ctx.errors.push(a012_map_key_type(sp, (**key).clone(), t));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The type a builtin call gives back (Unknown for nothing).
+fn builtin_ret_type(b: &Builtin, first: Option<AType>) -> AType {
+    match b.ret {
+        Ret::Nothing => AType::Nothing,
+        Ret::Int => AType::Int,
+        Ret::Float => AType::Float,
+        Ret::Bool => AType::Bool,
+        Ret::Str => AType::Str,
+        Ret::StrList => AType::List(Box::new(AType::Str)),
+        Ret::KeyList => match first {
+            Some(AType::Map(key, _)) => AType::List(key),
+            _ => AType::List(Box::new(AType::Unknown)),
+        },
+    }
+}
+
+/// Do two types agree, treating Unknown (anywhere inside) as "could be anything"?
+fn compatible(a: &AType, b: &AType) -> bool {
+    match (a, b) {
+        (AType::Unknown, _) | (_, AType::Unknown) => true,
+        // Already reported as A033.
+        (AType::Nothing, _) | (_, AType::Nothing) => true,
+        (AType::List(x), AType::List(y)) => compatible(x, y),
+        (AType::Map(k1, v1), AType::Map(k2, v2)) => compatible(k1, k2) && compatible(v1, v2),
+        _ => a == b,
+    }
+}
+
+/// The more precise of two compatible types.
+fn merge(a: &AType, b: &AType) -> AType {
+    match (a, b) {
+        (AType::Unknown | AType::Nothing, t) | (t, AType::Unknown | AType::Nothing) => t.clone(),
+        (AType::List(x), AType::List(y)) => AType::List(Box::new(merge(x, y))),
+        (AType::Map(k1, v1), AType::Map(k2, v2)) => {
+            AType::Map(Box::new(merge(k1, k2)), Box::new(merge(v1, v2)))
+        }
+        _ => a.clone(),
+    }
+}
+
+fn infer_expr_type(e: &Expr, ctx: &Ctx) -> AType {
     match e {
         Expr::Int(_, _) => AType::Int,
+        Expr::Float(_, _) => AType::Float,
         Expr::Bool(_, _) => AType::Bool,
         Expr::Char(_, _) => AType::Char,
         Expr::Str(_, _) => AType::Str,
         Expr::Cmp(_, _, _, _) => AType::Bool,
         Expr::Add(a, b, _) => {
-            let ta = infer_expr_type(a, locals, local_types);
-            let tb = infer_expr_type(b, locals, local_types);
+            let ta = infer_expr_type(a, ctx);
+            let tb = infer_expr_type(b, ctx);
             match (ta, tb) {
                 (AType::Int, AType::Int) => AType::Int,
+                (AType::Float, AType::Float) => AType::Float,
+                (AType::Str, AType::Str) => AType::Str,
                 (AType::Unknown, AType::Int) | (AType::Int, AType::Unknown) => AType::Unknown,
                 _ => AType::Unknown,
             }
         }
-        Expr::Var(name, _) => {
-            if let Some(&idx) = locals.get(name) {
-                local_types.get(idx).cloned().unwrap_or(AType::Unknown)
-            } else {
-                AType::Unknown
-            }
+        Expr::Var(name, _) | Expr::Call(name, _, _) if ctx.is_variant_name(name) => ctx
+            .variant(name)
+            .map(|(e, _)| AType::Enum(e.to_string()))
+            .unwrap_or(AType::Unknown),
+        Expr::Var(name, _) => ctx.type_of(name).cloned().unwrap_or(AType::Unknown),
+        Expr::Call(name, args, _) if builtins::lookup(name).is_some() => {
+            let first = args.first().map(|a| infer_expr_type(a, ctx));
+            builtins::lookup(name)
+                .map(|b| builtin_ret_type(b, first))
+                .unwrap_or(AType::Unknown)
         }
+        Expr::Call(name, _, _) if ctx.func(name).is_some() => ctx
+            .func(name)
+            .map(|sig| sig.ret.clone().unwrap_or(AType::Nothing))
+            .unwrap_or(AType::Unknown),
         Expr::Call(_, args, _) => {
             if args.is_empty() {
                 AType::Unknown
             } else {
                 let _ = args
                     .iter()
-                    .map(|a| infer_expr_type(a, locals, local_types))
+                    .map(|a| infer_expr_type(a, ctx))
                     .collect::<Vec<_>>();
                 AType::Unknown
             }
         }
+        Expr::List(items, _) => {
+            let item = first_known(items.iter(), ctx);
+            AType::List(Box::new(item))
+        }
+        Expr::Map(entries, _) => {
+            let key = first_known(entries.iter().map(|(k, _)| k), ctx);
+            let value = first_known(entries.iter().map(|(_, v)| v), ctx);
+            AType::Map(Box::new(key), Box::new(value))
+        }
+        Expr::Record(name, _, _) => AType::Record(name.clone()),
+        Expr::Field(target, field, _) => match infer_expr_type(target, ctx) {
+            AType::Record(record) => ctx
+                .field_type(&record, field)
+                .cloned()
+                .unwrap_or(AType::Unknown),
+            _ => AType::Unknown,
+        },
+        Expr::Index(target, _, _) => match infer_expr_type(target, ctx) {
+            AType::List(item) => *item,
+            AType::Map(_, value) => *value,
+            AType::Str => AType::Char,
+            _ => AType::Unknown,
+        },
     }
 }
 
-fn a007_if_condition_must_be_bool(span: Span) -> AError {
-    AError {
-        span,
-        code: "A007".to_string(),
-        title: "If condition must be true/false (bool)".to_string(),
-        mental_model: "`If` asks a yes/no question. The condition must already be yes/no."
-            .to_string(),
-        help: vec![
-            "Option 1: Compare to produce a boolean (example: `age > 18`).".to_string(),
-            "Option 2: Use `==` / `!=` to test equality.".to_string(),
-        ],
-        example: r#"Func main() {
-    age: i32 = 20
-    If age > 18 then {
-        Print("Adult")
-    } Else {
-        Print("Not adult")
-    }
-}"#
-        .to_string(),
-        backend: None,
+/// The type of the first expression whose type is known; it decides the
+/// element type of a list or map literal.
+fn first_known<'e>(exprs: impl Iterator<Item = &'e Expr>, ctx: &Ctx) -> AType {
+    exprs
+        .map(|e| infer_expr_type(e, ctx))
+        .find(|t| *t != AType::Unknown)
+        .unwrap_or(AType::Unknown)
+}
+
+/* =========================
+   Lessons (their text is in the lang catalogs)
+   ========================= */
+
+/// One of the examples several lessons share (`enum`, `func`, `import`).
+fn shared_example(name: &str) -> String {
+    i18n::text("examples", name, &[])
+}
+
+/// "Did you mean ...?" as the first help line, when there is a suggestion.
+fn suggest(mut e: AError, key: &str, suggestion: Option<&str>, args: &[(&str, &dyn fmt::Display)]) -> AError {
+    if let Some(s) = suggestion {
+        let mut args = args.to_vec();
+        args.push(("similar", &s));
+        e.help.insert(0, i18n::text(key, "did_you_mean", &args));
     }
+    e
 }
 
-fn a001_undeclared_variable(span: Span, name: String) -> AError {
-    AError {
-        span,
-        code: "A001".to_string(),
-        title: format!("Use of undeclared variable '{}'", name),
-        mental_model: "You used a variable that hasn't been declared yet.".to_string(),
-        help: vec![format!("Declare it first: `{} = <expr>`", name)],
-        example: format!("Func main() {{\n    {} = 1\n}}", name),
-        backend: None,
+fn a007_if_condition_must_be_bool(span: Span) -> AError {
+    AError::lesson(span, "a007_if_condition_must_be_bool", &[])
+}
+
+/// `suggestion`: a declared variable with a similar name, and where it is declared.
+fn a001_undeclared_variable(span: Span, name: &str, suggestion: Option<(String, Span)>) -> AError {
+    const KEY: &str = "a001_undeclared_variable";
+    let mut e = AError::lesson(span, KEY, &[("name", &name)]);
+    if let Some((similar, decl)) = suggestion {
+        let args: [(&str, &dyn fmt::Display); 2] = [("similar", &similar), ("name", &name)];
+        // Most likely a typo, so the suggestion comes first and declaring a
+        // new variable is only the other way out.
+        e.help = vec![
+            i18n::text(KEY, "did_you_mean", &args),
+            i18n::text(KEY, "declare_if_new", &args),
+        ];
+        e.labels.push((decl, i18n::text(KEY, "declared_here", &args)));
     }
+    e
 }
 
 fn a002_assign_type_mismatch(span: Span, expected: AType, found: AType) -> AError {
-    AError {
+    AError::lesson(
+        span,
+        "a002_assign_type_mismatch",
+        &[("expected", &expected), ("found", &found)],
+    )
+}
+
+fn a003_add_operands(span: Span, left: AType, right: AType) -> AError {
+    AError::lesson(span, "a003_add_operands", &[("left", &left), ("right", &right)])
+}
+
+fn a008_list_items_same_type(span: Span, expected: AType, found: AType) -> AError {
+    AError::lesson(
         span,
-        code: "A002".to_string(),
-        title: "Type mismatch in assignment".to_string(),
-        mental_model: format!(
-            "The value assigned has type {:?} but the variable expects {:?}.",
-            found, expected
-        ),
-        help: vec!["Ensure the assigned value matches the variable's type.".to_string()],
-        example: "Example: `x = 1 + 2` (assigning int to int)".to_string(),
-        backend: None,
+        "a008_list_items_same_type",
+        &[("expected", &expected), ("found", &found)],
+    )
+}
+
+/// `what`: what needs the value, e.g. "`Len`" or `i18n::text(.., "what_for")`.
+/// `kind`: what it accepts: "list", "map", "string", "list_or_map" or "sized".
+fn a009_needs_a_list(span: Span, what: &str, kind: &str, found: AType) -> AError {
+    const KEY: &str = "a009_needs_a_list";
+    let kind_text = i18n::text(KEY, &format!("kind_{}", kind), &[]);
+    let mut e = AError::lesson(span, KEY, &[("what", &what), ("kind", &kind_text), ("found", &found)]);
+    let sized = kind == "sized";
+    e.help = ["list", "map", "string"]
+        .iter()
+        .filter(|k| sized || kind.contains(*k))
+        .map(|k| i18n::text(KEY, &format!("help_{}", k), &[]))
+        .collect();
+    if kind == "string" {
+        e.example = i18n::text(KEY, "example_string", &[]);
     }
+    e
+}
+
+fn a010_index_must_be_int(span: Span, found: AType) -> AError {
+    AError::lesson(span, "a010_index_must_be_int", &[("found", &found)])
+}
+
+fn a011_push_needs_variable(span: Span) -> AError {
+    AError::lesson(span, "a011_push_needs_variable", &[])
+}
+
+fn a012_map_key_type(span: Span, expected: AType, found: AType) -> AError {
+    AError::lesson(span, "a012_map_key_type", &[("expected", &expected), ("found", &found)])
+}
+
+/// `part` is "keys" or "values".
+fn a013_map_entries_same_type(span: Span, part: &str, expected: AType, found: AType) -> AError {
+    const KEY: &str = "a013_map_entries_same_type";
+    let part = i18n::text(KEY, &format!("part_{}", part), &[]);
+    AError::lesson(span, KEY, &[("part", &part), ("expected", &expected), ("found", &found)])
 }
 
-fn a003_add_operands_must_be_int(span: Span, left: AType, right: AType) -> AError {
-    AError {
+fn a014_unknown_type(span: Span, name: &str, suggestion: Option<String>) -> AError {
+    const KEY: &str = "a014_unknown_type";
+    let args: [(&str, &dyn fmt::Display); 1] = [("name", &name)];
+    suggest(AError::lesson(span, KEY, &args), KEY, suggestion.as_deref(), &args)
+}
+
+fn a015_no_such_field(span: Span, record: &str, field: &str, suggestion: Option<String>) -> AError {
+    const KEY: &str = "a015_no_such_field";
+    let args: [(&str, &dyn fmt::Display); 2] = [("record", &record), ("field", &field)];
+    suggest(AError::lesson(span, KEY, &args), KEY, suggestion.as_deref(), &args)
+}
+
+fn a016_missing_fields(span: Span, record: &str, missing: &[&str]) -> AError {
+    let fields = missing
+        .iter()
+        .map(|f| format!("`{}: ...`", f))
+        .collect::<Vec<_>>()
+        .join(", ");
+    AError::lesson(
         span,
-        code: "A003".to_string(),
-        title: "Add operands must be integers".to_string(),
-        mental_model: format!(
-            "`+` expects integer operands but found {:?} and {:?}.",
-            left, right
-        ),
-        help: vec![
-            "Ensure both sides are integers (e.g., `1 + 2`), or convert values explicitly."
-                .to_string(),
+        "a016_missing_fields",
+        &[("record", &record), ("missing", &missing.join(", ")), ("fields", &fields)],
+    )
+}
+
+fn a037_field_listed_twice(span: Span, record: &str, field: &str) -> AError {
+    AError::lesson(span, "a037_field_listed_twice", &[("record", &record), ("field", &field)])
+}
+
+fn a017_field_type(span: Span, record: &str, field: &str, expected: AType, found: AType) -> AError {
+    AError::lesson(
+        span,
+        "a017_field_type",
+        &[
+            ("record", &record),
+            ("field", &field),
+            ("expected", &expected),
+            ("found", &found),
         ],
-        example: "Example: `x = 1 + 2`".to_string(),
-        backend: None,
+    )
+}
+
+fn a018_not_a_record(span: Span, field: &str, found: AType) -> AError {
+    AError::lesson(span, "a018_not_a_record", &[("field", &field), ("found", &found)])
+}
+
+/// `missing`: each unhandled variant with how many values it holds.
+fn a019_match_missing_cases(span: Span, enum_name: &str, missing: &[(String, usize)]) -> AError {
+    let pattern = |(v, n): &(String, usize)| {
+        if *n == 0 {
+            v.clone()
+        } else {
+            format!("{}({})", v, vec!["_"; *n].join(", "))
+        }
+    };
+    let arms: Vec<String> = missing.iter().map(pattern).collect();
+    let names = missing
+        .iter()
+        .map(|(v, _)| v.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let listed = arms
+        .iter()
+        .map(|a| format!("`{} => ...`", a))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut e = AError::lesson(
+        span,
+        "a019_match_missing_cases",
+        &[("enum", &enum_name), ("missing", &names), ("arms", &listed)],
+    );
+    e.example = format!(
+        "Match value {{\n{}}}",
+        arms.iter()
+            .map(|a| format!("    {} => {{ ... }}\n", a))
+            .collect::<String>()
+    );
+    e
+}
+
+fn a020_match_needs_enum(span: Span, found: AType) -> AError {
+    let mut e = AError::lesson(span, "a020_match_needs_enum", &[("found", &found)]);
+    e.example = shared_example("enum");
+    e
+}
+
+fn a038_unknown_variant(span: Span, variant: &str, enum_name: &str, suggestion: Option<String>) -> AError {
+    const KEY: &str = "a038_unknown_variant";
+    let args: [(&str, &dyn fmt::Display); 2] = [("variant", &variant), ("enum", &enum_name)];
+    let mut e = suggest(AError::lesson(span, KEY, &args), KEY, suggestion.as_deref(), &args);
+    e.example = shared_example("enum");
+    e
+}
+
+fn a039_variant_of_other_enum(span: Span, variant: &str, owner: &str, enum_name: &str) -> AError {
+    let mut e = AError::lesson(
+        span,
+        "a039_variant_of_other_enum",
+        &[("variant", &variant), ("owner", &owner), ("enum", &enum_name)],
+    );
+    e.example = shared_example("enum");
+    e
+}
+
+fn a040_wrong_bindings(span: Span, variant: &str, expected: usize) -> AError {
+    let pattern = if expected == 0 {
+        variant.to_string()
+    } else {
+        format!("{}({})", variant, vec!["_"; expected].join(", "))
+    };
+    let mut e = AError::lesson(
+        span,
+        "a040_wrong_bindings",
+        &[("variant", &variant), ("count", &expected), ("pattern", &pattern)],
+    );
+    e.example = shared_example("enum");
+    e
+}
+
+/// `reason`: what makes the arm unreachable, "after_catch_all" or "handled_above".
+fn a041_case_never_runs(span: Span, reason: &str) -> AError {
+    const KEY: &str = "a041_case_never_runs";
+    let reason = i18n::text(KEY, &format!("reason_{}", reason), &[]);
+    let mut e = AError::lesson(span, KEY, &[("reason", &reason)]);
+    e.example = shared_example("enum");
+    e
+}
+
+/// `found`: the type of the wrong value, or `None` when the count is wrong.
+fn a021_variant_values(span: Span, variant: &str, payload: &[AType], found: Option<AType>) -> AError {
+    const KEY: &str = "a021_variant_values";
+    let types = payload.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+    let shape = if types.is_empty() {
+        variant.to_string()
+    } else {
+        format!("{}({})", variant, types.join(", "))
+    };
+    let count = payload.len();
+    let mut e = AError::lesson(span, KEY, &[("variant", &variant), ("count", &count), ("shape", &shape)]);
+    if let Some(t) = found {
+        let args: [(&str, &dyn fmt::Display); 3] = [("variant", &variant), ("shape", &shape), ("found", &t)];
+        e.title = i18n::text(KEY, "title_type", &args);
+        e.mental_model = i18n::text(KEY, "why_type", &args);
+    }
+    e.example = shared_example("enum");
+    e
+}
+
+fn a042_variant_declared_twice(span: Span, variant: &str, owner: &str) -> AError {
+    let mut e = AError::lesson(
+        span,
+        "a042_variant_declared_twice",
+        &[("variant", &variant), ("owner", &owner)],
+    );
+    e.example = shared_example("enum");
+    e
+}
+
+/// `what`: "add" (`+`) or "compare".
+fn a022_int_float_mix(span: Span, what: &str, left: AType, right: AType) -> AError {
+    const KEY: &str = "a022_int_float_mix";
+    let what = i18n::text(KEY, &format!("what_{}", what), &[]);
+    AError::lesson(span, KEY, &[("what", &what), ("left", &left), ("right", &right)])
+}
+
+/// A number literal too big for its type (reported by the parser).
+pub fn number_out_of_range(span: Span, text: &str) -> AError {
+    AError::lesson(span, "a023_number_out_of_range", &[("text", &text)])
+}
+
+fn a024_needs_a_number(span: Span, what: &str, found: AType) -> AError {
+    AError::lesson(span, "a024_needs_a_number", &[("what", &what), ("found", &found)])
+}
+
+fn a025_compare_text_with_other(span: Span, left: AType, right: AType) -> AError {
+    AError::lesson(span, "a025_compare_text_with_other", &[("left", &left), ("right", &right)])
+}
+
+/// `reason`: what went wrong looking for it (see `a026_looked_for`).
+pub fn a026_import_not_found(span: Span, path: &str, reason: &str) -> AError {
+    let mut e = AError::lesson(span, "a026_import_not_found", &[("path", &path)]);
+    e.labels.push((span, reason.to_string()));
+    e.example = shared_example("import");
+    e
+}
+
+/// Why an import couldn't be read: where it was looked for and the error.
+pub fn a026_looked_for(path: &dyn fmt::Display, error: &dyn fmt::Display) -> String {
+    i18n::text("a026_import_not_found", "looked_for", &[("path", path), ("error", error)])
+}
+
+fn a043_unknown_module(span: Span, module: &str, suggestion: Option<String>) -> AError {
+    const KEY: &str = "a043_unknown_module";
+    let args: [(&str, &dyn fmt::Display); 1] = [("module", &module)];
+    let mut e = suggest(AError::lesson(span, KEY, &args), KEY, suggestion.as_deref(), &args);
+    e.example = shared_example("import");
+    e
+}
+
+fn a044_no_such_function(span: Span, module: &str, f: &str, suggestion: Option<String>) -> AError {
+    const KEY: &str = "a044_no_such_function";
+    let args: [(&str, &dyn fmt::Display); 2] = [("module", &module), ("name", &f)];
+    let mut e = suggest(AError::lesson(span, KEY, &args), KEY, suggestion.as_deref(), &args);
+    e.example = shared_example("import");
+    e
+}
+
+fn signature(name: &str, sig: &FnSig) -> String {
+    let params = sig.params.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+    match &sig.ret {
+        Some(ret) => format!("{}({}) -> {}", name, params.join(", "), ret),
+        None => format!("{}({})", name, params.join(", ")),
+    }
+}
+
+/// `found`: the type of the wrong value, or `None` when the count is wrong.
+fn a027_call_values(span: Span, name: &str, sig: &FnSig, found: Option<AType>) -> AError {
+    const KEY: &str = "a027_call_values";
+    let shape = signature(name, sig);
+    let count = sig.params.len();
+    let mut e = AError::lesson(span, KEY, &[("name", &name), ("count", &count), ("shape", &shape)]);
+    if let Some(t) = found {
+        let args: [(&str, &dyn fmt::Display); 3] = [("name", &name), ("shape", &shape), ("found", &t)];
+        e.title = i18n::text(KEY, "title_type", &args);
+        e.mental_model = i18n::text(KEY, "why_type", &args);
+    }
+    e.example = shared_example("func");
+    e
+}
+
+fn a045_builtin_values(span: Span, b: &Builtin) -> AError {
+    const KEY: &str = "a045_builtin_values";
+    let count = match b.arity() {
+        Some(n) => n.to_string(),
+        None => i18n::text(KEY, "at_least", &[("n", &b.min_arity())]),
+    };
+    let mut e = AError::lesson(span, KEY, &[("name", &b.name), ("count", &count), ("usage", &b.usage)]);
+    e.example = shared_example("func");
+    e
+}
+
+fn a046_declared_twice(span: Span, name: &str) -> AError {
+    let mut e = AError::lesson(span, "a046_declared_twice", &[("name", &name)]);
+    e.example = shared_example("func");
+    e
+}
+
+fn a047_builtin_name(span: Span, name: &str) -> AError {
+    let lower = name.to_ascii_lowercase();
+    let mut e = AError::lesson(span, "a047_builtin_name", &[("name", &name), ("lower", &lower)]);
+    e.example = shared_example("func");
+    e
+}
+
+/// `main` written with parameters or a result, or a second `main`.
+fn a048_bad_main(span: Span, twice: bool) -> AError {
+    const KEY: &str = "a048_bad_main";
+    let mut e = AError::lesson(span, KEY, &[]);
+    if twice {
+        e.title = i18n::text(KEY, "title_twice", &[]);
+    }
+    e.example = shared_example("func");
+    e
+}
+
+fn a028_missing_return(span: Span, name: &str, ret: &AType) -> AError {
+    let mut e = AError::lesson(span, "a028_missing_return", &[("name", &name), ("ret", ret)]);
+    e.example = shared_example("func");
+    e
+}
+
+/// `expected`: what the function declares (`None`: nothing).
+fn a049_return_type(span: Span, expected: Option<AType>, found: Option<AType>) -> AError {
+    const KEY: &str = "a049_return_type";
+    let (suffix, expected, found) = match (expected, found) {
+        (None, _) => ("_nothing", String::new(), String::new()),
+        (Some(e), None) => ("_missing", e.to_string(), String::new()),
+        (Some(e), Some(f)) => ("", e.to_string(), f.to_string()),
+    };
+    let args: [(&str, &dyn fmt::Display); 2] = [("expected", &expected), ("found", &found)];
+    let mut e = AError::lesson(span, KEY, &args);
+    e.title = i18n::text(KEY, &format!("title{}", suffix), &args);
+    e.mental_model = i18n::text(KEY, &format!("why{}", suffix), &args);
+    e.example = shared_example("func");
+    e
+}
+
+fn a029_format_values(span: Span, holes: usize, values: usize) -> AError {
+    AError::lesson(span, "a029_format_values", &[("holes", &holes), ("values", &values)])
+}
+
+fn a030_unknown_function(span: Span, name: &str, suggestion: Option<String>) -> AError {
+    const KEY: &str = "a030_unknown_function";
+    let args: [(&str, &dyn fmt::Display); 1] = [("name", &name)];
+    let mut e = suggest(AError::lesson(span, KEY, &args), KEY, suggestion.as_deref(), &args);
+    e.example = shared_example("func");
+    e
+}
+
+fn a050_return_value_from_main(span: Span) -> AError {
+    let mut e = AError::lesson(span, "a050_return_value_from_main", &[]);
+    e.example = shared_example("func");
+    e
+}
+
+/// What to say about a way past an If, For or Match that doesn't set `name`.
+fn way_label(way: Way, name: &str) -> String {
+    let key = match way {
+        Way::Branch => "way_branch",
+        Way::Case => "way_case",
+        Way::NoElse => "way_no_else",
+        Way::EmptyLoop => "way_empty_loop",
+    };
+    i18n::text("a031_maybe_unset", key, &[("name", &name)])
+}
+
+fn a031_maybe_unset(span: Span, name: &str, labels: Vec<(Span, String)>) -> AError {
+    let mut e = AError::lesson(span, "a031_maybe_unset", &[("name", &name)]);
+    e.labels = labels;
+    e
+}
+
+/// `spans`/`types`: where and how the variable is set on two of the ways.
+fn a032_type_depends_on_way(name: &str, spans: (Span, Span), types: (AType, AType)) -> AError {
+    const KEY: &str = "a032_type_depends_on_way";
+    let mut e = AError::lesson(
+        spans.1,
+        KEY,
+        &[("name", &name), ("first", &types.0), ("second", &types.1)],
+    );
+    let label = i18n::text(KEY, "declared_as", &[("name", &name), ("ty", &types.0)]);
+    e.labels.push((spans.0, label));
+    e
+}
+
+fn a033_no_value(span: Span, name: &str) -> AError {
+    AError::lesson(span, "a033_no_value", &[("name", &name)])
+}
+
+/// `cycle`: the files in import order, starting and ending with the same one.
+pub fn a034_import_cycle(span: Span, cycle: &[String]) -> AError {
+    let mut e = AError::lesson(span, "a034_import_cycle", &[("cycle", &cycle.join(" -> "))]);
+    e.example = shared_example("import");
+    e
+}
+
+pub fn a035_same_module_name(span: Span, name: &str) -> AError {
+    let mut e = AError::lesson(span, "a035_same_module_name", &[("name", &name)]);
+    e.example = shared_example("import");
+    e
+}
+
+pub fn a036_no_main(span: Span) -> AError {
+    let mut e = AError::lesson(span, "a036_no_main", &[]);
+    e.example = shared_example("func");
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program;
+
+    /// Codes of the errors in program `src`.
+    fn program_codes(src: &str) -> Vec<String> {
+        let program = parse_program(src).expect("test program parses");
+        match analyze(&program, &HashMap::new(), None) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|e| e.code).collect(),
+        }
+    }
+
+    /// Codes of the errors in a `main` with this body.
+    fn codes(body: &str) -> Vec<String> {
+        program_codes(&format!("Func main() {{\n{}\n}}\n", body))
+    }
+
+    #[test]
+    fn builtin_without_a_value_cant_be_used_as_one() {
+        assert_eq!(codes("xs = [1]\nxs = Push(xs, 4)"), ["A033"]);
+        assert_eq!(codes("xs = [1]\nPrint(Push(xs, 1))"), ["A033"]);
+        assert_eq!(codes("x = Print(1)"), ["A033"]);
+        assert!(codes("xs = [1]\nPush(xs, 4)\nPrint(xs)").is_empty());
+    }
+
+    #[test]
+    fn function_without_a_result_cant_be_used_as_one() {
+        let with_f = |main: &str| program_codes(&format!("Func f() {{\n    Print(1)\n}}\n{}", main));
+        assert_eq!(with_f("Func main() {\n    y = f()\n    Print(y)\n}\n"), ["A033"]);
+        assert_eq!(with_f("Func main() {\n    Print(f())\n}\n"), ["A033"]);
+        assert!(with_f("Func main() {\n    f()\n}\n").is_empty());
+    }
+
+    const ASK: &str = "answer = ReadLine()\n";
+
+    #[test]
+    fn set_on_only_some_ways_is_a031() {
+        let if_only = "If answer == \"y\" then {\n    x = 1\n}\nPrint(x)";
+        assert_eq!(codes(&format!("{}{}", ASK, if_only)), ["A031"]);
+        let both = "If answer == \"y\" then {\n    x = 1\n} Else {\n    x = 2\n}\nPrint(x)";
+        assert!(codes(&format!("{}{}", ASK, both)).is_empty());
+        let elseif = "If answer == \"y\" then {\n    x = 1\n} ElseIf answer == \"n\" then {\n    x = 2\n}\nPrint(x)";
+        assert_eq!(codes(&format!("{}{}", ASK, elseif)), ["A031"]);
+        // Reported once, at the first read.
+        assert_eq!(codes(&format!("{}{}\nPrint(x)", ASK, if_only)), ["A031"]);
+    }
+
+    #[test]
+    fn match_arms_that_miss_a_variable_are_a031() {
+        let program = |arms: &str| {
+            format!(
+                "Enum Door {{ Open, Shut }}\n\nFunc main() {{\n    d = Open\n    Match d {{\n{}    }}\n    Print(x)\n}}\n",
+                arms
+            )
+        };
+        let missing = "        Open => x = 1\n        Shut => Print(0)\n";
+        assert_eq!(program_codes(&program(missing)), ["A031"]);
+        let all = "        Open => x = 1\n        Shut => x = 2\n";
+        assert!(program_codes(&program(all)).is_empty());
+    }
+
+    #[test]
+    fn different_types_on_different_ways_are_a032() {
+        let mixed = "If answer == \"y\" then {\n    x = 1\n} Else {\n    x = \"one\"\n}\nPrint(x)";
+        assert_eq!(codes(&format!("{}{}", ASK, mixed)), ["A032"]);
+        let same = "If answer == \"y\" then {\n    x = \"1\"\n} Else {\n    x = \"one\"\n}\nPrint(x)";
+        assert!(codes(&format!("{}{}", ASK, same)).is_empty());
+    }
+
+    #[test]
+    fn suggestions_pick_the_closest_name() {
+        let pick = |name: &str, names: &[&str]| did_you_mean(name, names.iter().copied());
+        assert_eq!(pick("Cuont", &["Counter", "Count"]), Some("Count".to_string()));
+        assert_eq!(pick("count", &["amount", "Count"]), Some("Count".to_string()));
+        // The first of equally close names wins.
+        assert_eq!(pick("totl", &["total", "tot"]), Some("total".to_string()));
+        assert_eq!(pick("apple", &["orange"]), None);
+        assert_eq!(pick("x", &["y"]), None);
+        assert_eq!(pick("total", &["total"]), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn a001_suggests_a_similar_name_before_declaring_one() {
+        let src = "Func main() {\n    Count = 1\n    Print(count)\n}\n";
+        let program = parse_program(src).unwrap();
+        let errors = analyze(&program, &HashMap::new(), None).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].help,
+            [
+                "Did you mean `Count`? Names must match exactly, including capital letters.",
+                "If you meant a new variable, declare it first: `count = <expr>`",
+            ]
+        );
+
+        let program = parse_program("Func main() {\n    Print(count)\n}\n").unwrap();
+        let errors = analyze(&program, &HashMap::new(), None).unwrap_err();
+        assert_eq!(errors[0].help, ["Declare it first: `count = <expr>`"]);
+    }
+
+    #[test]
+    fn list_lessons() {
+        assert_eq!(codes("xs = [1, \"two\"]\nPrint(xs)"), ["A008"]);
+        assert_eq!(codes("n = 3\nFor x in n {\n    Print(x)\n}"), ["A009"]);
+        assert_eq!(codes("xs = [1]\nPrint(xs[\"0\"])"), ["A010"]);
+        assert_eq!(codes("Push([1], 2)"), ["A011"]);
+        assert!(codes("xs = [1]\nPush(xs, 2)\nPrint(xs[0], Len(xs))").is_empty());
+    }
+
+    #[test]
+    fn map_lessons() {
+        assert_eq!(codes("m = {\"a\": 1}\nPrint(m[2])"), ["A012"]);
+        assert_eq!(codes("m = {\"a\": 1, \"b\": \"two\"}\nPrint(m)"), ["A013"]);
+        assert!(codes("m = {\"a\": 1}\nm[\"b\"] = 2\nPrint(m[\"b\"], Keys(m))").is_empty());
+    }
+
+    #[test]
+    fn struct_lessons() {
+        let with_point = |body: &str| {
+            program_codes(&format!("Struct Point {{ x: Int, y: Int }}\n\nFunc main() {{\n{}\n}}\n", body))
+        };
+        assert_eq!(with_point("p = Spot { x: 1 }\nPrint(p)"), ["A014"]);
+        assert_eq!(with_point("p = Point { x: 1, y: 2 }\nPrint(p.z)"), ["A015"]);
+        assert_eq!(with_point("p = Point { x: 1 }\nPrint(p)"), ["A016"]);
+        assert_eq!(with_point("p = Point { x: 1, y: 2, x: 3 }\nPrint(p)"), ["A037"]);
+        assert_eq!(with_point("p = Point { x: 1, y: \"2\" }\nPrint(p)"), ["A017"]);
+        assert_eq!(with_point("n = 1\nPrint(n.x)"), ["A018"]);
+        assert!(with_point("p = Point { x: 1, y: 2 }\np.y = 3\nPrint(p.x)").is_empty());
+    }
+
+    #[test]
+    fn enum_lessons() {
+        let decls = "Enum Door { Open, Shut(Int) }\nEnum Light { On, Off }";
+        let with_enums = |body: &str| {
+            program_codes(&format!("{}\n\nFunc main() {{\n    d = Shut(1)\n{}\n}}\n", decls, body))
+        };
+        assert_eq!(with_enums("Match d {\n    Open => Print(1)\n}"), ["A019"]);
+        assert_eq!(with_enums("n = 1\nMatch n {\n    _ => Print(1)\n}"), ["A020"]);
+        assert_eq!(with_enums("Match d {\n    Ajar => Print(1)\n    _ => Print(2)\n}"), ["A038"]);
+        assert_eq!(with_enums("Match d {\n    On => Print(1)\n    _ => Print(2)\n}"), ["A039"]);
+        let wrong_bindings = "Match d {\n    Open => Print(1)\n    Shut(a, b) => Print(a)\n}";
+        assert_eq!(with_enums(wrong_bindings), ["A040"]);
+        assert_eq!(with_enums("Match d {\n    _ => Print(2)\n    Open => Print(1)\n}"), ["A041"]);
+        assert_eq!(with_enums("e = Shut(\"1\")\nPrint(e)"), ["A021"]);
+        let covered = "Match d {\n    Open => Print(1)\n    Shut(n) => Print(n)\n}";
+        assert!(with_enums(covered).is_empty());
+
+        let twice = "Enum Door { Open, Open }\n\nFunc main() {\n    Print(Open)\n}\n";
+        assert_eq!(program_codes(twice), ["A042"]);
+    }
+
+    #[test]
+    fn float_lessons() {
+        assert_eq!(codes("x = 1 + 0.5"), ["A022"]);
+        assert_eq!(codes("x = 0.5\nPrint(x < 1)"), ["A022"]);
+        // Out-of-range literals are caught while parsing.
+        for big in ["99999999999999999999", "1e400"] {
+            let src = format!("Func main() {{\n    x = {}\n}}\n", big);
+            let lesson = parse_program(&src).unwrap_err().lesson.expect("a lesson");
+            assert_eq!(lesson.code, "A023");
+        }
+        assert_eq!(codes("x = ToInt(\"7\")"), ["A024"]);
+        assert!(codes("x = ToFloat(1) + 0.5\nPrint(ToInt(x))").is_empty());
+    }
+
+    #[test]
+    fn string_lessons() {
+        assert_eq!(codes("x = \"a\" < 1"), ["A025"]);
+        assert_eq!(codes("x = \"1\" == 1"), ["A025"]);
+        assert!(codes("x = \"a\" + \"b\"\nPrint(x < \"c\", Len(x) == 2)").is_empty());
+    }
+
+    #[test]
+    fn module_lessons() {
+        let math = parse_program("Func square(x: Int) -> Int {\n    Return x + x\n}\n").unwrap();
+        let interface = analyze(&math, &HashMap::new(), None).unwrap().interface;
+        let imports = HashMap::from([("math".to_string(), interface)]);
+        let errors = |body: &str| {
+            let src = format!("Func main() {{\n{}\n}}\n", body);
+            match analyze(&parse_program(&src).unwrap(), &imports, None) {
+                Ok(_) => Vec::new(),
+                Err(errors) => errors,
+            }
+        };
+
+        assert!(errors("Print(math.square(2))").is_empty());
+        let e = errors("Print(maths.square(2))");
+        assert_eq!(e[0].code, "A043");
+        assert!(e[0].help[0].contains("`math`"), "{:?}", e[0].help);
+        let e = errors("Print(math.sqare(2))");
+        assert_eq!(e[0].code, "A044");
+        assert!(e[0].help[0].contains("`math.square`"), "{:?}", e[0].help);
+    }
+
+    #[test]
+    fn format_lessons() {
+        assert_eq!(codes("x = Format(\"{} {}\", 1)"), ["A029"]);
+        assert_eq!(codes("x = Format(1, 2)"), ["A009"]);
+        assert_eq!(codes("PrintSep(1, 2)"), ["A009"]);
+        assert!(codes("t = \"{}\"\nx = Format(t, 1)\nPrintSep(\"-\", x)").is_empty());
+    }
+
+    #[test]
+    fn call_lessons() {
+        let double = "Func double(x: Int) -> Int {\n    Return x + x\n}\n\n";
+        let with_double = |body: &str| {
+            program_codes(&format!("{}Func main() {{\n{}\n}}\n", double, body))
+        };
+        assert_eq!(with_double("Print(double(1, 2))"), ["A027"]);
+        assert_eq!(with_double("Print(double(\"1\"))"), ["A027"]);
+        assert_eq!(with_double("Print()"), ["A045"]);
+        assert_eq!(with_double("x = Len(\"a\", \"b\")"), ["A045"]);
+        assert!(with_double("Print(double(2), len(\"ab\"))").is_empty());
+
+        let src = format!("{}Func main() {{\n    Print(doubel(1))\n}}\n", double);
+        let found = analyze(&parse_program(&src).unwrap(), &HashMap::new(), None).unwrap_err();
+        assert_eq!(found[0].code, "A030");
+        assert!(found[0].help[0].contains("`double(Int) -> Int`"), "{:?}", found[0].help);
+        assert_eq!(codes("Frobnicate(1)"), ["A030"]);
+    }
+
+    #[test]
+    fn function_declaration_lessons() {
+        let main = "Func main() {\n}\n";
+        let twice = "Func f() {\n}\n\nFunc f() {\n}\n\n";
+        assert_eq!(program_codes(&format!("{}{}", twice, main)), ["A046"]);
+        assert_eq!(program_codes(&format!("Func print(x: Int) {{\n}}\n\n{}", main)), ["A047"]);
+        assert_eq!(program_codes("Func main(x: Int) {\n}\n"), ["A048"]);
+        assert_eq!(program_codes("Func main() -> Int {\n    Return 1\n}\n"), ["A048"]);
+    }
+}
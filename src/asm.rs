@@ -0,0 +1,661 @@
+// src/asm.rs
+//
+// Textual bytecode: `.a.asm` files that mirror `bytecode::Instr` one line per
+// instruction, with labels instead of jump indices, local names instead of
+// slots and literals instead of constant-pool indices.
+//
+//     ; comments start with `;`
+//     .local x              ; declare local slots in order
+//     .file main.a          ; source file for the instructions that follow
+//     .line 2               ; source line for the instructions that follow
+//         LoadConst 40
+//         LoadConst 2
+//         AddInt
+//         StoreLocal x
+//     loop:                 ; a label names the next instruction
+//         LoadLocal x
+//         Print 1
+//         Halt
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::bytecode::{map_insert, Chunk, Instr, Value};
+use crate::vm;
+
+pub const ASM_EXT: &str = "a.asm";
+pub const ASM_SUFFIX: &str = ".a.asm";
+
+/* =========================
+   Disassembler
+   ========================= */
+
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut out = String::from("; A bytecode assembly\n");
+
+    for name in &chunk.locals {
+        let _ = writeln!(out, ".local {}", name);
+    }
+
+    // Every jump target gets a label; targets are printed in code order.
+    let mut targets: Vec<usize> = chunk
+        .code
+        .iter()
+        .filter_map(|i| match *i {
+            Instr::Jump(t) | Instr::JumpIfFalse(t) | Instr::Call(t, _) => Some(t),
+            _ => None,
+        })
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    let label = |t: usize| format!("L{}", t);
+
+    let mut file = None;
+    let mut line = 0;
+    for (ip, instr) in chunk.code.iter().enumerate() {
+        if targets.binary_search(&ip).is_ok() {
+            let _ = writeln!(out, "{}:", label(ip));
+        }
+        if let Some(name) = chunk.file_name(chunk.file_at(ip)) {
+            if file != Some(name) {
+                file = Some(name);
+                line = 0;
+                let _ = writeln!(out, ".file {}", name);
+            }
+        }
+        let instr_line = chunk.lines.get(ip).copied().unwrap_or(0);
+        if instr_line != line {
+            line = instr_line;
+            let _ = writeln!(out, ".line {}", line);
+        }
+
+        let (name, operand) = mnemonic(instr);
+        let operand = match operand {
+            Operand::None => String::new(),
+            Operand::Count(n) => n.to_string(),
+            Operand::Const(i) => match chunk.consts.get(i) {
+                Some(v) => vm::value_repr(v),
+                None => format!("#{}", i),
+            },
+            Operand::Local(i) => chunk
+                .locals
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| format!("#{}", i)),
+            Operand::Label(t) => label(t),
+            Operand::Name(i) => name_operand(chunk, i),
+            Operand::NameCount(i, n) => format!("{} {}", name_operand(chunk, i), n),
+            Operand::NameTagCount(i, tag, n) => {
+                format!("{} {} {}", name_operand(chunk, i), tag, n)
+            }
+            Operand::LabelCount(t, n) => format!("{} {}", label(t), n),
+            Operand::Slots(_, 0) => "0".to_string(),
+            Operand::Slots(first, n) => {
+                let first = chunk
+                    .locals
+                    .get(first)
+                    .cloned()
+                    .unwrap_or_else(|| format!("#{}", first));
+                format!("{} {}", first, n)
+            }
+        };
+        if operand.is_empty() {
+            let _ = writeln!(out, "    {}", name);
+        } else {
+            let _ = writeln!(out, "    {} {}", name, operand);
+        }
+    }
+
+    // A jump may target "one past the end" (finish the program).
+    if targets.binary_search(&chunk.code.len()).is_ok() {
+        let _ = writeln!(out, "{}:", label(chunk.code.len()));
+    }
+
+    out
+}
+
+enum Operand {
+    None,
+    Count(usize),
+    Const(usize),
+    Local(usize),
+    Label(usize),
+    /// A field or type name kept in the constant pool, written bare.
+    Name(usize),
+    NameCount(usize, usize),
+    /// A variant name, its tag and its number of values.
+    NameTagCount(usize, usize, usize),
+    /// A function to call and its number of arguments.
+    LabelCount(usize, usize),
+    /// A function's local slots: the first one by name and how many
+    /// (just `0` when it has none).
+    Slots(usize, usize),
+}
+
+fn name_operand(chunk: &Chunk, i: usize) -> String {
+    match chunk.consts.get(i) {
+        Some(Value::Str(s)) => s.clone(),
+        _ => format!("#{}", i),
+    }
+}
+
+fn mnemonic(instr: &Instr) -> (&'static str, Operand) {
+    match *instr {
+        Instr::LoadConst(i) => ("LoadConst", Operand::Const(i)),
+        Instr::ReadLine => ("ReadLine", Operand::None),
+        Instr::Print(n) => ("Print", Operand::Count(n)),
+        Instr::AddInt => ("AddInt", Operand::None),
+        Instr::LoadLocal(i) => ("LoadLocal", Operand::Local(i)),
+        Instr::StoreLocal(i) => ("StoreLocal", Operand::Local(i)),
+        Instr::Jump(t) => ("Jump", Operand::Label(t)),
+        Instr::JumpIfFalse(t) => ("JumpIfFalse", Operand::Label(t)),
+        Instr::CmpEq => ("CmpEq", Operand::None),
+        Instr::CmpNe => ("CmpNe", Operand::None),
+        Instr::CmpLt => ("CmpLt", Operand::None),
+        Instr::CmpLe => ("CmpLe", Operand::None),
+        Instr::CmpGt => ("CmpGt", Operand::None),
+        Instr::CmpGe => ("CmpGe", Operand::None),
+        Instr::Halt => ("Halt", Operand::None),
+        Instr::MakeList(n) => ("MakeList", Operand::Count(n)),
+        Instr::Index => ("Index", Operand::None),
+        Instr::Len => ("Len", Operand::None),
+        Instr::ListPush => ("ListPush", Operand::None),
+        Instr::MakeMap(n) => ("MakeMap", Operand::Count(n)),
+        Instr::SetIndex => ("SetIndex", Operand::None),
+        Instr::Has => ("Has", Operand::None),
+        Instr::Keys => ("Keys", Operand::None),
+        Instr::MakeRecord(name, n) => ("MakeRecord", Operand::NameCount(name, n)),
+        Instr::GetField(i) => ("GetField", Operand::Name(i)),
+        Instr::SetField(i) => ("SetField", Operand::Name(i)),
+        Instr::MakeVariant(name, tag, n) => ("MakeVariant", Operand::NameTagCount(name, tag, n)),
+        Instr::Switch(n) => ("Switch", Operand::Count(n)),
+        Instr::Payload(i) => ("Payload", Operand::Count(i)),
+        Instr::AddFloat => ("AddFloat", Operand::None),
+        Instr::CmpLtFloat => ("CmpLtFloat", Operand::None),
+        Instr::CmpLeFloat => ("CmpLeFloat", Operand::None),
+        Instr::CmpGtFloat => ("CmpGtFloat", Operand::None),
+        Instr::CmpGeFloat => ("CmpGeFloat", Operand::None),
+        Instr::ToFloat => ("ToFloat", Operand::None),
+        Instr::ToInt => ("ToInt", Operand::None),
+        Instr::Concat => ("Concat", Operand::None),
+        Instr::Upper => ("Upper", Operand::None),
+        Instr::Lower => ("Lower", Operand::None),
+        Instr::Trim => ("Trim", Operand::None),
+        Instr::Split => ("Split", Operand::None),
+        Instr::Contains => ("Contains", Operand::None),
+        Instr::Substring => ("Substring", Operand::None),
+        Instr::Call(t, n) => ("Call", Operand::LabelCount(t, n)),
+        Instr::Enter(first, n) => ("Enter", Operand::Slots(first, n)),
+        Instr::Ret => ("Ret", Operand::None),
+        Instr::Pop => ("Pop", Operand::None),
+        Instr::Write(n) => ("Write", Operand::Count(n)),
+        Instr::PrintSep(n) => ("PrintSep", Operand::Count(n)),
+        Instr::Format(n) => ("Format", Operand::Count(n)),
+    }
+}
+
+/* =========================
+   Assembler
+   ========================= */
+
+/// Assemble `.a.asm` text into a Chunk. Errors name the offending line.
+pub fn assemble(text: &str) -> Result<Chunk, String> {
+    let mut chunk = Chunk::new();
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    // (instruction index, label, source line) for jumps whose target comes later
+    let mut fixups: Vec<(usize, String, usize)> = Vec::new();
+    let mut file = 0u32;
+    let mut line = 0u32;
+
+    for (n, raw) in text.lines().enumerate() {
+        let lineno = n + 1;
+        let err = |msg: String| format!("line {}: {}", lineno, msg);
+
+        let stmt = strip_comment(raw).trim();
+        if stmt.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = stmt.strip_suffix(':') {
+            let name = name.trim();
+            if !is_name(name) {
+                return Err(err(format!("`{}` is not a valid label name", name)));
+            }
+            if labels.insert(name.to_string(), chunk.code.len()).is_some() {
+                return Err(err(format!("label `{}` is defined twice", name)));
+            }
+            continue;
+        }
+
+        let (word, rest) = match stmt.find(char::is_whitespace) {
+            Some(i) => (&stmt[..i], stmt[i..].trim()),
+            None => (stmt, ""),
+        };
+
+        if let Some(directive) = word.strip_prefix('.') {
+            match directive {
+                "local" => {
+                    if !is_name(rest) {
+                        return Err(err(format!("`{}` is not a valid local name", rest)));
+                    }
+                    if chunk.locals.iter().any(|l| l == rest) {
+                        return Err(err(format!("local `{}` is declared twice", rest)));
+                    }
+                    chunk.locals.push(rest.to_string());
+                }
+                "file" => {
+                    if rest.is_empty() {
+                        return Err(err("`.file` needs a file name".to_string()));
+                    }
+                    file = match chunk.file_names.iter().position(|f| f == rest) {
+                        Some(i) => i as u32,
+                        None => {
+                            chunk.file_names.push(rest.to_string());
+                            chunk.file_names.len() as u32 - 1
+                        }
+                    };
+                }
+                "line" => {
+                    line = rest
+                        .parse()
+                        .map_err(|_| err(format!("`.line` needs a number, got `{}`", rest)))?;
+                }
+                other => return Err(err(format!("unknown directive `.{}`", other))),
+            }
+            continue;
+        }
+
+        let no_operand = |instr: Instr| {
+            if rest.is_empty() {
+                Ok(instr)
+            } else {
+                Err(err(format!("`{}` takes no operand", word)))
+            }
+        };
+        let count = || {
+            rest.parse::<usize>()
+                .map_err(|_| err(format!("`{}` needs a count, got `{}`", word, rest)))
+        };
+        let local = |chunk: &Chunk| {
+            chunk.locals.iter().position(|l| l == rest).ok_or_else(|| {
+                err(format!(
+                    "unknown local `{}` (declare it first with `.local {}`)",
+                    rest, rest
+                ))
+            })
+        };
+        let name = |chunk: &mut Chunk, text: &str| {
+            if !is_name(text) {
+                return Err(err(format!("`{}` needs a name, got `{}`", word, text)));
+            }
+            Ok(chunk.add_const(Value::Str(text.to_string())))
+        };
+        let mut target = |chunk: &Chunk| {
+            if !is_name(rest) {
+                return Err(err(format!("`{}` needs a label, got `{}`", word, rest)));
+            }
+            fixups.push((chunk.code.len(), rest.to_string(), lineno));
+            Ok(0)
+        };
+
+        let instr = match word {
+            "LoadConst" => {
+                let v = parse_literal(rest).map_err(err)?;
+                Instr::LoadConst(chunk.add_const(v))
+            }
+            "ReadLine" => no_operand(Instr::ReadLine)?,
+            "Print" => Instr::Print(count()?),
+            "Write" => Instr::Write(count()?),
+            "PrintSep" => Instr::PrintSep(count()?),
+            "Format" => Instr::Format(count()?),
+            "AddInt" => no_operand(Instr::AddInt)?,
+            "LoadLocal" => Instr::LoadLocal(local(&chunk)?),
+            "StoreLocal" => Instr::StoreLocal(local(&chunk)?),
+            "Jump" => Instr::Jump(target(&chunk)?),
+            "JumpIfFalse" => Instr::JumpIfFalse(target(&chunk)?),
+            "CmpEq" => no_operand(Instr::CmpEq)?,
+            "CmpNe" => no_operand(Instr::CmpNe)?,
+            "CmpLt" => no_operand(Instr::CmpLt)?,
+            "CmpLe" => no_operand(Instr::CmpLe)?,
+            "CmpGt" => no_operand(Instr::CmpGt)?,
+            "CmpGe" => no_operand(Instr::CmpGe)?,
+            "Halt" => no_operand(Instr::Halt)?,
+            "MakeList" => Instr::MakeList(count()?),
+            "Index" => no_operand(Instr::Index)?,
+            "Len" => no_operand(Instr::Len)?,
+            "ListPush" => no_operand(Instr::ListPush)?,
+            "MakeMap" => Instr::MakeMap(count()?),
+            "SetIndex" => no_operand(Instr::SetIndex)?,
+            "Has" => no_operand(Instr::Has)?,
+            "Keys" => no_operand(Instr::Keys)?,
+            "MakeRecord" => {
+                let (type_name, n) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let n = n.trim().parse::<usize>().map_err(|_| {
+                    err(format!("`MakeRecord` needs a type name and a field count, got `{}`", rest))
+                })?;
+                Instr::MakeRecord(name(&mut chunk, type_name)?, n)
+            }
+            "GetField" => Instr::GetField(name(&mut chunk, rest)?),
+            "SetField" => Instr::SetField(name(&mut chunk, rest)?),
+            "MakeVariant" => {
+                let parts: Vec<&str> = rest.split_whitespace().collect();
+                let numbers = match parts.as_slice() {
+                    [variant, tag, n] => tag.parse::<usize>().ok().zip(n.parse::<usize>().ok()).map(|tn| (*variant, tn)),
+                    _ => None,
+                };
+                let Some((variant, (tag, n))) = numbers else {
+                    return Err(err(format!(
+                        "`MakeVariant` needs a variant name, a tag and a value count, got `{}`",
+                        rest
+                    )));
+                };
+                Instr::MakeVariant(name(&mut chunk, variant)?, tag, n)
+            }
+            "Switch" => Instr::Switch(count()?),
+            "Payload" => Instr::Payload(count()?),
+            "AddFloat" => no_operand(Instr::AddFloat)?,
+            "CmpLtFloat" => no_operand(Instr::CmpLtFloat)?,
+            "CmpLeFloat" => no_operand(Instr::CmpLeFloat)?,
+            "CmpGtFloat" => no_operand(Instr::CmpGtFloat)?,
+            "CmpGeFloat" => no_operand(Instr::CmpGeFloat)?,
+            "ToFloat" => no_operand(Instr::ToFloat)?,
+            "ToInt" => no_operand(Instr::ToInt)?,
+            "Concat" => no_operand(Instr::Concat)?,
+            "Upper" => no_operand(Instr::Upper)?,
+            "Lower" => no_operand(Instr::Lower)?,
+            "Trim" => no_operand(Instr::Trim)?,
+            "Split" => no_operand(Instr::Split)?,
+            "Contains" => no_operand(Instr::Contains)?,
+            "Substring" => no_operand(Instr::Substring)?,
+            "Call" => {
+                let (label, n) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let n = n.trim().parse::<usize>().ok().filter(|_| is_name(label));
+                let Some(n) = n else {
+                    return Err(err(format!(
+                        "`Call` needs a label and an argument count, got `{}`",
+                        rest
+                    )));
+                };
+                fixups.push((chunk.code.len(), label.to_string(), lineno));
+                Instr::Call(0, n)
+            }
+            "Enter" if rest == "0" => Instr::Enter(0, 0),
+            "Enter" => {
+                let (first, n) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let first = chunk.locals.iter().position(|l| l == first);
+                let Some((first, n)) = first.zip(n.trim().parse::<usize>().ok()) else {
+                    return Err(err(format!(
+                        "`Enter` needs the function's first local and how many it has (or just `0`), got `{}`",
+                        rest
+                    )));
+                };
+                Instr::Enter(first, n)
+            }
+            "Ret" => no_operand(Instr::Ret)?,
+            "Pop" => no_operand(Instr::Pop)?,
+            other => return Err(err(format!("unknown instruction `{}`", other))),
+        };
+        chunk.push_in(instr, file, line);
+    }
+
+    for (ip, name, lineno) in fixups {
+        let t = *labels
+            .get(&name)
+            .ok_or_else(|| format!("line {}: no label named `{}`", lineno, name))?;
+        chunk.code[ip] = match chunk.code[ip] {
+            Instr::Jump(_) => Instr::Jump(t),
+            Instr::JumpIfFalse(_) => Instr::JumpIfFalse(t),
+            Instr::Call(_, n) => Instr::Call(t, n),
+            other => other,
+        };
+    }
+
+    Ok(chunk)
+}
+
+/// Names may also start with `$` (the compiler's hidden loop locals) and
+/// contain `.` and `$` (a function's locals, e.g. `area.w` or `area.$i1`).
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+/// Drop a `;` comment, ignoring `;` inside string or char literals.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ';' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+/// Parse a constant written the way `vm::value_repr` prints it, including
+/// nested lists `[1, 2]` and maps `{"a": 1}`.
+fn parse_literal(s: &str) -> Result<Value, String> {
+    let mut p = Literal { s, pos: 0 };
+    let v = p.value()?;
+    p.skip_ws();
+    if p.pos != s.len() {
+        return Err(format!("unexpected `{}` after the literal", &s[p.pos..]));
+    }
+    Ok(v)
+}
+
+struct Literal<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Literal<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.s.len() - trimmed.len();
+    }
+
+    /// Consume `c` (after any spaces) if it comes next.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}` in literal `{}`", c, self.s))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_ws();
+        if self.eat('[') {
+            let mut items = Vec::new();
+            while !self.eat(']') {
+                items.push(self.value()?);
+                if !self.eat(',') {
+                    self.expect(']')?;
+                    break;
+                }
+            }
+            return Ok(Value::List(items));
+        }
+        if self.eat('{') {
+            let mut entries = Vec::new();
+            while !self.eat('}') {
+                let k = self.value()?;
+                self.expect(':')?;
+                let v = self.value()?;
+                map_insert(&mut entries, k, v);
+                if !self.eat(',') {
+                    self.expect('}')?;
+                    break;
+                }
+            }
+            return Ok(Value::Map(entries));
+        }
+        if let Some(q) = self.rest().chars().next().filter(|&c| c == '"' || c == '\'') {
+            return self.quoted(q);
+        }
+
+        // A bare word: number, true/false or ().
+        let end = self
+            .rest()
+            .find(|c: char| c.is_whitespace() || ",:]}".contains(c))
+            .unwrap_or(self.rest().len());
+        let word = &self.rest()[..end];
+        self.pos += end;
+        if is_name(word) && self.eat('{') {
+            // Record: Point { x: 1, y: 2 }
+            let mut fields = Vec::new();
+            while !self.eat('}') {
+                self.skip_ws();
+                let end = self
+                    .rest()
+                    .find(|c: char| c.is_whitespace() || c == ':')
+                    .unwrap_or(self.rest().len());
+                let field = self.rest()[..end].to_string();
+                self.pos += end;
+                self.expect(':')?;
+                fields.push((field, self.value()?));
+                if !self.eat(',') {
+                    self.expect('}')?;
+                    break;
+                }
+            }
+            return Ok(Value::Record(word.to_string(), fields));
+        }
+        match word {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            "()" => Ok(Value::Unit),
+            _ => word
+                .parse::<i64>()
+                .map(Value::Int)
+                .or_else(|_| word.parse::<f64>().map(Value::Float))
+                .map_err(|_| {
+                format!(
+                    "`{}` is not a literal (use a number, true/false, 'c', \"text\", (), [..] or {{..}})",
+                    word
+                )
+            }),
+        }
+    }
+
+    fn quoted(&mut self, q: char) -> Result<Value, String> {
+        let body_start = self.pos + 1;
+        let mut escaped = false;
+        let close = self.s[body_start..].char_indices().find(|&(_, c)| {
+            let is_close = !escaped && c == q;
+            escaped = !escaped && c == '\\';
+            is_close
+        });
+        let Some((len, _)) = close else {
+            return Err(format!("missing closing {} in literal `{}`", q, self.s));
+        };
+        let text = unescape(&self.s[body_start..body_start + len])?;
+        self.pos = body_start + len + 1;
+
+        if q == '"' {
+            return Ok(Value::Str(text));
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(Value::Char(c)),
+            _ => Err(format!("char literal '{}' must hold exactly one character", text)),
+        }
+    }
+}
+
+/// Undo the escapes `{:?}` produces: \n \r \t \0 \\ \" \' and \u{...}.
+fn unescape(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            Some('\'') => out.push('\''),
+            Some('u') => {
+                let rest: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let hex = rest
+                    .strip_prefix('{')
+                    .ok_or_else(|| "`\\u` must be written as \\u{XXXX}".to_string())?;
+                let ch = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("`\\u{{{}}}` is not a valid character", hex))?;
+                out.push(ch);
+            }
+            Some(other) => return Err(format!("unknown escape `\\{}`", other)),
+            None => return Err("literal ends with a lone `\\`".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::OptLevel;
+    use crate::pipeline::compile_test_program;
+
+    fn round_trips(chunk: &Chunk) {
+        let text = disassemble(chunk);
+        let back = assemble(&text).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+        assert_eq!(format!("{:?}", back), format!("{:?}", chunk), "\n{}", text);
+    }
+
+    #[test]
+    fn disassembled_programs_assemble_to_the_same_chunk() {
+        let src = "Enum Shape { Dot, Square(Float) }\n\n\
+                   Func area(w: Float) -> Float {\n    Return w + w\n}\n\n\
+                   Func main() {\n    x = 0.1 + 0.2\n    Print(x)\n    s = Square(0.1)\n    Match s {\n        Dot => Print(\"dot\")\n        Square(w) => Print(area(w))\n    }\n    Print(\"two words; and #\")\n}\n";
+        for opt in [OptLevel::O0, OptLevel::O1] {
+            let chunk = compile_test_program(src, opt);
+            assert!(chunk.code.iter().any(|i| matches!(i, Instr::Switch(_))));
+            assert!(chunk.code.iter().any(|i| matches!(i, Instr::Enter(..))));
+            round_trips(&chunk);
+        }
+    }
+
+    #[test]
+    fn hand_written_constants_survive() {
+        let chunk = assemble(
+            ".local x\n.line 1\n    LoadConst 0.1\n    LoadConst -0.0\n    LoadConst \"a\\nb\"\n    LoadConst [1, 2.5, \"c\"]\n    Halt\n",
+        )
+        .unwrap();
+        round_trips(&chunk);
+    }
+}
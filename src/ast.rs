@@ -3,7 +3,7 @@
 // All core AST types live here.
 // Keep this module "dumb": structs/enums + span helpers only.
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
@@ -23,6 +23,7 @@ pub enum CmpOp {
 #[allow(dead_code)]
 pub enum Expr {
     Int(i64, Span),
+    Float(f64, Span),
     Str(String, Span),
     Char(char, Span),
     Bool(bool, Span),
@@ -36,6 +37,21 @@ pub enum Expr {
 
     /// Function call: Name(args...)
     Call(String, Vec<Expr>, Span),
+
+    /// List literal: [a, b, c]
+    List(Vec<Expr>, Span),
+
+    /// Indexing: xs[i] / m[key]
+    Index(Box<Expr>, Box<Expr>, Span),
+
+    /// Map literal: { key: value, ... }
+    Map(Vec<(Expr, Expr)>, Span),
+
+    /// Struct value: Point { x: 1, y: 2 } (each field keeps its own span)
+    Record(String, Vec<(String, Expr, Span)>, Span),
+
+    /// Field access: p.x
+    Field(Box<Expr>, String, Span),
 }
 
 #[derive(Debug, Clone)]
@@ -65,6 +81,22 @@ pub enum Stmt {
         span: Span,
     },
 
+    /// `xs[i] = v` / `m[key] = v`
+    SetIndex {
+        name: String,
+        index: Expr,
+        expr: Expr,
+        span: Span,
+    },
+
+    /// `p.x = v`
+    SetField {
+        name: String,
+        field: String,
+        expr: Expr,
+        span: Span,
+    },
+
     /// `mute x = 1` (your A keyword for mutable variable creation)
     Mute {
         name: String,
@@ -81,13 +113,96 @@ pub enum Stmt {
         span: Span,
     },
 
+    /// `Match s { Circle(r) => ..., _ => ... }`
+    Match {
+        expr: Expr,
+        arms: Vec<MatchArm>,
+        span: Span,
+    },
+
+    /// `For x in xs { ... }`
+    For {
+        var: String,
+        iter: Expr,
+        body: Vec<Stmt>,
+        span: Span,
+    },
+
+    /// `Return x` / `Return` (ends a function; in main, ends the program)
+    Return {
+        expr: Option<Expr>,
+        span: Span,
+    },
+
     /// Expression used as a statement (e.g. a function call)
     Expr(Expr),
 }
 
+/// One `Pattern => body` case of a Match.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    /// Variant name, or `None` for the catch-all `_`.
+    pub variant: Option<String>,
+    /// Names bound to the variant's values (`_` ignores one).
+    pub bindings: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+/// `Enum Shape { Circle(Int), Square(Int), Empty }`
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct EnumDecl {
+    pub name: String,
+    /// (variant name, payload type names, span of the variant)
+    pub variants: Vec<(String, Vec<String>, Span)>,
+    pub span: Span,
+}
+
+/// `Struct Point { x: Int, y: Int }`
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct StructDecl {
+    pub name: String,
+    /// (field name, type name, span of the field)
+    pub fields: Vec<(String, String, Span)>,
+    pub span: Span,
+}
+
+/// `Func area(w: Int, h: Int) -> Int { ... }`
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct FuncDecl {
+    pub name: String,
+    /// (parameter name, type name, span of the parameter)
+    pub params: Vec<(String, String, Span)>,
+    /// Type name after `->`, or `None` for a function that gives nothing back.
+    pub ret: Option<String>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+/// `Import "lib/math.a"` / `Use math`: the file's functions are then called
+/// as `math.square(x)`, named after the file.
+#[derive(Debug, Clone)]
+pub struct Import {
+    /// Module name used in qualified calls (the file name without `.a`).
+    pub name: String,
+    /// Path as written, relative to the importing file.
+    pub path: String,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct Program {
+    pub imports: Vec<Import>,
+    pub structs: Vec<StructDecl>,
+    pub enums: Vec<EnumDecl>,
+    pub funcs: Vec<FuncDecl>,
+    /// Body of `main` (empty when the file has none).
     pub stmts: Vec<Stmt>,
+    /// Span of `Func main() { ... }`; only the file being run needs one.
+    pub main: Option<Span>,
 }
 
 /* =========================
@@ -97,13 +212,19 @@ pub struct Program {
 pub fn expr_span(e: &Expr) -> Span {
     match e {
         Expr::Int(_, sp)
+        | Expr::Float(_, sp)
         | Expr::Str(_, sp)
         | Expr::Char(_, sp)
         | Expr::Bool(_, sp)
         | Expr::Var(_, sp)
         | Expr::Add(_, _, sp)
         | Expr::Cmp(_, _, _, sp)
-        | Expr::Call(_, _, sp) => *sp,
+        | Expr::Call(_, _, sp)
+        | Expr::List(_, sp)
+        | Expr::Index(_, _, sp)
+        | Expr::Map(_, sp)
+        | Expr::Record(_, _, sp)
+        | Expr::Field(_, _, sp) => *sp,
     }
 }
 
@@ -112,8 +233,21 @@ pub fn stmt_span(s: &Stmt) -> Span {
     match s {
         Stmt::Let { span, .. }
         | Stmt::Assign { span, .. }
+        | Stmt::SetIndex { span, .. }
+        | Stmt::SetField { span, .. }
         | Stmt::Mute { span, .. }
-        | Stmt::If { span, .. } => *span,
+        | Stmt::If { span, .. }
+        | Stmt::Match { span, .. }
+        | Stmt::For { span, .. }
+        | Stmt::Return { span, .. } => *span,
         Stmt::Expr(e) => expr_span(e),
     }
 }
+
+/// From the first to the last statement of a block (`None` when it's empty).
+pub fn block_span(stmts: &[Stmt]) -> Option<Span> {
+    Some(Span {
+        start: stmt_span(stmts.first()?).start,
+        end: stmt_span(stmts.last()?).end,
+    })
+}
@@ -0,0 +1,228 @@
+// src/builtins.rs
+//
+// Every function A provides without an import. The ones in `BUILTINS` are
+// run by the VM itself; the rest are written in A (`prelude.a`), compiled with
+// every program and linked in like an imported file. Both kinds are called by
+// bare name, in any letter case (`print`, `Print`).
+
+use crate::bytecode::Instr;
+
+/// Source of the part of the standard library written in A.
+pub const PRELUDE_SRC: &str = include_str!("prelude.a");
+
+/// File name shown for code in the prelude.
+pub const PRELUDE_FILE: &str = "<prelude>";
+
+/// Module name calls into the prelude are linked through. Not a valid
+/// identifier, so no imported file can take it.
+pub const PRELUDE_MODULE: &str = "$prelude";
+
+/// What a builtin accepts for one of its values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Param {
+    /// Any value.
+    Any,
+    Int,
+    Str,
+    /// A String with one `{}` for each value after it.
+    Template,
+    /// An Int or a Float.
+    Number,
+    /// A List, a Map or a String.
+    Sized,
+    /// A list variable: the result is stored back into it.
+    ListVar,
+    Map,
+    /// A value of the item type of the first value (a list).
+    Item,
+    /// A key of the first value (a map).
+    Key,
+}
+
+/// What a builtin gives back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ret {
+    Nothing,
+    Int,
+    Float,
+    Bool,
+    Str,
+    /// A List of Strings.
+    StrList,
+    /// A List of the keys of the first value (a map).
+    KeyList,
+}
+
+/// How the VM runs a builtin. The values are on the stack in order.
+#[derive(Debug, Clone, Copy)]
+pub enum Native {
+    /// This instruction.
+    Instr(Instr),
+    /// The instruction made from how many values were given for the
+    /// repeated last parameter (`Print(a, b)` -> `Print(2)`).
+    Counted(fn(usize) -> Instr),
+}
+
+pub struct Builtin {
+    /// Name as the docs spell it.
+    pub name: &'static str,
+    /// How a call looks, for messages.
+    pub usage: &'static str,
+    pub params: &'static [Param],
+    /// The last parameter may be given any number of times (at least once).
+    pub variadic: bool,
+    pub ret: Ret,
+    pub native: Native,
+}
+
+impl Builtin {
+    /// How many values a call must pass (`None`: any number from `min_arity`).
+    pub fn arity(&self) -> Option<usize> {
+        (!self.variadic).then_some(self.params.len())
+    }
+
+    /// The fewest values a call can pass.
+    pub fn min_arity(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Whether a call may pass `n` values.
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.min_arity() && self.arity().is_none_or(|a| a == n)
+    }
+
+    /// How many values a call passes before the repeated last parameter.
+    pub fn fixed_params(&self) -> usize {
+        if self.variadic {
+            self.params.len() - 1
+        } else {
+            self.params.len()
+        }
+    }
+
+    /// What the builtin accepts for its value at `index`.
+    pub fn param(&self, index: usize) -> Param {
+        match self.params.get(index) {
+            Some(&p) => p,
+            None => self.params.last().copied().unwrap_or(Param::Any),
+        }
+    }
+}
+
+const fn builtin(
+    name: &'static str,
+    usage: &'static str,
+    params: &'static [Param],
+    ret: Ret,
+    instr: Instr,
+) -> Builtin {
+    Builtin {
+        name,
+        usage,
+        params,
+        variadic: false,
+        ret,
+        native: Native::Instr(instr),
+    }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "Print",
+        usage: "Print(a, b, ...)",
+        params: &[Param::Any],
+        variadic: true,
+        ret: Ret::Nothing,
+        native: Native::Counted(Instr::Print),
+    },
+    Builtin {
+        name: "Write",
+        usage: "Write(a, b, ...)",
+        params: &[Param::Any],
+        variadic: true,
+        ret: Ret::Nothing,
+        native: Native::Counted(Instr::Write),
+    },
+    Builtin {
+        name: "PrintSep",
+        usage: "PrintSep(sep, a, b, ...)",
+        params: &[Param::Str, Param::Any],
+        variadic: true,
+        ret: Ret::Nothing,
+        native: Native::Counted(Instr::PrintSep),
+    },
+    Builtin {
+        name: "Format",
+        usage: "Format(\"{} of {}\", a, b, ...)",
+        params: &[Param::Template, Param::Any],
+        variadic: true,
+        ret: Ret::Str,
+        native: Native::Counted(Instr::Format),
+    },
+    builtin("ReadLine", "ReadLine()", &[], Ret::Str, Instr::ReadLine),
+    builtin("Len", "Len(xs)", &[Param::Sized], Ret::Int, Instr::Len),
+    builtin("Push", "Push(xs, value)", &[Param::ListVar, Param::Item], Ret::Nothing, Instr::ListPush),
+    builtin("Has", "Has(m, key)", &[Param::Map, Param::Key], Ret::Bool, Instr::Has),
+    builtin("Keys", "Keys(m)", &[Param::Map], Ret::KeyList, Instr::Keys),
+    builtin("ToFloat", "ToFloat(n)", &[Param::Number], Ret::Float, Instr::ToFloat),
+    builtin("ToInt", "ToInt(x)", &[Param::Number], Ret::Int, Instr::ToInt),
+    builtin("Upper", "Upper(s)", &[Param::Str], Ret::Str, Instr::Upper),
+    builtin("Lower", "Lower(s)", &[Param::Str], Ret::Str, Instr::Lower),
+    builtin("Trim", "Trim(s)", &[Param::Str], Ret::Str, Instr::Trim),
+    builtin("Split", "Split(s, sep)", &[Param::Str, Param::Str], Ret::StrList, Instr::Split),
+    builtin("Contains", "Contains(s, part)", &[Param::Str, Param::Str], Ret::Bool, Instr::Contains),
+    builtin(
+        "Substring",
+        "Substring(s, start, end)",
+        &[Param::Str, Param::Int, Param::Int],
+        Ret::Str,
+        Instr::Substring,
+    ),
+];
+
+/// The VM-run builtin called `name` (any letter case).
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program;
+    use crate::pipeline::{run_test_program, strip_line_comments_preserve_len};
+
+    #[test]
+    fn lookup_ignores_letter_case() {
+        assert_eq!(lookup("print").map(|b| b.name), Some("Print"));
+        assert_eq!(lookup("SUBSTRING").map(|b| b.name), Some("Substring"));
+        assert!(lookup("Max").is_none(), "Max is in the prelude");
+        let sub = lookup("Substring").unwrap();
+        assert!(sub.accepts(3) && !sub.accepts(2) && !sub.accepts(4));
+        assert_eq!(sub.param(2), Param::Int);
+        let print = lookup("Print").unwrap();
+        assert!(print.accepts(1) && print.accepts(5) && !print.accepts(0));
+        assert_eq!(print.param(4), Param::Any);
+    }
+
+    #[test]
+    fn prelude_functions_dont_shadow_builtins() {
+        let src = strip_line_comments_preserve_len(PRELUDE_SRC);
+        let prelude = parse_program(&src).expect("prelude parses");
+        for f in &prelude.funcs {
+            assert!(lookup(&f.name).is_none(), "{} is also a builtin", f.name);
+        }
+    }
+
+    #[test]
+    fn prelude_functions_run() {
+        let src = concat!(
+            "Func main() {\n",
+            "    Print(Max(2, 5), min(2, 5), Clamp(9, 0, 3))\n",
+            "    Print(Join(Split(\"a b c\", \" \"), \"-\"), StartsWith(\"abc\", \"ab\"))\n",
+            "    Print(IsBlank(\" \"), IsBlank(\"x\"))\n",
+            "    Print(Sum([1, 2, 3]), Includes([1, 2], 3))\n",
+            "}\n",
+        );
+        assert_eq!(run_test_program(src).unwrap(), "5 2 3\na-b-c true\ntrue false\n6 false\n");
+    }
+}
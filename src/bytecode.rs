@@ -4,12 +4,34 @@
 // You can extend this as you add features (strings, locals, jumps, etc.).
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-pub const BYTECODE_VERSION: u32 = 1;
+mod legacy;
+
+// Compatibility rules for the on-disk format:
+// - Every change bumps `BYTECODE_VERSION`, so an older A given a newer file
+//   says it is too new instead of failing to decode it.
+// - New `Value` and `Instr` variants go at the end. Files from before them
+//   then still decode with the current types: add the old version to
+//   `SAME_LAYOUT`.
+// - Any other change (reordering variants, changing payloads, adding fields to
+//   `Chunk`/`BytecodeFile`) also needs a frozen copy of the old layout and an
+//   upgrade step in `legacy.rs`.
+//
+// Versions: v4 added this container; v5 added lists, maps, structs, enums,
+// floats, string operations, modules and `Write`; v6 added the file of each
+// instruction (`Chunk::files`, `Chunk::file_names`).
+
+/// First bytes of every `.a.byte` file.
+pub const BYTECODE_MAGIC: &[u8; 4] = b"A\0BC";
+pub const BYTECODE_VERSION: u32 = 6;
+/// Container versions whose payload decodes with the current types.
+const SAME_LAYOUT: std::ops::RangeInclusive<u32> = 6..=BYTECODE_VERSION;
 pub const BYTECODE_EXT: &str = "a.byte";
 pub const BYTECODE_SUFFIX: &str = ".a.byte";
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+// Not `Eq`: a Float can be NaN, which is not equal to itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum Value {
     Int(i64),
@@ -17,18 +39,59 @@ pub enum Value {
     Char(char),
     Str(String),
     Unit,
+    List(Vec<Value>),
+    /// Key/value pairs in insertion order, keys unique.
+    Map(Vec<(Value, Value)>),
+    /// A struct value: type name and (field name, value) in declaration order.
+    Record(String, Vec<(String, Value)>),
+    /// An enum value: variant name, its position in the Enum, and its values.
+    Variant(String, usize, Vec<Value>),
+    Float(f64),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Whether two constants can share a pool slot: equal, with Floats compared
+/// bit for bit, so `-0.0` keeps its sign and a NaN is reused like any other
+/// value (`==` says `-0.0 == 0.0` and `NaN != NaN`).
+fn identical(a: &Value, b: &Value) -> bool {
+    let all = |xs: &[Value], ys: &[Value]| {
+        xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| identical(x, y))
+    };
+    match (a, b) {
+        (Value::Float(x), Value::Float(y)) => x.to_bits() == y.to_bits(),
+        (Value::List(xs), Value::List(ys)) => all(xs, ys),
+        (Value::Map(xs), Value::Map(ys)) => {
+            xs.len() == ys.len()
+                && xs.iter().zip(ys).all(|((k, v), (l, w))| identical(k, l) && identical(v, w))
+        }
+        (Value::Record(n, xs), Value::Record(m, ys)) => {
+            n == m
+                && xs.len() == ys.len()
+                && xs.iter().zip(ys).all(|((f, v), (g, w))| f == g && identical(v, w))
+        }
+        (Value::Variant(n, i, xs), Value::Variant(m, j, ys)) => n == m && i == j && all(xs, ys),
+        _ => a == b,
+    }
+}
+
+/// Set `key` in a map's entries: replace the value if the key is there,
+/// otherwise append (so maps keep insertion order).
+pub fn map_insert(entries: &mut Vec<(Value, Value)>, key: Value, value: Value) {
+    match entries.iter_mut().find(|(k, _)| *k == key) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key, value)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum Instr {
-    /// Push a constant onto the stack
-    Const(Value),
+    /// Push `consts[idx]` onto the stack
+    LoadConst(usize),
 
     /// Read a line from stdin (text input), push as Value::Str
     ReadLine,
 
-    /// Pop and print N values (simple version: prints with spaces)
+    /// Pop and print N values separated by spaces, then a newline
     Print(usize),
 
     /// Arithmetic (expects Int, Int)
@@ -56,6 +119,112 @@ pub enum Instr {
 
     /// Halt program
     Halt,
+
+    /// Pop N values and push them as one List (first popped is last item)
+    MakeList(usize),
+
+    /// Pop an Int index (a key for a Map) and a List, String or Map; push the
+    /// item, Char or value found there
+    Index,
+
+    /// Pop a List, Map or String; push its length (items, entries or
+    /// characters) as Int
+    Len,
+
+    /// Pop a value and a List, push the List with the value appended
+    ListPush,
+
+    /// Pop N key/value pairs (pushed key first) and push them as one Map
+    MakeMap(usize),
+
+    /// Pop a value, a key/index and a List or Map; push the updated container
+    SetIndex,
+
+    /// Pop a key and a Map, push whether the key is present
+    Has,
+
+    /// Pop a Map, push a List of its keys in insertion order
+    Keys,
+
+    /// Pop N (field name, value) pairs and push a Record whose type name is
+    /// `consts[name]`: MakeRecord(name, n)
+    MakeRecord(usize, usize),
+
+    /// Pop a Record, push the field named by `consts[idx]`
+    GetField(usize),
+
+    /// Pop a value and a Record, push the Record with field `consts[idx]` replaced
+    SetField(usize),
+
+    /// Pop N values and push them as the variant named `consts[name]` with
+    /// the given tag: MakeVariant(name, tag, n)
+    MakeVariant(usize, usize, usize),
+
+    /// Pop a Variant and skip `tag` instructions: it must be followed by a
+    /// table of N `Jump`s, one per variant of the Enum
+    Switch(usize),
+
+    /// Pop a Variant, push its value at position idx
+    Payload(usize),
+
+    /// Arithmetic (expects Float, Float)
+    AddFloat,
+
+    /// Ordering comparisons on Floats (pop right, pop left, push Bool)
+    CmpLtFloat,
+    CmpLeFloat,
+    CmpGtFloat,
+    CmpGeFloat,
+
+    /// Pop an Int or Float, push it as a Float
+    ToFloat,
+
+    /// Pop an Int or Float, push it as an Int (dropping any fraction)
+    ToInt,
+
+    /// Pop two Strings, push them joined
+    Concat,
+
+    /// Pop a String, push it in upper / lower case
+    Upper,
+    Lower,
+
+    /// Pop a String, push it without leading and trailing whitespace
+    Trim,
+
+    /// Pop a separator and a String, push the List of pieces between separators
+    Split,
+
+    /// Pop a part and a String, push whether the String contains the part
+    Contains,
+
+    /// Pop an end and a start position and a String, push the characters
+    /// from start up to (not including) end
+    Substring,
+
+    /// Call the function starting at the given index with N arguments on
+    /// the stack: Call(target, n). The target must be an `Enter`.
+    Call(usize, usize),
+
+    /// First instruction of a function: give it fresh local slots
+    /// first..first+n, keeping the caller's values until `Ret`: Enter(first, n)
+    Enter(usize, usize),
+
+    /// Pop the result, restore the caller's locals and go back after its `Call`
+    Ret,
+
+    /// Pop a value and drop it
+    Pop,
+
+    /// Like `Print`, without the newline at the end
+    Write(usize),
+
+    /// Pop N values and a separator String, print the values joined by it
+    PrintSep(usize),
+
+    /// Pop N values and a template String, push the template with each `{}`
+    /// replaced by the next value
+    Format(usize),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,37 +233,153 @@ pub struct Chunk {
     pub code: Vec<Instr>,
     pub consts: Vec<Value>,
     pub locals: Vec<String>,
+    /// 1-based source line for each entry in `code` (0 = no source line).
+    pub lines: Vec<u32>,
+    /// Source file of each entry in `code`: an index into `file_names`.
+    pub files: Vec<u32>,
+    /// Files the program was linked from, `file_names[0]` being the one that
+    /// was run. Empty when they aren't known (e.g. hand-written assembly).
+    pub file_names: Vec<String>,
 }
 
+/// Where a bytecode file came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BytecodeMeta {
+    /// Version of the `a` tool that compiled it.
+    pub compiler_version: String,
+    /// Source file name as given on the command line.
+    pub source_name: Option<String>,
+    /// Full source text, if the build asked to embed it.
+    pub source: Option<String>,
+}
+
+impl BytecodeMeta {
+    pub fn new(source_name: Option<String>, source: Option<String>) -> Self {
+        Self {
+            compiler_version: env!("CARGO_PKG_VERSION").to_string(),
+            source_name,
+            source,
+        }
+    }
+}
+
+/// Payload of a `.a.byte` file.
+///
+/// On disk: `BYTECODE_MAGIC`, then the format version (u32 LE), then a CRC-32
+/// (u32 LE) over the version bytes and the payload, then the bincode payload.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BytecodeFile {
-    pub version: u32,
+    pub meta: BytecodeMeta,
     pub chunk: Chunk,
 }
 
-impl BytecodeFile {
-    pub fn new(chunk: Chunk) -> Self {
-        Self {
-            version: BYTECODE_VERSION,
-            chunk,
+const HEADER_LEN: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Doesn't start with the magic bytes at all.
+    NotBytecode,
+    /// Written by a newer A than this one.
+    TooNew { version: u32 },
+    /// Header is fine but the contents are damaged.
+    Corrupted(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::NotBytecode => write!(
+                f,
+                "This is not an A bytecode file. Bytecode files are made by `a build file.a`; \
+                 to run source code, pass the `.a` file instead."
+            ),
+            DecodeError::TooNew { version } => write!(
+                f,
+                "This bytecode was built by a newer version of A (format v{}, this A reads up to v{}). \
+                 Update with `a update`, or rebuild it from source with this version.",
+                version, BYTECODE_VERSION
+            ),
+            DecodeError::Corrupted(detail) => write!(
+                f,
+                "This bytecode file is corrupted ({}). Rebuild it from source with `a build`.",
+                detail
+            ),
         }
     }
 }
 
+pub fn encode_file(file: &BytecodeFile) -> Result<Vec<u8>, String> {
+    let payload = bincode::serialize(file).map_err(|e| e.to_string())?;
+    let version = BYTECODE_VERSION.to_le_bytes();
+
+    let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+    out.extend_from_slice(BYTECODE_MAGIC);
+    out.extend_from_slice(&version);
+    out.extend_from_slice(&crc32(&[&version, &payload]).to_le_bytes());
+    out.extend_from_slice(&payload);
+    Ok(out)
+}
+
+pub fn decode_file(bytes: &[u8]) -> Result<BytecodeFile, DecodeError> {
+    if !bytes.starts_with(BYTECODE_MAGIC) {
+        // Formats before v4 had no magic and started with the version number.
+        // Plenty of other files start like that too, so one that doesn't
+        // decode as an old format isn't bytecode at all.
+        if let Some(v) = bytes.get(..4) {
+            let version = u32::from_le_bytes([v[0], v[1], v[2], v[3]]);
+            if (legacy::OLDEST_SUPPORTED..4).contains(&version) {
+                return legacy::decode(version, bytes).map_err(|_| DecodeError::NotBytecode);
+            }
+        }
+        return Err(DecodeError::NotBytecode);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(DecodeError::Corrupted("the file is cut short".to_string()));
+    }
+
+    let version_bytes = &bytes[4..8];
+    let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let stored_crc = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    let payload = &bytes[HEADER_LEN..];
+
+    if crc32(&[version_bytes, payload]) != stored_crc {
+        return Err(DecodeError::Corrupted("checksum mismatch".to_string()));
+    }
+    if version > BYTECODE_VERSION {
+        return Err(DecodeError::TooNew { version });
+    }
+    if !SAME_LAYOUT.contains(&version) {
+        return legacy::decode_container(version, payload).map_err(DecodeError::Corrupted);
+    }
+    bincode::deserialize(payload).map_err(|e| DecodeError::Corrupted(e.to_string()))
+}
+
+#[allow(dead_code)]
 pub fn encode_chunk(chunk: &Chunk) -> Result<Vec<u8>, String> {
-    let file = BytecodeFile::new(chunk.clone());
-    bincode::serialize(&file).map_err(|e| e.to_string())
+    encode_file(&BytecodeFile {
+        meta: BytecodeMeta::new(None, None),
+        chunk: chunk.clone(),
+    })
 }
 
-pub fn decode_chunk(bytes: &[u8]) -> Result<Chunk, String> {
-    let file: BytecodeFile = bincode::deserialize(bytes).map_err(|e| e.to_string())?;
-    if file.version != BYTECODE_VERSION {
-        return Err(format!(
-            "Unsupported bytecode version {} (expected {})",
-            file.version, BYTECODE_VERSION
-        ));
+#[allow(dead_code)]
+pub fn decode_chunk(bytes: &[u8]) -> Result<Chunk, DecodeError> {
+    decode_file(bytes).map(|f| f.chunk)
+}
+
+/// CRC-32 (IEEE) over several byte slices as if they were one.
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for part in parts {
+        for &byte in *part {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
     }
-    Ok(file.chunk)
+    !crc
 }
 
 impl Chunk {
@@ -103,15 +388,43 @@ impl Chunk {
             code: Vec::new(),
             consts: Vec::new(),
             locals: Vec::new(),
+            lines: Vec::new(),
+            files: Vec::new(),
+            file_names: Vec::new(),
         }
     }
 
-    pub fn push(&mut self, i: Instr) {
+    pub fn push(&mut self, i: Instr, line: u32) {
+        self.push_in(i, 0, line);
+    }
+
+    /// Push an instruction that comes from line `line` of file `file`.
+    pub fn push_in(&mut self, i: Instr, file: u32, line: u32) {
         self.code.push(i);
+        self.files.push(file);
+        self.lines.push(line);
+    }
+
+    /// Source line of the instruction at `ip`, if it has one.
+    pub fn line_at(&self, ip: usize) -> Option<u32> {
+        self.lines.get(ip).copied().filter(|&l| l > 0)
     }
 
-    #[allow(dead_code)]
+    /// Index into `file_names` of the file the instruction at `ip` comes from.
+    pub fn file_at(&self, ip: usize) -> u32 {
+        self.files.get(ip).copied().unwrap_or(0)
+    }
+
+    /// Name of file `file`, if known.
+    pub fn file_name(&self, file: u32) -> Option<&str> {
+        self.file_names.get(file as usize).map(String::as_str)
+    }
+
+    /// Intern a constant, reusing the slot of an identical one already in the pool.
     pub fn add_const(&mut self, v: Value) -> usize {
+        if let Some(i) = self.consts.iter().position(|c| identical(c, &v)) {
+            return i;
+        }
         self.consts.push(v);
         self.consts.len() - 1
     }
@@ -125,3 +438,87 @@ impl Chunk {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> BytecodeFile {
+        let mut chunk = Chunk::new();
+        let hi = chunk.add_const(Value::Str("hi".to_string()));
+        chunk.push(Instr::LoadConst(hi), 1);
+        chunk.push(Instr::Print(1), 1);
+        chunk.push(Instr::Halt, 0);
+        BytecodeFile {
+            meta: BytecodeMeta::new(Some("hi.a".to_string()), None),
+            chunk,
+        }
+    }
+
+    /// `bytes` claiming to be format `version`, with a checksum to match.
+    fn with_version(mut bytes: Vec<u8>, version: u32) -> Vec<u8> {
+        bytes[4..8].copy_from_slice(&version.to_le_bytes());
+        let crc = crc32(&[&bytes[4..8], &bytes[HEADER_LEN..]]);
+        bytes[8..12].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn encoded_files_decode_to_the_same_chunk() {
+        let file = sample();
+        let decoded = decode_file(&encode_file(&file).unwrap()).unwrap();
+        assert_eq!(decoded.chunk.code.len(), file.chunk.code.len());
+        assert_eq!(decoded.chunk.consts, file.chunk.consts);
+        assert_eq!(decoded.chunk.lines, file.chunk.lines);
+        assert_eq!(decoded.meta.source_name.as_deref(), Some("hi.a"));
+    }
+
+    #[test]
+    fn newer_formats_are_reported_as_too_new() {
+        let bytes = with_version(encode_file(&sample()).unwrap(), BYTECODE_VERSION + 1);
+        assert_eq!(
+            decode_file(&bytes).unwrap_err(),
+            DecodeError::TooNew { version: BYTECODE_VERSION + 1 }
+        );
+    }
+
+    #[test]
+    fn damaged_files_are_reported_as_corrupted() {
+        let mut bytes = encode_file(&sample()).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(decode_file(&bytes), Err(DecodeError::Corrupted(_))));
+        assert_eq!(decode_file(b"Func main() {}").unwrap_err(), DecodeError::NotBytecode);
+    }
+
+    #[test]
+    fn files_that_only_start_like_old_formats_are_not_bytecode() {
+        // A v2 header (the version, LE) with text after it.
+        let bytes = [&2u32.to_le_bytes()[..], b"some text"].concat();
+        assert_eq!(decode_file(&bytes).unwrap_err(), DecodeError::NotBytecode);
+    }
+
+    #[test]
+    fn v4_files_still_decode() {
+        let bytes = include_bytes!("../examples/legacy/legacy_program.v4.a.byte");
+        let file = decode_file(bytes).unwrap();
+        assert_eq!(file.chunk.locals, ["greeting", "x"]);
+        assert!(file.chunk.consts.contains(&Value::Str("Hello".to_string())));
+    }
+
+    #[test]
+    fn constants_are_shared_only_when_identical() {
+        let mut chunk = Chunk::new();
+        let zero = chunk.add_const(Value::Float(0.0));
+        assert_ne!(chunk.add_const(Value::Float(-0.0)), zero);
+        assert_eq!(chunk.add_const(Value::Float(0.0)), zero);
+        let nan = chunk.add_const(Value::Float(f64::NAN));
+        assert_eq!(chunk.add_const(Value::Float(f64::NAN)), nan);
+        let hi = chunk.add_const(Value::Str("hi".to_string()));
+        assert_eq!(chunk.add_const(Value::Str("hi".to_string())), hi);
+        assert_ne!(chunk.add_const(Value::Int(0)), zero);
+        let list = chunk.add_const(Value::List(vec![Value::Float(0.0)]));
+        assert_ne!(chunk.add_const(Value::List(vec![Value::Float(-0.0)])), list);
+        assert_eq!(chunk.consts.len(), 7);
+    }
+}
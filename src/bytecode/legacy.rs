@@ -0,0 +1,323 @@
+// src/bytecode/legacy.rs
+//
+// Frozen layouts of the older bytecode formats: the ones before the `A\0BC`
+// container (v1-v3) and older container payloads (v4-v5), and how to upgrade
+// each one to the current Chunk.
+//
+// Never edit these types: they describe files that already exist on people's
+// disks. When the current format changes incompatibly, freeze it here as the
+// next `vN` module and add one more upgrade step.
+
+use serde::Deserialize;
+
+use super::{BytecodeFile, BytecodeMeta, Chunk, Instr, Value};
+
+/// Oldest format version this A can still read.
+pub const OLDEST_SUPPORTED: u32 = 1;
+
+/// Decode a pre-container file whose leading u32 is `version` (1..=3).
+pub fn decode(version: u32, bytes: &[u8]) -> Result<BytecodeFile, String> {
+    let chunk = match version {
+        1 => upgrade_v2(upgrade_v1(decode_file::<v1::File>(bytes)?.chunk)),
+        2 => upgrade_v2(decode_file::<v2::File>(bytes)?.chunk),
+        3 => upgrade_v3(decode_file::<v3::File>(bytes)?.chunk),
+        other => return Err(format!("unknown bytecode version {}", other)),
+    };
+
+    Ok(BytecodeFile {
+        meta: BytecodeMeta {
+            compiler_version: format!("unknown (format v{})", version),
+            source_name: None,
+            source: None,
+        },
+        chunk,
+    })
+}
+
+/// Decode the payload of a container file older than the current format.
+pub fn decode_container(version: u32, payload: &[u8]) -> Result<BytecodeFile, String> {
+    match version {
+        4 | 5 => {
+            let file = decode_file::<v5::File>(payload)?;
+            Ok(BytecodeFile {
+                meta: file.meta,
+                chunk: upgrade_v5(file.chunk),
+            })
+        }
+        other => Err(format!("unknown bytecode version {}", other)),
+    }
+}
+
+fn decode_file<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, String> {
+    bincode::deserialize(bytes).map_err(|e| e.to_string())
+}
+
+/* =========================
+   Frozen formats
+   ========================= */
+
+/// Value layout shared by v1-v3.
+#[derive(Deserialize)]
+enum ValueV1 {
+    Int(i64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Unit,
+}
+
+impl From<ValueV1> for Value {
+    fn from(v: ValueV1) -> Self {
+        match v {
+            ValueV1::Int(i) => Value::Int(i),
+            ValueV1::Bool(b) => Value::Bool(b),
+            ValueV1::Char(c) => Value::Char(c),
+            ValueV1::Str(s) => Value::Str(s),
+            ValueV1::Unit => Value::Unit,
+        }
+    }
+}
+
+/// v1: the first format; constants were stored inline in `Const`.
+mod v1 {
+    use super::ValueV1;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub enum Instr {
+        Const(ValueV1),
+        ReadLine,
+        Print(usize),
+        AddInt,
+        LoadLocal(usize),
+        StoreLocal(usize),
+        Jump(usize),
+        JumpIfFalse(usize),
+        CmpEq,
+        CmpNe,
+        CmpLt,
+        CmpLe,
+        CmpGt,
+        CmpGe,
+        Halt,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Chunk {
+        pub code: Vec<Instr>,
+        #[allow(dead_code)]
+        pub consts: Vec<ValueV1>,
+        pub locals: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct File {
+        #[allow(dead_code)]
+        pub version: u32,
+        pub chunk: Chunk,
+    }
+}
+
+/// v2: v1 plus a source line per instruction.
+mod v2 {
+    use serde::Deserialize;
+
+    pub use super::v1::Instr;
+    use super::ValueV1;
+
+    #[derive(Deserialize)]
+    pub struct Chunk {
+        pub code: Vec<Instr>,
+        #[allow(dead_code)]
+        pub consts: Vec<ValueV1>,
+        pub locals: Vec<String>,
+        pub lines: Vec<u32>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct File {
+        #[allow(dead_code)]
+        pub version: u32,
+        pub chunk: Chunk,
+    }
+}
+
+/// v3: constants moved into the pool (`Const(Value)` became `LoadConst(idx)`).
+mod v3 {
+    use super::ValueV1;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub enum Instr {
+        LoadConst(usize),
+        ReadLine,
+        Print(usize),
+        AddInt,
+        LoadLocal(usize),
+        StoreLocal(usize),
+        Jump(usize),
+        JumpIfFalse(usize),
+        CmpEq,
+        CmpNe,
+        CmpLt,
+        CmpLe,
+        CmpGt,
+        CmpGe,
+        Halt,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Chunk {
+        pub code: Vec<Instr>,
+        pub consts: Vec<ValueV1>,
+        pub locals: Vec<String>,
+        pub lines: Vec<u32>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct File {
+        #[allow(dead_code)]
+        pub version: u32,
+        pub chunk: Chunk,
+    }
+}
+
+/// v4-v5: the `A\0BC` container, before instructions recorded their file.
+/// v5 only added `Instr`/`Value` variants at the end, so both still decode
+/// with the current types.
+mod v5 {
+    use crate::bytecode::{BytecodeMeta, Instr, Value};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub struct Chunk {
+        pub code: Vec<Instr>,
+        pub consts: Vec<Value>,
+        pub locals: Vec<String>,
+        pub lines: Vec<u32>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct File {
+        pub meta: BytecodeMeta,
+        pub chunk: Chunk,
+    }
+}
+
+/* =========================
+   Upgrades (one step each)
+   ========================= */
+
+fn upgrade_v1(c: v1::Chunk) -> v2::Chunk {
+    // v1 had no line table; 0 means "no source line".
+    let lines = vec![0; c.code.len()];
+    v2::Chunk {
+        code: c.code,
+        consts: c.consts,
+        locals: c.locals,
+        lines,
+    }
+}
+
+fn upgrade_v2(c: v2::Chunk) -> Chunk {
+    // v2 -> v3 moved inline constants into the pool; v3 -> v4 only changed
+    // the file container, so this lands directly on the current Chunk.
+    // (The pool existed before v3 but nothing ever filled it.)
+    let mut chunk = Chunk::new();
+    chunk.locals = c.locals;
+    for (ip, instr) in c.code.into_iter().enumerate() {
+        let line = c.lines.get(ip).copied().unwrap_or(0);
+        let instr = match instr {
+            v1::Instr::Const(v) => Instr::LoadConst(chunk.add_const(v.into())),
+            v1::Instr::ReadLine => Instr::ReadLine,
+            v1::Instr::Print(n) => Instr::Print(n),
+            v1::Instr::AddInt => Instr::AddInt,
+            v1::Instr::LoadLocal(i) => Instr::LoadLocal(i),
+            v1::Instr::StoreLocal(i) => Instr::StoreLocal(i),
+            v1::Instr::Jump(t) => Instr::Jump(t),
+            v1::Instr::JumpIfFalse(t) => Instr::JumpIfFalse(t),
+            v1::Instr::CmpEq => Instr::CmpEq,
+            v1::Instr::CmpNe => Instr::CmpNe,
+            v1::Instr::CmpLt => Instr::CmpLt,
+            v1::Instr::CmpLe => Instr::CmpLe,
+            v1::Instr::CmpGt => Instr::CmpGt,
+            v1::Instr::CmpGe => Instr::CmpGe,
+            v1::Instr::Halt => Instr::Halt,
+        };
+        chunk.push(instr, line);
+    }
+    chunk
+}
+
+fn upgrade_v3(c: v3::Chunk) -> Chunk {
+    let mut chunk = Chunk::new();
+    chunk.consts = c.consts.into_iter().map(Value::from).collect();
+    chunk.locals = c.locals;
+    for (ip, instr) in c.code.into_iter().enumerate() {
+        let line = c.lines.get(ip).copied().unwrap_or(0);
+        let instr = match instr {
+            v3::Instr::LoadConst(i) => Instr::LoadConst(i),
+            v3::Instr::ReadLine => Instr::ReadLine,
+            v3::Instr::Print(n) => Instr::Print(n),
+            v3::Instr::AddInt => Instr::AddInt,
+            v3::Instr::LoadLocal(i) => Instr::LoadLocal(i),
+            v3::Instr::StoreLocal(i) => Instr::StoreLocal(i),
+            v3::Instr::Jump(t) => Instr::Jump(t),
+            v3::Instr::JumpIfFalse(t) => Instr::JumpIfFalse(t),
+            v3::Instr::CmpEq => Instr::CmpEq,
+            v3::Instr::CmpNe => Instr::CmpNe,
+            v3::Instr::CmpLt => Instr::CmpLt,
+            v3::Instr::CmpLe => Instr::CmpLe,
+            v3::Instr::CmpGt => Instr::CmpGt,
+            v3::Instr::CmpGe => Instr::CmpGe,
+            v3::Instr::Halt => Instr::Halt,
+        };
+        chunk.push(instr, line);
+    }
+    chunk
+}
+
+fn upgrade_v5(c: v5::Chunk) -> Chunk {
+    // Every instruction is from the one, unnamed, file.
+    let files = vec![0; c.code.len()];
+    Chunk {
+        code: c.code,
+        consts: c.consts,
+        locals: c.locals,
+        lines: c.lines,
+        files,
+        file_names: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::decode_file;
+    use crate::verify::verify_chunk;
+    use crate::vm::Vm;
+
+    /// examples/legacy/legacy_program.a, as built by each old format.
+    const FIXTURES: [(u32, &[u8]); 3] = [
+        (1, include_bytes!("../../examples/legacy/legacy_program.v1.a.byte")),
+        (2, include_bytes!("../../examples/legacy/legacy_program.v2.a.byte")),
+        (3, include_bytes!("../../examples/legacy/legacy_program.v3.a.byte")),
+    ];
+
+    #[test]
+    fn old_formats_still_load_and_run() {
+        for (version, bytes) in FIXTURES {
+            let file = decode_file(bytes).unwrap_or_else(|e| panic!("v{}: {:?}", version, e));
+            assert_eq!(file.meta.compiler_version, format!("unknown (format v{})", version));
+            let chunk = file.chunk;
+            assert_eq!(verify_chunk(&chunk), Ok(()), "v{}", version);
+            assert_eq!(chunk.locals, ["greeting", "x"], "v{}", version);
+
+            let mut vm = Vm::new();
+            vm.capture_output();
+            vm.run(&chunk).unwrap_or_else(|e| panic!("v{}: {}", version, e));
+            assert_eq!(vm.take_output(), "Hello three 3\ndone\n", "v{}", version);
+            assert_eq!(vm.locals(), [Value::Str("Hello".to_string()), Value::Int(3)], "v{}", version);
+        }
+    }
+}
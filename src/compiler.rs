@@ -2,184 +2,819 @@
 //
 // Compile A AST -> bytecode::Chunk
 // (Right now: enough to run tiny programs with Int/String/Char/Bool literals,
-// addition on ints, and Print(...). Extend as you grow A.)
+// lists, addition on ints, If/For, functions and Print(...). Extend as you grow A.)
+//
+// Layout: main's code, `Halt`, then one function after another. Each file is
+// compiled on its own; `link` joins the files of a program into one Chunk.
+
+use std::collections::{HashMap, HashSet};
 
-use crate::ast::{Expr, Program, Stmt};
-use crate::bytecode::{Chunk, Instr, Value};
+use crate::analysis::AType;
+use crate::builtins::{self, Builtin, Native, Param, Ret};
+use crate::ast::{stmt_span, Expr, FuncDecl, Program, Span, Stmt};
+use crate::bytecode::{map_insert, Chunk, Instr, Value};
 
-pub fn compile_to_bytecode(program: &Program) -> Result<Chunk, String> {
-    let mut chunk = Chunk::new();
+/// One compiled file. Calls into imported files are left for `link` to fill in.
+pub struct CompiledModule {
+    pub chunk: Chunk,
+    /// Where each of the file's functions starts.
+    pub funcs: HashMap<String, usize>,
+    /// `Call`s to `module.function`, by instruction index.
+    pub imported_calls: Vec<(usize, String)>,
+}
 
-    for stmt in &program.stmts {
-        compile_stmt(stmt, &mut chunk)?;
+/// `op_types`: operand types of the `+`/comparison expressions that work on
+/// Floats or Strings (`AnalysisResult::op_types`). `run_main`: compile
+/// `main` (only the file being run needs it). `prelude`: names of the
+/// prelude's functions, which calls reach through `link`.
+pub fn compile_to_bytecode(
+    program: &Program,
+    src: &str,
+    op_types: &HashMap<Span, AType>,
+    run_main: bool,
+    prelude: &[String],
+) -> Result<CompiledModule, String> {
+    let mut c = Compiler::new(src);
+    c.op_types = op_types.clone();
+    c.prelude = prelude.to_vec();
+    c.func_names = program.funcs.iter().map(|f| f.name.clone()).collect();
+    for decl in &program.structs {
+        let fields = decl.fields.iter().map(|(f, _, _)| f.clone()).collect();
+        c.structs.insert(decl.name.clone(), fields);
+    }
+    for decl in &program.enums {
+        for (tag, (name, payload, _)) in decl.variants.iter().enumerate() {
+            c.variants
+                .insert(name.clone(), (decl.name.clone(), tag, payload.len()));
+        }
+        let names = decl.variants.iter().map(|(v, _, _)| v.clone()).collect();
+        c.enums.insert(decl.name.clone(), names);
     }
 
-    chunk.push(Instr::Halt);
-    Ok(chunk)
-}
+    if run_main {
+        for stmt in &program.stmts {
+            c.compile_stmt(stmt)?;
+        }
 
-fn compile_stmt(stmt: &Stmt, chunk: &mut Chunk) -> Result<(), String> {
-    match stmt {
-        Stmt::Let { name, expr, .. } | Stmt::Mute { name, expr, .. } => {
-            // compile RHS then store into a new local slot
-            compile_expr(expr, chunk)?;
-            let slot = chunk.ensure_local(name);
-            chunk.push(Instr::StoreLocal(slot));
-            Ok(())
-        }
-        Stmt::Assign { name, expr, .. } => {
-            // compile RHS then store into existing (or new) local slot
-            compile_expr(expr, chunk)?;
-            let slot = chunk.ensure_local(name);
-            chunk.push(Instr::StoreLocal(slot));
-            Ok(())
-        }
-        
-        Stmt::Expr(e) => {
-            // Special-case Print(...) at bytecode level
-            if let Expr::Call(name, args, _) = e {
-                if name.eq_ignore_ascii_case("print") {
-                    for a in args {
-                        compile_expr(a, chunk)?;
-                    }
-                    chunk.push(Instr::Print(args.len()));
-                    return Ok(());
+        // Halt doesn't belong to any statement.
+        c.line = 0;
+        c.emit(Instr::Halt);
+    }
+
+    for f in &program.funcs {
+        c.compile_func(f)?;
+    }
+
+    let mut imported_calls = Vec::new();
+    for (pos, name) in std::mem::take(&mut c.calls) {
+        match c.funcs.get(&name) {
+            Some(&target) => {
+                if let Instr::Call(_, n) = c.chunk.code[pos] {
+                    c.chunk.code[pos] = Instr::Call(target, n);
                 }
             }
+            None => imported_calls.push((pos, name)),
+        }
+    }
+
+    Ok(CompiledModule {
+        chunk: c.chunk,
+        funcs: c.funcs,
+        imported_calls,
+    })
+}
+
+struct Compiler {
+    chunk: Chunk,
+    /// Byte offset where each source line starts (for the line table).
+    line_starts: Vec<usize>,
+    /// Line recorded for instructions emitted right now.
+    line: u32,
+    /// Number of For loops so far (names their hidden locals).
+    loops: usize,
+    /// Field names of each struct, in declaration order.
+    structs: HashMap<String, Vec<String>>,
+    /// Variant names of each enum, in declaration order (the order is the tag).
+    enums: HashMap<String, Vec<String>>,
+    /// Enum, tag and payload size of each variant.
+    variants: HashMap<String, (String, usize, usize)>,
+    /// Number of Matches so far (names their hidden locals).
+    matches: usize,
+    /// Operand types of `+`/comparison expressions that don't work on Ints.
+    op_types: HashMap<Span, AType>,
+    /// Functions declared in this file.
+    func_names: HashSet<String>,
+    /// Functions of the prelude.
+    prelude: Vec<String>,
+    /// Function being compiled (its locals are named `function.name`).
+    scope: Option<String>,
+    /// Where each compiled function starts.
+    funcs: HashMap<String, usize>,
+    /// `Call` placeholders and the function they call.
+    calls: Vec<(usize, String)>,
+}
+
+impl Compiler {
+    fn new(src: &str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(src.match_indices('\n').map(|(i, _)| i + 1));
+        Self {
+            chunk: Chunk::new(),
+            line_starts,
+            line: 0,
+            loops: 0,
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            variants: HashMap::new(),
+            matches: 0,
+            op_types: HashMap::new(),
+            func_names: HashSet::new(),
+            prelude: Vec::new(),
+            scope: None,
+            funcs: HashMap::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, i: Instr) {
+        self.chunk.push(i, self.line);
+    }
+
+    fn emit_const(&mut self, v: Value) {
+        let idx = self.chunk.add_const(v);
+        self.emit(Instr::LoadConst(idx));
+    }
+
+    /// Constant-pool slot holding a field or type name.
+    fn name_const(&mut self, name: &str) -> usize {
+        self.chunk.add_const(Value::Str(name.to_string()))
+    }
+
+    /// Slot name of a variable: a function's locals are kept apart from main's.
+    fn local_name(&self, name: &str) -> String {
+        match &self.scope {
+            Some(f) => format!("{}.{}", f, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn ensure_local(&mut self, name: &str) -> usize {
+        let name = self.local_name(name);
+        self.chunk.ensure_local(&name)
+    }
+
+    fn local_slot(&self, name: &str) -> Result<usize, String> {
+        let scoped = self.local_name(name);
+        self.chunk
+            .locals
+            .iter()
+            .position(|n| *n == scoped)
+            .ok_or_else(|| format!("Bytecode compiler: unknown variable `{}`", name))
+    }
+
+    /// Placeholder jump past the remaining If branches. It gets no source
+    /// line so a debugger step from the end of a branch doesn't stop on the
+    /// `If` line again.
+    fn emit_end_jump(&mut self) -> usize {
+        let pos = self.chunk.code.len();
+        self.chunk.push(Instr::Jump(0), 0);
+        pos
+    }
+
+    /// A variant name that isn't shadowed by a variable.
+    fn variant(&self, name: &str) -> Option<(usize, usize)> {
+        if self.local_slot(name).is_ok() {
+            return None;
+        }
+        self.variants.get(name).map(|(_, tag, n)| (*tag, *n))
+    }
+
+    fn line_of(&self, offset: usize) -> u32 {
+        match self.line_starts.binary_search(&offset) {
+            Ok(i) => i as u32 + 1,
+            Err(i) => i as u32,
+        }
+    }
+
+    /// `Enter`, then the arguments (on the stack, last one on top) go into
+    /// the parameters, then the body.
+    fn compile_func(&mut self, f: &FuncDecl) -> Result<(), String> {
+        // The prologue has no source line, so stepping into a call stops
+        // on the first statement of the body.
+        self.line = 0;
+        self.funcs.insert(f.name.clone(), self.chunk.code.len());
+        self.scope = Some(f.name.clone());
 
-            // Otherwise compile expression (no side effects yet)
-            compile_expr(e, chunk)?;
-            Ok(())
+        let first = self.chunk.locals.len();
+        let enter = self.chunk.code.len();
+        self.emit(Instr::Enter(first, 0)); // patched once all locals are known
+        let slots: Vec<usize> = f.params.iter().map(|(p, _, _)| self.ensure_local(p)).collect();
+        for &slot in slots.iter().rev() {
+            self.emit(Instr::StoreLocal(slot));
         }
-        Stmt::If { first, elseifs, else_body, .. } => {
-            // Lower If/ElseIf/Else into conditional jumps.
-            // Strategy:
-            // 1. Compile condition, emit JumpIfFalse to else/next placeholder.
-            // 2. Compile body, emit Jump to after-if placeholder.
-            // 3. Patch placeholders to point at correct targets.
 
-            // compile first condition
-            compile_expr(&first.cond, chunk)?;
-            let jf_pos = chunk.code.len();
-            chunk.push(Instr::JumpIfFalse(0)); // placeholder
+        for s in &f.body {
+            self.compile_stmt(s)?;
+        }
+
+        // Running off the end gives back nothing.
+        self.emit_const(Value::Unit);
+        self.emit(Instr::Ret);
+
+        self.chunk.code[enter] = Instr::Enter(first, self.chunk.locals.len() - first);
+        self.scope = None;
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+        // Instructions belong to the line the statement starts on; nested
+        // statements set their own line and we restore ours afterwards.
+        let outer_line = self.line;
+        self.line = self.line_of(stmt_span(stmt).start);
+        let res = self.compile_stmt_inner(stmt);
+        self.line = outer_line;
+        res
+    }
 
-            // compile first body
-            for s in &first.body {
-                compile_stmt(s, chunk)?;
+    fn compile_stmt_inner(&mut self, stmt: &Stmt) -> Result<(), String> {
+        match stmt {
+            Stmt::Let { name, expr, .. } | Stmt::Mute { name, expr, .. } => {
+                // compile RHS then store into a new local slot
+                self.compile_expr(expr)?;
+                let slot = self.ensure_local(name);
+                self.emit(Instr::StoreLocal(slot));
+                Ok(())
+            }
+            Stmt::Assign { name, expr, .. } => {
+                // compile RHS then store into existing (or new) local slot
+                self.compile_expr(expr)?;
+                let slot = self.ensure_local(name);
+                self.emit(Instr::StoreLocal(slot));
+                Ok(())
             }
 
-            // after first body, jump to end
-            let after_jmp_pos = chunk.code.len();
-            chunk.push(Instr::Jump(0)); // placeholder to jump past remaining branches
+            Stmt::SetIndex {
+                name, index, expr, ..
+            } => {
+                // Like Push: load the container, update it, store it back.
+                let slot = self.local_slot(name)?;
+                self.emit(Instr::LoadLocal(slot));
+                self.compile_expr(index)?;
+                self.compile_expr(expr)?;
+                self.emit(Instr::SetIndex);
+                self.emit(Instr::StoreLocal(slot));
+                Ok(())
+            }
 
-            // patch first JumpIfFalse to point at current position (start of next branch)
-            let next_branch_start = chunk.code.len();
-            chunk.code[jf_pos] = Instr::JumpIfFalse(next_branch_start);
+            Stmt::SetField {
+                name, field, expr, ..
+            } => {
+                let slot = self.local_slot(name)?;
+                self.emit(Instr::LoadLocal(slot));
+                self.compile_expr(expr)?;
+                let field = self.name_const(field);
+                self.emit(Instr::SetField(field));
+                self.emit(Instr::StoreLocal(slot));
+                Ok(())
+            }
+
+            Stmt::For {
+                var, iter, body, ..
+            } => {
+                // Lower `For x in xs { body }` into an index loop over a copy
+                // of the list, kept in `$` locals the debugger hides:
+                //
+                //   $list = xs; $i = 0
+                //   top: if !($i < Len($list)) goto end
+                //        x = $list[$i]; body; $i = $i + 1; goto top
+                //   end:
+                self.loops += 1;
+                let list = self.ensure_local(&format!("$list{}", self.loops));
+                let idx = self.ensure_local(&format!("$i{}", self.loops));
+
+                self.compile_expr(iter)?;
+                self.emit(Instr::StoreLocal(list));
+                self.emit_const(Value::Int(0));
+                self.emit(Instr::StoreLocal(idx));
 
-            // collect jumps that should all target the final end
-            let mut end_jumps = vec![after_jmp_pos];
+                let top = self.chunk.code.len();
+                self.emit(Instr::LoadLocal(idx));
+                self.emit(Instr::LoadLocal(list));
+                self.emit(Instr::Len);
+                self.emit(Instr::CmpLt);
+                let exit_pos = self.chunk.code.len();
+                self.emit(Instr::JumpIfFalse(0)); // placeholder
 
-            // compile else-ifs
-            for elseif in elseifs {
-                // compile elseif condition
-                compile_expr(&elseif.cond, chunk)?;
-                let jf_pos = chunk.code.len();
-                chunk.push(Instr::JumpIfFalse(0));
+                let slot = self.ensure_local(var);
+                self.emit(Instr::LoadLocal(list));
+                self.emit(Instr::LoadLocal(idx));
+                self.emit(Instr::Index);
+                self.emit(Instr::StoreLocal(slot));
 
-                // compile elseif body
-                for s in &elseif.body {
-                    compile_stmt(s, chunk)?;
+                for s in body {
+                    self.compile_stmt(s)?;
                 }
 
-                // after elseif body, jump to end
-                let after_jmp_pos = chunk.code.len();
-                chunk.push(Instr::Jump(0));
-                end_jumps.push(after_jmp_pos);
+                // Advancing belongs to no line, so stepping from the end of
+                // the body goes straight back to the `For` line.
+                let for_line = self.line;
+                self.line = 0;
+                self.emit(Instr::LoadLocal(idx));
+                self.emit_const(Value::Int(1));
+                self.emit(Instr::AddInt);
+                self.emit(Instr::StoreLocal(idx));
+                self.emit(Instr::Jump(top));
+                self.line = for_line;
 
-                // patch this elseif's JumpIfFalse to point at next branch start
-                let next_branch_start = chunk.code.len();
-                chunk.code[jf_pos] = Instr::JumpIfFalse(next_branch_start);
+                let end = self.chunk.code.len();
+                self.chunk.code[exit_pos] = Instr::JumpIfFalse(end);
+                Ok(())
             }
 
-            // compile else body if present
-            if let Some(else_stmts) = else_body {
-                for s in else_stmts {
-                    compile_stmt(s, chunk)?;
+            Stmt::Match { expr, arms, .. } => {
+                // Lower Match into a jump table, one entry per variant:
+                //
+                //   $match = s
+                //   Switch(n)          ; skip `tag` entries of the table
+                //   Jump arm_for_tag_0
+                //   ...
+                //   Jump arm_for_tag_n-1
+                //   arm: r = $match value 0; body; goto end
+                //   end:
+                self.matches += 1;
+                let scrutinee = self.ensure_local(&format!("$match{}", self.matches));
+                self.compile_expr(expr)?;
+                self.emit(Instr::StoreLocal(scrutinee));
+
+                let enum_variants = arms
+                    .iter()
+                    .filter_map(|arm| arm.variant.as_ref())
+                    .find_map(|v| self.variants.get(v))
+                    .and_then(|(e, _, _)| self.enums.get(e))
+                    .cloned()
+                    .unwrap_or_default();
+
+                self.emit(Instr::LoadLocal(scrutinee));
+                self.emit(Instr::Switch(enum_variants.len()));
+                let table = self.chunk.code.len();
+                for _ in &enum_variants {
+                    self.emit_end_jump();
+                }
+
+                // Variants no arm names go to `_` (or straight to the end).
+                let mut targets: Vec<Option<usize>> = vec![None; enum_variants.len()];
+                let mut end_jumps = Vec::new();
+                for arm in arms {
+                    let arm_line = self.line;
+                    self.line = self.line_of(arm.span.start);
+                    let start = self.chunk.code.len();
+                    match &arm.variant {
+                        Some(v) => {
+                            let tag = enum_variants
+                                .iter()
+                                .position(|n| n == v)
+                                .ok_or_else(|| format!("Bytecode compiler: unknown variant `{}`", v))?;
+                            targets[tag].get_or_insert(start);
+                            for (i, name) in arm.bindings.iter().enumerate() {
+                                if name == "_" {
+                                    continue;
+                                }
+                                let slot = self.ensure_local(name);
+                                self.emit(Instr::LoadLocal(scrutinee));
+                                self.emit(Instr::Payload(i));
+                                self.emit(Instr::StoreLocal(slot));
+                            }
+                        }
+                        None => {
+                            for t in targets.iter_mut() {
+                                t.get_or_insert(start);
+                            }
+                        }
+                    }
+                    for s in &arm.body {
+                        self.compile_stmt(s)?;
+                    }
+                    end_jumps.push(self.emit_end_jump());
+                    self.line = arm_line;
+                }
+
+                let end = self.chunk.code.len();
+                for (i, target) in targets.into_iter().enumerate() {
+                    self.chunk.code[table + i] = Instr::Jump(target.unwrap_or(end));
+                }
+                for pos in end_jumps {
+                    self.chunk.code[pos] = Instr::Jump(end);
+                }
+                Ok(())
+            }
+
+            Stmt::Expr(e) => {
+                // Compile the expression and drop any value it leaves
+                self.compile_expr(e)?;
+                if leaves_value(e) {
+                    self.emit(Instr::Pop);
+                }
+                Ok(())
+            }
+
+            Stmt::Return { expr, .. } => {
+                // In main, `Return` ends the program.
+                if self.scope.is_none() {
+                    self.emit(Instr::Halt);
+                    return Ok(());
+                }
+                match expr {
+                    Some(e) => self.compile_expr(e)?,
+                    None => self.emit_const(Value::Unit),
+                }
+                self.emit(Instr::Ret);
+                Ok(())
+            }
+            Stmt::If {
+                first,
+                elseifs,
+                else_body,
+                ..
+            } => {
+                // Lower If/ElseIf/Else into conditional jumps.
+                // Strategy:
+                // 1. Compile condition, emit JumpIfFalse to else/next placeholder.
+                // 2. Compile body, emit Jump to after-if placeholder.
+                // 3. Patch placeholders to point at correct targets.
+
+                // compile first condition
+                self.compile_expr(&first.cond)?;
+                let jf_pos = self.chunk.code.len();
+                self.emit(Instr::JumpIfFalse(0)); // placeholder
+
+                // compile first body
+                for s in &first.body {
+                    self.compile_stmt(s)?;
+                }
+
+                // after first body, jump to end
+                let after_jmp_pos = self.emit_end_jump();
+
+                // patch first JumpIfFalse to point at current position (start of next branch)
+                let next_branch_start = self.chunk.code.len();
+                self.chunk.code[jf_pos] = Instr::JumpIfFalse(next_branch_start);
+
+                // collect jumps that should all target the final end
+                let mut end_jumps = vec![after_jmp_pos];
+
+                // compile else-ifs
+                for elseif in elseifs {
+                    // the condition lives on the ElseIf line, not the If line
+                    let if_line = self.line;
+                    self.line = self.line_of(elseif.span.start);
+
+                    // compile elseif condition
+                    self.compile_expr(&elseif.cond)?;
+                    let jf_pos = self.chunk.code.len();
+                    self.emit(Instr::JumpIfFalse(0));
+
+                    // compile elseif body
+                    for s in &elseif.body {
+                        self.compile_stmt(s)?;
+                    }
+
+                    // after elseif body, jump to end
+                    let after_jmp_pos = self.emit_end_jump();
+                    end_jumps.push(after_jmp_pos);
+
+                    // patch this elseif's JumpIfFalse to point at next branch start
+                    let next_branch_start = self.chunk.code.len();
+                    self.chunk.code[jf_pos] = Instr::JumpIfFalse(next_branch_start);
+
+                    self.line = if_line;
                 }
+
+                // compile else body if present
+                if let Some(else_stmts) = else_body {
+                    for s in else_stmts {
+                        self.compile_stmt(s)?;
+                    }
+                }
+
+                // patch all end jumps to point at final end
+                let final_end = self.chunk.code.len();
+                for pos in end_jumps {
+                    self.chunk.code[pos] = Instr::Jump(final_end);
+                }
+
+                Ok(())
             }
+        }
+    }
 
-            // patch all end jumps to point at final end
-            let final_end = chunk.code.len();
-            for pos in end_jumps {
-                chunk.code[pos] = Instr::Jump(final_end);
+    fn compile_expr(&mut self, expr: &Expr) -> Result<(), String> {
+        if matches!(expr, Expr::List(..) | Expr::Map(..)) {
+            if let Some(v) = const_value(expr) {
+                self.emit_const(v);
+                return Ok(());
             }
+        }
 
-            Ok(())
+        match expr {
+            Expr::Int(v, _) => {
+                self.emit_const(Value::Int(*v));
+                Ok(())
+            }
+            Expr::Float(v, _) => {
+                self.emit_const(Value::Float(*v));
+                Ok(())
+            }
+            Expr::Bool(b, _) => {
+                self.emit_const(Value::Bool(*b));
+                Ok(())
+            }
+            Expr::Char(c, _) => {
+                self.emit_const(Value::Char(*c));
+                Ok(())
+            }
+            Expr::Str(s, _) => {
+                self.emit_const(Value::Str(s.clone()));
+                Ok(())
+            }
+            Expr::Var(name, _) if self.variant(name).is_some() => self.compile_variant(name, &[]),
+            Expr::Call(name, args, _) if self.variant(name).is_some() => {
+                self.compile_variant(name, args)
+            }
+            Expr::Var(name, _) => {
+                // load the local slot for this variable
+                // if it doesn't exist yet, that's a compile-time error (should be declared by analyzer)
+                let idx = self.local_slot(name)?;
+                self.emit(Instr::LoadLocal(idx));
+                Ok(())
+            }
+            Expr::Add(a, b, sp) => {
+                self.compile_expr(a)?;
+                self.compile_expr(b)?;
+                match self.op_types.get(sp) {
+                    Some(AType::Float) => self.emit(Instr::AddFloat),
+                    Some(AType::Str) => self.emit(Instr::Concat),
+                    _ => self.emit(Instr::AddInt),
+                }
+                Ok(())
+            }
+            Expr::Cmp(a, op, b, sp) => {
+                self.compile_expr(a)?;
+                self.compile_expr(b)?;
+                // Strings use the Int comparisons, which also order text.
+                let float = self.op_types.get(sp) == Some(&AType::Float);
+                match op {
+                    crate::ast::CmpOp::Eq => self.emit(Instr::CmpEq),
+                    crate::ast::CmpOp::Ne => self.emit(Instr::CmpNe),
+                    crate::ast::CmpOp::Lt if float => self.emit(Instr::CmpLtFloat),
+                    crate::ast::CmpOp::Le if float => self.emit(Instr::CmpLeFloat),
+                    crate::ast::CmpOp::Gt if float => self.emit(Instr::CmpGtFloat),
+                    crate::ast::CmpOp::Ge if float => self.emit(Instr::CmpGeFloat),
+                    crate::ast::CmpOp::Lt => self.emit(Instr::CmpLt),
+                    crate::ast::CmpOp::Le => self.emit(Instr::CmpLe),
+                    crate::ast::CmpOp::Gt => self.emit(Instr::CmpGt),
+                    crate::ast::CmpOp::Ge => self.emit(Instr::CmpGe),
+                }
+                Ok(())
+            }
+            Expr::Call(name, args, _) => {
+                if let Some(b) = builtins::lookup(name) {
+                    self.compile_builtin(b, args)
+                } else if let Some(target) = self.call_target(name) {
+                    for a in args {
+                        self.compile_expr(a)?;
+                    }
+                    self.calls.push((self.chunk.code.len(), target));
+                    self.emit(Instr::Call(0, args.len())); // patched at the end
+                    Ok(())
+                } else {
+                    Err(format!("Bytecode compiler: unknown function `{}`", name))
+                }
+            }
+            Expr::List(items, _) => {
+                for item in items {
+                    self.compile_expr(item)?;
+                }
+                self.emit(Instr::MakeList(items.len()));
+                Ok(())
+            }
+            Expr::Index(list, idx, _) => {
+                self.compile_expr(list)?;
+                self.compile_expr(idx)?;
+                self.emit(Instr::Index);
+                Ok(())
+            }
+            Expr::Map(entries, _) => {
+                for (k, v) in entries {
+                    self.compile_expr(k)?;
+                    self.compile_expr(v)?;
+                }
+                self.emit(Instr::MakeMap(entries.len()));
+                Ok(())
+            }
+            Expr::Record(name, fields, _) => {
+                // Fields are stored in declaration order, whatever order they were written in.
+                let order = self
+                    .structs
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| format!("Bytecode compiler: unknown struct `{}`", name))?;
+                for field in &order {
+                    let (_, value, _) = fields
+                        .iter()
+                        .find(|(f, _, _)| f == field)
+                        .ok_or_else(|| format!("Bytecode compiler: missing field `{}`", field))?;
+                    self.emit_const(Value::Str(field.clone()));
+                    self.compile_expr(value)?;
+                }
+                let name = self.name_const(name);
+                self.emit(Instr::MakeRecord(name, order.len()));
+                Ok(())
+            }
+            Expr::Field(target, field, _) => {
+                self.compile_expr(target)?;
+                let field = self.name_const(field);
+                self.emit(Instr::GetField(field));
+                Ok(())
+            }
         }
     }
-}
 
-fn compile_expr(expr: &Expr, chunk: &mut Chunk) -> Result<(), String> {
-    match expr {
-        Expr::Int(v, _) => {
-            chunk.push(Instr::Const(Value::Int(*v)));
-            Ok(())
+    /// Push the values, then run the builtin; `Push` stores the grown list
+    /// back (lists are values).
+    fn compile_builtin(&mut self, b: &Builtin, args: &[Expr]) -> Result<(), String> {
+        if !b.accepts(args.len()) {
+            return Err(format!(
+                "Bytecode compiler: `{}` can't take {} value(s)",
+                b.name,
+                args.len()
+            ));
         }
-        Expr::Bool(b, _) => {
-            chunk.push(Instr::Const(Value::Bool(*b)));
-            Ok(())
+        for a in args {
+            self.compile_expr(a)?;
         }
-        Expr::Char(c, _) => {
-            chunk.push(Instr::Const(Value::Char(*c)));
-            Ok(())
+        match b.native {
+            Native::Instr(instr) => self.emit(instr),
+            Native::Counted(instr) => self.emit(instr(args.len() - b.fixed_params())),
         }
-        Expr::Str(s, _) => {
-            chunk.push(Instr::Const(Value::Str(s.clone())));
-            Ok(())
+        if b.params.first() == Some(&Param::ListVar) {
+            let Some(Expr::Var(list, _)) = args.first() else {
+                return Err(format!("`{}` needs a list variable as its first argument", b.name));
+            };
+            let slot = self.ensure_local(list);
+            self.emit(Instr::StoreLocal(slot));
         }
-        Expr::Var(name, _) => {
-            // load the local slot for this variable
-            // if it doesn't exist yet, that's a compile-time error (should be declared by analyzer)
-            if let Some(idx) = chunk.locals.iter().position(|n| n == name) {
-                chunk.push(Instr::LoadLocal(idx));
-                Ok(())
-            } else {
-                Err(format!("Bytecode compiler: unknown variable `{}`", name))
-            }
+        Ok(())
+    }
+
+    /// What a call of a user function links to: this file's `total`,
+    /// `math.double`, or a prelude function (any letter case).
+    fn call_target(&self, name: &str) -> Option<String> {
+        if name.contains('.') || self.func_names.contains(name) {
+            return Some(name.to_string());
         }
-        Expr::Add(a, b, _) => {
-            compile_expr(a, chunk)?;
-            compile_expr(b, chunk)?;
-            chunk.push(Instr::AddInt);
-            Ok(())
+        self.prelude
+            .iter()
+            .find(|f| f.eq_ignore_ascii_case(name))
+            .map(|f| format!("{}.{}", builtins::PRELUDE_MODULE, f))
+    }
+
+    /// `Circle(5)` / `Empty`: push the values, then wrap them in the variant.
+    fn compile_variant(&mut self, name: &str, args: &[Expr]) -> Result<(), String> {
+        let (tag, size) = self
+            .variant(name)
+            .ok_or_else(|| format!("Bytecode compiler: unknown variant `{}`", name))?;
+        if size != args.len() {
+            return Err(format!(
+                "Bytecode compiler: `{}` holds {} value(s), got {}",
+                name,
+                size,
+                args.len()
+            ));
         }
-        Expr::Cmp(a, op, b, _) => {
-            compile_expr(a, chunk)?;
-            compile_expr(b, chunk)?;
-            match op {
-                crate::ast::CmpOp::Eq => chunk.push(Instr::CmpEq),
-                crate::ast::CmpOp::Ne => chunk.push(Instr::CmpNe),
-                crate::ast::CmpOp::Lt => chunk.push(Instr::CmpLt),
-                crate::ast::CmpOp::Le => chunk.push(Instr::CmpLe),
-                crate::ast::CmpOp::Gt => chunk.push(Instr::CmpGt),
-                crate::ast::CmpOp::Ge => chunk.push(Instr::CmpGe),
-            }
-            Ok(())
+        for a in args {
+            self.compile_expr(a)?;
         }
-        Expr::Call(name, args, _) => {
-            // Support built-in write/print calls which return Unit (side-effect)
-            if name.eq_ignore_ascii_case("print") || name.eq_ignore_ascii_case("write") {
-                for a in args {
-                    compile_expr(a, chunk)?;
-                }
-                chunk.push(Instr::Print(args.len()));
-                Ok(())
-            } else {
-                Err(format!(
-                    "Bytecode compiler: function calls not implemented yet (saw `{}`)",
-                    name
-                ))
+        let name = self.name_const(name);
+        self.emit(Instr::MakeVariant(name, tag, size));
+        Ok(())
+    }
+}
+
+/// Whether an expression statement leaves a value to drop: builtins that
+/// give back nothing (`Print`, `Push`, ...) leave nothing.
+fn leaves_value(e: &Expr) -> bool {
+    match e {
+        Expr::Call(name, _, _) => builtins::lookup(name).is_none_or(|b| b.ret != Ret::Nothing),
+        _ => true,
+    }
+}
+
+/// The value of a literal built only from literals (`[1, 2]`, `{"a": 1}`),
+/// so it can sit in the constant pool instead of being assembled at runtime.
+fn const_value(e: &Expr) -> Option<Value> {
+    match e {
+        Expr::Int(v, _) => Some(Value::Int(*v)),
+        Expr::Float(v, _) => Some(Value::Float(*v)),
+        Expr::Bool(b, _) => Some(Value::Bool(*b)),
+        Expr::Char(c, _) => Some(Value::Char(*c)),
+        Expr::Str(s, _) => Some(Value::Str(s.clone())),
+        Expr::List(items, _) => items.iter().map(const_value).collect::<Option<_>>().map(Value::List),
+        Expr::Map(entries, _) => {
+            let mut out = Vec::with_capacity(entries.len());
+            for (k, v) in entries {
+                map_insert(&mut out, const_value(k)?, const_value(v)?);
             }
+            Some(Value::Map(out))
         }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::run_test_program;
+
+    /// What a `main` with this body prints.
+    fn output(body: &str) -> String {
+        run_test_program(&format!("Func main() {{\n{}\n}}\n", body)).unwrap()
+    }
+
+    /// What a program with these declarations and `main` body prints.
+    fn output_with(decls: &str, body: &str) -> String {
+        run_test_program(&format!("{}\n\nFunc main() {{\n{}\n}}\n", decls, body)).unwrap()
+    }
+
+    /// Why a `main` with this body stops.
+    fn failure(body: &str) -> String {
+        run_test_program(&format!("Func main() {{\n{}\n}}\n", body)).unwrap_err()
+    }
+
+    #[test]
+    fn lists_index_push_and_iterate() {
+        let body = "xs = [1, 2]\nPush(xs, 3)\nxs[0] = 5\nPrint(Len(xs), xs[2], xs)\nFor x in xs {\n    Write(x)\n}\nPrint(\"\")";
+        assert_eq!(output(body), "3 3 [5, 2, 3]\n523\n");
+        // The loop walks the list as it was when the loop started.
+        assert_eq!(output("xs = [1]\nFor x in xs {\n    Push(xs, x)\n}\nPrint(xs)"), "[1, 1]\n");
+        assert!(failure("xs = [1]\nPrint(xs[1])").contains("Index 1 is outside the list"));
+    }
+
+    #[test]
+    fn maps_look_up_set_and_list_keys() {
+        let body = "ages = {\"ann\": 30, \"bob\": 4}\nages[\"ann\"] = 31\nages[\"cy\"] = 7\nPrint(ages[\"ann\"], Len(ages), Has(ages, \"bob\"), Has(ages, \"dan\"))\nPrint(Keys(ages))";
+        assert_eq!(output(body), "31 3 true false\n[\"ann\", \"bob\", \"cy\"]\n");
+        assert!(failure("m = {1: 2}\nPrint(m[3])").contains("Key 3 is not in the map"));
+    }
+
+    #[test]
+    fn structs_build_read_and_change_fields() {
+        let decls = "Struct Point { x: Int, y: Int }";
+        let body = "p = Point { y: 2, x: 1 }\np.x = p.x + 10\nPrint(p.x, p.y)\nPrint(p)";
+        assert_eq!(output_with(decls, body), "11 2\nPoint { x: 11, y: 2 }\n");
+    }
+
+    #[test]
+    fn match_runs_the_arm_of_the_variant() {
+        let decls = concat!(
+            "Enum Shape { Circle(Int), Square(Int, Int), Empty }\n\n",
+            "Func describe(s: Shape) {\n    Match s {\n",
+            "        Square(w, _) => Print(\"square\", w)\n",
+            "        Empty => Print(\"empty\")\n",
+            "        _ => Print(\"other\")\n    }\n}",
+        );
+        let body = "describe(Square(2, 3))\ndescribe(Empty)\ndescribe(Circle(1))\nPrint(Square(4, 5))";
+        assert_eq!(output_with(decls, body), "square 2\nempty\nother\nSquare(4, 5)\n");
+    }
+
+    #[test]
+    fn floats_add_compare_and_convert() {
+        let body = "x = 0.5 + 0.25\nPrint(x, x < 1.0, 2.5 >= 2.5, 1e-3)\nPrint(ToFloat(2), ToInt(7.9), ToInt(3))";
+        assert_eq!(output(body), "0.75 true true 0.001\n2.0 7 3\n");
+        assert!(failure("Print(ToInt(1e19))").contains("ToInt can't turn"));
+    }
+
+    #[test]
+    fn strings_join_slice_and_compare() {
+        let body = concat!(
+            "s = \"h\u{e9}llo\" + \" w\"\n",
+            "Print(s, Len(s), s[1], Substring(s, 1, 4))\n",
+            "Print(Upper(\"ab\"), Lower(\"AB\"), Trim(\"  x \"), Contains(s, \"ll\"))\n",
+            "Print(Split(\"a,b\", \",\"), \"apple\" < \"banana\", \"b\" > \"banana\")",
+        );
+        let expected = "h\u{e9}llo w 7 \u{e9} \u{e9}ll\nAB ab x true\n[\"a\", \"b\"] true false\n";
+        assert_eq!(output(body), expected);
+        assert!(failure("Print(Substring(\"abc\", 2, 5))").contains("Substring from 2 to 5"));
+        assert!(failure("s = \"ab\"\nPrint(s[2])").contains("Index 2 is outside the string"));
+    }
+
+    #[test]
+    fn write_print_print_sep_and_format() {
+        let body = concat!(
+            "Write(\"a\", 1)\nWrite(\"b\")\nPrint(\"c\", 2)\n",
+            "PrintSep(\", \", 1, \"x\", [2])\n",
+            "Print(Format(\"{} of {}\", 3, \"four\"))",
+        );
+        assert_eq!(output(body), "a 1bc 2\n1, x, [2]\n3 of four\n");
+        let wrong = failure("t = \"{} and {}\"\nPrint(Format(t, 1))");
+        assert!(wrong.contains("`Format` got 1 value(s) for 2"), "{}", wrong);
     }
 }
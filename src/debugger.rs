@@ -0,0 +1,373 @@
+// src/debugger.rs
+//
+// Interactive step-through debugger behind `a debug`.
+// Runs a Chunk one source line at a time using the line and file tables the
+// compiler and linker record (so it steps into imported files and the
+// prelude too), and shows locals (by name) and the value stack at every stop.
+
+use crate::bytecode::{Chunk, Value};
+use crate::vm::{self, Vm, VmLimits};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Commands:
+  break <line>   (b)  stop when the program reaches <line>
+                      (<file>:<line> for a line of an imported file)
+  delete <line>  (d)  remove the breakpoint on <line>
+  step           (s)  run the current line and stop at the next one
+  next           (n)  like step, but never stops inside a called function
+  continue       (c)  run until the next breakpoint or the end
+  locals         (l)  show variables and the value stack again
+  help           (h)  show this list
+  quit           (q)  stop debugging
+Pressing Enter repeats the last command.";
+
+/// A source line: (index into `Chunk::file_names`, 1-based line).
+type Location = (u32, u32);
+
+/// `sources[i]`: the text of file `i` of the chunk, if known.
+pub fn run(chunk: &Chunk, sources: &[Option<String>], limits: VmLimits) -> Result<(), String> {
+    let mut dbg = Debugger::new(chunk, sources, limits);
+
+    println!("A debugger. Type `help` for commands.");
+    if !dbg.at_code() {
+        println!("Program finished.");
+        return Ok(());
+    }
+    dbg.show_stop();
+
+    let stdin = io::stdin();
+    let mut last = String::new();
+    loop {
+        print!("(a-debug) ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let mut input = String::new();
+        let read = stdin.lock().read_line(&mut input).map_err(|e| e.to_string())?;
+        if read == 0 {
+            println!();
+            return Ok(());
+        }
+        let mut input = input.trim().to_string();
+        if input.is_empty() {
+            input = last.clone();
+        }
+        last = input.clone();
+
+        let mut words = input.split_whitespace();
+        let cmd = words.next().unwrap_or("");
+        let arg = words.next();
+
+        let still_running = match cmd {
+            "" => true,
+            "break" | "b" => {
+                dbg.add_breakpoint(arg);
+                true
+            }
+            "delete" | "d" => {
+                dbg.remove_breakpoint(arg);
+                true
+            }
+            "step" | "s" => dbg.step_line(false)?,
+            "next" | "n" => dbg.step_line(true)?,
+            "continue" | "c" => dbg.continue_to_breakpoint()?,
+            "locals" | "l" => {
+                dbg.show_state();
+                true
+            }
+            "help" | "h" => {
+                println!("{}", HELP);
+                true
+            }
+            "quit" | "q" => return Ok(()),
+            other => {
+                println!("Unknown command `{}`. Type `help` for commands.", other);
+                true
+            }
+        };
+
+        // Show what the program wrote with `Write` before our own output.
+        vm::flush_output();
+        if !still_running {
+            println!("Program finished.");
+            return Ok(());
+        }
+        if matches!(cmd, "step" | "s" | "next" | "n" | "continue" | "c") {
+            dbg.show_stop();
+        }
+    }
+}
+
+struct Debugger<'a> {
+    chunk: &'a Chunk,
+    /// Lines of each file's source.
+    src_lines: Vec<Vec<&'a str>>,
+    vm: Vm,
+    breakpoints: BTreeSet<Location>,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(chunk: &'a Chunk, sources: &'a [Option<String>], limits: VmLimits) -> Self {
+        let mut dbg = Debugger {
+            chunk,
+            src_lines: sources
+                .iter()
+                .map(|src| src.as_deref().map(|s| s.lines().collect()).unwrap_or_default())
+                .collect(),
+            vm: Vm::with_limits(limits),
+            breakpoints: BTreeSet::new(),
+        };
+        dbg.vm.start(chunk);
+        dbg
+    }
+
+    fn at_code(&self) -> bool {
+        self.vm.ip() < self.chunk.code.len()
+    }
+
+    fn current_location(&self) -> Option<Location> {
+        let ip = self.vm.ip();
+        self.chunk.line_at(ip).map(|line| (self.chunk.file_at(ip), line))
+    }
+
+    /// `line 3`, or `line 3 of math.a` outside the file being run.
+    fn describe(&self, (file, line): Location) -> String {
+        match self.chunk.file_name(file) {
+            Some(name) if file > 0 => format!("line {} of {}", line, name),
+            _ => format!("line {}", line),
+        }
+    }
+
+    /// Run until execution reaches a different source line (or the program
+    /// ends). With `over`, lines inside functions called from here are run
+    /// through without stopping.
+    fn step_line(&mut self, over: bool) -> Result<bool, String> {
+        let start = self.current_location();
+        let depth = self.vm.call_depth();
+        loop {
+            if !self.vm.step(self.chunk)? {
+                return Ok(false);
+            }
+            if over && self.vm.call_depth() > depth {
+                continue;
+            }
+            if let Some(location) = self.current_location() {
+                // Back in the caller counts as moving on, even on the same line.
+                if Some(location) != start || self.vm.call_depth() < depth {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    fn continue_to_breakpoint(&mut self) -> Result<bool, String> {
+        loop {
+            if !self.step_line(false)? {
+                return Ok(false);
+            }
+            if let Some(location) = self.current_location() {
+                if self.breakpoints.contains(&location) {
+                    println!("Breakpoint hit at {}.", self.describe(location));
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    fn add_breakpoint(&mut self, arg: Option<&str>) {
+        match self.breakpoint_at(arg) {
+            Ok(location) => {
+                self.breakpoints.insert(location);
+                println!("Breakpoint set at {}.", self.describe(location));
+            }
+            Err(msg) => println!("{}", msg),
+        }
+    }
+
+    /// Where `break <arg>` stops: a line with code on it.
+    fn breakpoint_at(&self, arg: Option<&str>) -> Result<Location, String> {
+        let location = self.parse_location(arg)?;
+        let has_code = (0..self.chunk.code.len())
+            .any(|ip| self.chunk.file_at(ip) == location.0 && self.chunk.lines[ip] == location.1);
+        if !has_code {
+            return Err(format!(
+                "{} has no code to stop on (blank, comment or `}}`). Pick a line with a statement.",
+                capitalize(&self.describe(location))
+            ));
+        }
+        Ok(location)
+    }
+
+    fn remove_breakpoint(&mut self, arg: Option<&str>) {
+        let location = match self.parse_location(arg) {
+            Ok(location) => location,
+            Err(msg) => {
+                println!("{}", msg);
+                return;
+            }
+        };
+        if self.breakpoints.remove(&location) {
+            println!("Breakpoint on {} removed.", self.describe(location));
+        } else {
+            println!("There is no breakpoint on {}.", self.describe(location));
+        }
+    }
+
+    /// `3` (a line of the file being run) or `math.a:3`. The file is matched
+    /// against the files the program was linked from, by full name or by the
+    /// last part of its path.
+    fn parse_location(&self, arg: Option<&str>) -> Result<Location, String> {
+        const USAGE: &str = "Give a line number, for example: break 3 (or break math.a:3)";
+        let arg = arg.ok_or(USAGE)?;
+        let (file, line) = match arg.rsplit_once(':') {
+            Some((name, line)) => (self.find_file(name)?, line),
+            None => (0, arg),
+        };
+        match line.parse::<u32>() {
+            Ok(n) if n > 0 => Ok((file, n)),
+            _ => Err(USAGE.to_string()),
+        }
+    }
+
+    fn find_file(&self, name: &str) -> Result<u32, String> {
+        let names = &self.chunk.file_names;
+        let last_part = |f: &str| f.rsplit(['/', '\\']).next().unwrap_or(f).to_string();
+        names
+            .iter()
+            .position(|f| f == name)
+            .or_else(|| names.iter().position(|f| last_part(f) == name))
+            .map(|i| i as u32)
+            .ok_or_else(|| {
+                let files: Vec<String> = names.iter().map(|f| last_part(f)).collect();
+                format!("No file `{}` in this program (its files: {}).", name, files.join(", "))
+            })
+    }
+
+    fn show_stop(&self) {
+        match self.current_location() {
+            Some((file, line)) => {
+                let text = self
+                    .src_lines
+                    .get(file as usize)
+                    .and_then(|lines| lines.get(line as usize - 1))
+                    .map(|s| s.trim())
+                    .unwrap_or("");
+                println!("--> {}: {}", self.describe((file, line)), text);
+            }
+            None => println!("--> instruction {}", self.vm.ip()),
+        }
+        self.show_state();
+    }
+
+    fn show_state(&self) {
+        // Inside a function only its own slots are shown, named without the
+        // `func.` (or `module.func.`) prefix; main's locals have no prefix.
+        // `$` locals are the compiler's own bookkeeping (e.g. a For loop's position).
+        let frame = self.vm.frame_locals();
+        let visible: Vec<(&str, &Value)> = self
+            .chunk
+            .locals
+            .iter()
+            .zip(self.vm.locals())
+            .enumerate()
+            .filter(|(i, (name, _))| match &frame {
+                Some(range) => range.contains(i),
+                None => !name.contains('.'),
+            })
+            .map(|(_, (name, v))| (name.rsplit('.').next().unwrap_or(name), v))
+            .filter(|(name, _)| !name.starts_with('$'))
+            .collect();
+        if visible.is_empty() {
+            println!("    locals: (none)");
+        } else {
+            println!("    locals:");
+            for (name, value) in visible {
+                // Slots start out as Unit until their first assignment runs.
+                let shown = match value {
+                    Value::Unit => "(not set yet)".to_string(),
+                    v => vm::value_repr(v),
+                };
+                println!("      {} = {}", name, shown);
+            }
+        }
+
+        let stack: Vec<String> = self.vm.stack().iter().map(vm::value_repr).collect();
+        println!("    stack: [{}]", stack.join(", "));
+    }
+}
+
+/// `line 3` -> `Line 3`.
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Instr;
+    use crate::optimize::OptLevel;
+    use crate::pipeline::compile_test_program;
+
+    const PROGRAM: &str = "Func double(x: Int) -> Int {\n    Return x + x\n}\n\nFunc main() {\n    a = double(1)\n    b = double(a)\n    Print(b)\n}\n";
+
+    fn debugger(chunk: &Chunk) -> Debugger<'_> {
+        let mut dbg = Debugger::new(chunk, &[], VmLimits::default());
+        dbg.vm.capture_output();
+        dbg
+    }
+
+    #[test]
+    fn steps_into_and_over_calls() {
+        let chunk = compile_test_program(PROGRAM, OptLevel::O0);
+        let mut dbg = debugger(&chunk);
+        assert_eq!(dbg.current_location(), Some((0, 6)));
+        assert!(dbg.step_line(false).unwrap());
+        assert_eq!(dbg.current_location(), Some((0, 2)));
+        assert!(dbg.step_line(false).unwrap());
+        // Back in main, still on the line that made the call.
+        assert_eq!(dbg.current_location(), Some((0, 6)));
+        assert!(dbg.step_line(true).unwrap());
+        assert_eq!(dbg.current_location(), Some((0, 7)));
+        assert!(dbg.step_line(true).unwrap());
+        assert_eq!(dbg.current_location(), Some((0, 8)));
+    }
+
+    #[test]
+    fn continues_to_the_next_breakpoint() {
+        let chunk = compile_test_program(PROGRAM, OptLevel::O0);
+        let mut dbg = debugger(&chunk);
+        dbg.add_breakpoint(Some("8"));
+        assert!(dbg.continue_to_breakpoint().unwrap());
+        assert_eq!(dbg.current_location(), Some((0, 8)));
+        assert_eq!(dbg.vm.locals()[..2], [Value::Int(2), Value::Int(4)]);
+        assert!(!dbg.continue_to_breakpoint().unwrap());
+        assert_eq!(dbg.vm.take_output(), "4\n");
+    }
+
+    #[test]
+    fn breakpoints_name_a_file_of_the_program() {
+        let mut chunk = Chunk::new();
+        chunk.file_names = vec!["prog/main.a".to_string(), "prog/lib/math.a".to_string()];
+        chunk.push_in(Instr::Halt, 0, 2);
+        chunk.push_in(Instr::Enter(0, 0), 1, 5);
+        chunk.push_in(Instr::Ret, 1, 5);
+        let dbg = debugger(&chunk);
+
+        assert_eq!(dbg.breakpoint_at(Some("2")), Ok((0, 2)));
+        assert_eq!(dbg.breakpoint_at(Some("main.a:2")), Ok((0, 2)));
+        assert_eq!(dbg.breakpoint_at(Some("math.a:5")), Ok((1, 5)));
+        assert_eq!(dbg.breakpoint_at(Some("prog/lib/math.a:5")), Ok((1, 5)));
+        assert_eq!(dbg.describe((1, 5)), "line 5 of prog/lib/math.a");
+
+        assert!(dbg.breakpoint_at(Some("5")).unwrap_err().contains("Line 5 has no code"));
+        assert!(dbg.breakpoint_at(Some("other.a:5")).unwrap_err().contains("main.a, math.a"));
+        for bad in [None, Some("0"), Some("x"), Some("math.a:")] {
+            assert!(dbg.breakpoint_at(bad).unwrap_err().starts_with("Give a line number"));
+        }
+    }
+}
@@ -1,56 +1,238 @@
 // src/diag.rs
 
+use std::ops::Range;
+use std::sync::OnceLock;
+
 use ariadne::{Color, Label, Report, ReportKind, Source};
 
-use crate::analysis::AError;
+use crate::analysis::{ADiagnostic, AError, Severity};
+use crate::ast::Span;
+use crate::i18n;
 use crate::parser::ParseDiag;
 
+/// How much teaching text each error and warning shows (`--explain`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Explain {
+    /// One `file:line:col: A001 message` line, for editors and quickfix lists.
+    Short,
+    /// The code with its labels and the help.
+    Normal,
+    /// Also why it's a problem and an example fix.
+    Full,
+}
+
+/// Environment variable with the `--explain` level to use when it isn't given.
+pub const EXPLAIN_ENV: &str = "A_EXPLAIN";
+
+static EXPLAIN: OnceLock<Explain> = OnceLock::new();
+
+/// Show diagnostics at `level` from now on. Only the first call counts.
+pub fn set_explain(level: Explain) {
+    let _ = EXPLAIN.set(level);
+}
+
+fn explain() -> Explain {
+    EXPLAIN.get().copied().unwrap_or(Explain::Full)
+}
+
+/// 1-based line and column (in characters) of byte offset `pos`.
+fn line_col(src: &str, pos: usize) -> (usize, usize) {
+    let before = &src[..pos.min(src.len())];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn short_line(src: &str, file: &str, span: Span, code: &str, message: &str) -> String {
+    let (line, col) = line_col(src, span.start);
+    format!("{}:{}:{}: {} {}\n", file, line, col, code, message)
+}
+
 pub fn render_parse_error(src: &str, file: &str, d: &ParseDiag) {
-    let report = Report::build(ReportKind::Error, (file, d.span.start..d.span.end))
+    eprint!("{}", parse_report(src, file, d, explain()));
+}
+
+/// The text `render_parse_error` prints at `level`.
+fn parse_report(src: &str, file: &str, d: &ParseDiag, level: Explain) -> String {
+    if let Some(lesson) = &d.lesson {
+        return lesson_report(src, file, lesson, Severity::Error, level);
+    }
+    if level == Explain::Short {
+        return short_line(src, file, d.span, "A_PARSE", &d.message);
+    }
+    let error = i18n::text("ui", "error", &[]);
+    let kind = ReportKind::Custom(&error, Color::Red);
+    let mut report = Report::build(kind, (file, d.span.start..d.span.end))
         .with_code("A_PARSE")
         .with_message(&d.message)
         .with_label(
             Label::new((file, d.span.start..d.span.end))
-                .with_message("I got confused parsing here")
+                .with_message(i18n::text("a_parse", "label", &[]))
                 .with_color(Color::Red),
         )
-        .with_help(
-            r#"Example:
-Func main() {
-    x = 1
-    y = x + 2
-    Print(y)
-}"#,
-        )
-        .with_note(
-            "The parser couldn't match the code here to A's grammar.\n\
-Double-check braces `{}` and parentheses `()` are balanced, and statements are valid.",
-        )
-        .finish();
+        .with_note(i18n::text("a_parse", "note", &[]));
+    if level == Explain::Full {
+        report = report.with_help(i18n::text("a_parse", "example", &[]));
+    }
 
-    report.print((file, Source::from(src))).unwrap();
+    report_text(report.finish(), file, src)
 }
 
 pub fn render_lesson_error(src: &str, file: &str, e: &AError) {
-    let mut rep = Report::build(ReportKind::Error, (file, e.span.start..e.span.end))
+    render_lesson(src, file, e, Severity::Error);
+}
+
+pub fn render_diagnostic(src: &str, file: &str, d: &ADiagnostic) {
+    render_lesson(src, file, &d.lesson, d.severity);
+}
+
+fn render_lesson(src: &str, file: &str, e: &AError, severity: Severity) {
+    eprint!("{}", lesson_report(src, file, e, severity, explain()));
+}
+
+/// The text `render_lesson` prints at `level`.
+fn lesson_report(src: &str, file: &str, e: &AError, severity: Severity, level: Explain) -> String {
+    if level == Explain::Short {
+        return short_line(src, file, e.span, &e.code, &e.title);
+    }
+    let (heading, color) = match severity {
+        Severity::Error => (i18n::text("ui", "error", &[]), Color::Red),
+        Severity::Warning => (i18n::text("ui", "warning", &[]), Color::Yellow),
+    };
+    let mut rep = Report::build(ReportKind::Custom(&heading, color), (file, e.span.start..e.span.end))
         .with_code(&e.code)
         .with_message(&e.title)
         .with_label(
             Label::new((file, e.span.start..e.span.end))
-                .with_message("This is where the problem shows up")
-                .with_color(Color::Red),
-        )
-        .with_note(format!("Why: {}", e.mental_model));
+                .with_message(i18n::text("ui", "here", &[]))
+                .with_color(color),
+        );
 
-    for (i, h) in e.help.iter().enumerate() {
-        rep = rep.with_help(format!("Help {}: {}", i + 1, h));
+    for (span, msg) in &e.labels {
+        rep = rep.with_label(
+            Label::new((file, span.start..span.end))
+                .with_message(msg)
+                .with_color(Color::Blue),
+        );
     }
 
-    rep = rep.with_help(format!("Example fix:\n{}", e.example));
+    // ariadne numbers the help lines itself.
+    for h in &e.help {
+        rep = rep.with_help(h);
+    }
+
+    if level == Explain::Full {
+        rep = rep.with_note(i18n::text("ui", "why", &[("text", &e.mental_model)]));
+        if !e.example.is_empty() {
+            rep = rep.with_help(i18n::text("ui", "example_fix", &[("text", &e.example)]));
+        }
+    }
 
     if let Some(backend) = &e.backend {
-        rep = rep.with_note(format!("Backend: {}", backend));
+        rep = rep.with_note(i18n::text("ui", "backend", &[("text", backend)]));
+    }
+
+    report_text(rep.finish(), file, src)
+}
+
+/// A report as text, with its `Help`/`Note` headings in the chosen language
+/// (ariadne always writes them in English).
+fn report_text(report: Report<(&str, Range<usize>)>, file: &str, src: &str) -> String {
+    let mut out = Vec::new();
+    report.write((file, Source::from(src)), &mut out).unwrap();
+    let help = i18n::text("ui", "help_heading", &[]);
+    let note = i18n::text("ui", "note_heading", &[]);
+    translate_headings(&String::from_utf8_lossy(&out), &help, &note)
+}
+
+/// Replace the `Help` and `Note` (or `Help 2`, ...) that start a report line,
+/// right after its margin, with `help` and `note`.
+fn translate_headings(report: &str, help: &str, note: &str) -> String {
+    report
+        .split_inclusive('\n')
+        .map(|line| {
+            for (word, local) in [("Help", help), ("Note", note)] {
+                let Some(at) = line.find(word) else {
+                    continue;
+                };
+                let (before, after) = (&line[..at], &line[at + word.len()..]);
+                if is_margin(before) && is_heading_end(after) {
+                    return format!("{}{}{}", before, local, after);
+                }
+            }
+            line.to_string()
+        })
+        .collect()
+}
+
+/// Only the report's margin: spaces, then `│ ` (or `| `), maybe colored.
+/// Text indented further is a continuation line, not a heading.
+fn is_margin(text: &str) -> bool {
+    matches!(without_colors(text).trim_start(), "│ " | "| ")
+}
+
+/// `: ...` or ` 2: ...`, maybe after a color code.
+fn is_heading_end(text: &str) -> bool {
+    let text = without_colors(text);
+    let rest = text.trim_start_matches(|c: char| c == ' ' || c.is_ascii_digit());
+    rest.starts_with(':') && (rest.len() == text.len() || text.starts_with(' '))
+}
+
+fn without_colors(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_code = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => in_code = true,
+            'm' if in_code => in_code = false,
+            _ if in_code => {}
+            _ => out.push(c),
+        }
     }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    rep.finish().print((file, Source::from(src))).unwrap();
+    #[test]
+    fn report_headings_are_translated() {
+        let report = "[A001] Error: x\n   │ \n   │ \u{1b}[38;5;115mHelp 1\u{1b}[0m: one\n   │     Help: kept\n   │ Helpful: kept\n   │ Note: why\n";
+        assert_eq!(
+            translate_headings(report, "Ayuda", "Nota"),
+            "[A001] Error: x\n   │ \n   │ \u{1b}[38;5;115mAyuda 1\u{1b}[0m: one\n   │     Help: kept\n   │ Helpful: kept\n   │ Nota: why\n"
+        );
+    }
+
+    #[test]
+    fn explain_levels_show_more_and_more() {
+        let src = "Func main() {\n    Print(y)\n}\n";
+        let span = Span { start: 24, end: 25 };
+        let e = AError::lesson(span, "a001_undeclared_variable", &[("name", &"y")]);
+        let report = |level| without_colors(&lesson_report(src, "t.a", &e, Severity::Error, level));
+
+        let short = report(Explain::Short);
+        assert_eq!(short, "t.a:2:11: A001 Use of undeclared variable 'y'\n");
+        let normal = report(Explain::Normal);
+        assert!(normal.contains("Declare it first: `y = <expr>`"), "{}", normal);
+        assert!(!normal.contains("Why:") && !normal.contains("Example fix:"), "{}", normal);
+        let full = report(Explain::Full);
+        assert!(full.contains("Why: You used a variable"), "{}", full);
+        assert!(full.contains("Example fix:"), "{}", full);
+    }
+
+    #[test]
+    fn parse_errors_show_their_example_once() {
+        let src = "Func main() {\n    x = = 1\n}\n";
+        let d = crate::parser::parse_program(src).unwrap_err();
+        let short = parse_report(src, "t.a", &d, Explain::Short);
+        assert_eq!(short, "t.a:2:8: A_PARSE Parse error near here\n");
+        let full = without_colors(&parse_report(src, "t.a", &d, Explain::Full));
+        assert_eq!(full.matches("y = x + 2").count(), 1, "{}", full);
+        let normal = without_colors(&parse_report(src, "t.a", &d, Explain::Normal));
+        assert!(!normal.contains("y = x + 2"), "{}", normal);
+    }
 }
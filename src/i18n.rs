@@ -0,0 +1,274 @@
+// src/i18n.rs
+//
+// Lesson text in the learner's language. Every title, explanation, help line
+// and example lives in a catalog (`lang/<code>.toml`, built into the binary),
+// keyed by lesson. Anything a catalog doesn't have is shown in English.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+/// Language code -> catalog source. English comes first and is complete.
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("lang/en.toml")),
+    ("es", include_str!("lang/es.toml")),
+];
+
+/// Environment variable that picks the language when `--lang` isn't given.
+pub const LANG_ENV: &str = "A_LANG";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Lesson key -> text key -> text.
+type Catalog = HashMap<String, HashMap<String, Text>>;
+
+static LANG: OnceLock<&'static str> = OnceLock::new();
+static LOADED: OnceLock<Vec<Catalog>> = OnceLock::new();
+
+/// The codes `--lang` accepts.
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    CATALOGS.iter().map(|(code, _)| *code)
+}
+
+/// The known language `name` means: `es`, `ES`, `es_ES.UTF-8` and `es-MX`
+/// all mean `es`.
+pub fn parse_language(name: &str) -> Result<&'static str, String> {
+    let base = name
+        .split(['_', '-', '.'])
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    languages().find(|code| *code == base).ok_or_else(|| {
+        format!(
+            "unknown language `{}` (available: {})",
+            name,
+            languages().collect::<Vec<_>>().join(", ")
+        )
+    })
+}
+
+/// Show lessons in `lang` from now on. Only the first call counts.
+pub fn set_language(lang: &'static str) {
+    let _ = LANG.set(lang);
+}
+
+fn catalogs() -> &'static [Catalog] {
+    LOADED.get_or_init(|| {
+        CATALOGS
+            .iter()
+            .map(|(code, src)| {
+                toml::from_str(src)
+                    .unwrap_or_else(|e| panic!("lang/{}.toml is not a valid catalog: {}", code, e))
+            })
+            .collect()
+    })
+}
+
+/// The entry for `lesson.key` in the chosen language, else in English.
+fn lookup(lesson: &str, key: &str) -> Option<&'static Text> {
+    let lang = LANG.get().copied().unwrap_or("en");
+    let chosen = CATALOGS.iter().position(|(code, _)| *code == lang).unwrap_or(0);
+    let find = |i: usize| catalogs()[i].get(lesson)?.get(key);
+    find(chosen).or_else(|| find(0))
+}
+
+/// Text `key` of `lesson`, with each `{name}` replaced by its value in `args`.
+pub fn text(lesson: &str, key: &str, args: &[(&str, &dyn Display)]) -> String {
+    match lookup(lesson, key) {
+        Some(Text::One(s)) => fill(s, args),
+        Some(Text::Many(lines)) => fill(&lines.join("\n"), args),
+        None => format!("{}.{}", lesson, key),
+    }
+}
+
+/// Like `text`, for keys that hold a list (each `help` line).
+pub fn lines(lesson: &str, key: &str, args: &[(&str, &dyn Display)]) -> Vec<String> {
+    match lookup(lesson, key) {
+        Some(Text::One(s)) => vec![fill(s, args)],
+        Some(Text::Many(lines)) => lines.iter().map(|s| fill(s, args)).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Whether `lesson` has text `key` in any language.
+pub fn has(lesson: &str, key: &str) -> bool {
+    lookup(lesson, key).is_some()
+}
+
+/// Replace `{name}` by the value given for `name`. Braces around anything
+/// else (A code like `Func main() {`, or `{}`) are left alone.
+fn fill(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let arg = after
+            .find('}')
+            .and_then(|close| args.iter().find(|(name, _)| *name == &after[..close]));
+        match arg {
+            Some((name, value)) => {
+                out.push_str(&value.to_string());
+                rest = &after[name.len() + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::path::Path;
+
+    fn parse(code: &str, src: &str) -> Catalog {
+        toml::from_str(src).unwrap_or_else(|e| panic!("lang/{}.toml: {}", code, e))
+    }
+
+    fn english() -> Catalog {
+        parse("en", CATALOGS[0].1)
+    }
+
+    fn joined(text: &Text) -> String {
+        match text {
+            Text::One(s) => s.clone(),
+            Text::Many(lines) => lines.join("\n"),
+        }
+    }
+
+    /// The `{name}` placeholders in `text`.
+    fn placeholders(text: &str) -> BTreeSet<String> {
+        text.split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}'))
+            .map(|(name, _)| name)
+            .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn every_catalog_parses() {
+        for (code, src) in CATALOGS {
+            parse(code, src);
+        }
+    }
+
+    #[test]
+    fn translations_match_english() {
+        let en = english();
+        for (code, src) in &CATALOGS[1..] {
+            for (lesson, texts) in parse(code, src) {
+                for (key, text) in texts {
+                    let Some(original) = en.get(&lesson).and_then(|t| t.get(&key)) else {
+                        panic!("lang/{}.toml has {}.{}, which English doesn't", code, lesson, key);
+                    };
+                    assert_eq!(
+                        placeholders(&joined(&text)),
+                        placeholders(&joined(original)),
+                        "placeholders of {}.{} in lang/{}.toml",
+                        lesson,
+                        key,
+                        code
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_lesson_has_its_own_code() {
+        let mut codes: HashMap<String, String> = HashMap::new();
+        for lesson in english().into_keys().filter(|l| is_lesson(l) && !l.starts_with("vm_")) {
+            let code = lesson[..4].to_string();
+            if let Some(other) = codes.insert(code.clone(), lesson.clone()) {
+                panic!("{} and {} share code {}", other, lesson, code.to_ascii_uppercase());
+            }
+        }
+    }
+
+    /// Source of every `.rs` file under `dir`.
+    fn sources(dir: &Path, out: &mut Vec<String>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                sources(&path, out);
+            } else if path.extension().is_some_and(|e| e == "rs") {
+                out.push(std::fs::read_to_string(&path).unwrap());
+            }
+        }
+    }
+
+    /// A lesson table name like `a001_undeclared_variable` or `vm_stack`.
+    fn is_lesson(s: &str) -> bool {
+        let b = s.as_bytes();
+        let coded = b.len() > 5
+            && b[0].is_ascii_lowercase()
+            && b[1..4].iter().all(u8::is_ascii_digit)
+            && b[4] == b'_';
+        (coded || s.starts_with("vm_"))
+            && s.bytes().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'_')
+    }
+
+    /// The string literal `text` starts with, if any.
+    fn literal(text: &str) -> Option<&str> {
+        text.trim_start().strip_prefix('"')?.split('"').next()
+    }
+
+    #[test]
+    fn every_key_used_in_the_code_is_in_english() {
+        let en = english();
+        let mut files = Vec::new();
+        sources(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut files);
+        let mut missing = Vec::new();
+        for src in &files {
+            let src = src.split("#[cfg(test)]").next().unwrap_or(src);
+            // Every lesson named in the code has a title.
+            for (at, _) in src.match_indices('"') {
+                if let Some(lesson) = literal(&src[at..]).filter(|s| is_lesson(s)) {
+                    if !en.get(lesson).is_some_and(|t| t.contains_key("title")) {
+                        missing.push(format!("{}.title", lesson));
+                    }
+                }
+            }
+            // `text(lesson, "key", ...)`, with the lesson a literal or the
+            // function's `KEY`.
+            for call in ["i18n::text(", "i18n::lines(", "runtime_lesson("] {
+                for (at, _) in src.match_indices(call) {
+                    let args = &src[at + call.len()..];
+                    let Some((first, rest)) = args.split_once(',') else {
+                        continue;
+                    };
+                    let lesson = match first.trim() {
+                        "KEY" => src[..at]
+                            .rfind("const KEY: &str = ")
+                            .and_then(|k| literal(&src[k + "const KEY: &str = ".len()..])),
+                        other => literal(other),
+                    };
+                    if let (Some(lesson), Some(key)) = (lesson, literal(rest)) {
+                        if !en.get(lesson).is_some_and(|t| t.contains_key(key)) {
+                            missing.push(format!("{}.{}", lesson, key));
+                        }
+                    }
+                }
+            }
+        }
+        missing.sort();
+        missing.dedup();
+        assert!(missing.is_empty(), "missing from lang/en.toml: {:?}", missing);
+    }
+}
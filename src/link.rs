@@ -0,0 +1,123 @@
+// src/link.rs
+//
+// Join the compiled files of a program into the one Chunk that `a build`
+// writes and `a run` executes. The file being run comes first, so its `main`
+// starts at instruction 0; every other file follows, with its jump targets,
+// local slots and constants renumbered, and with the calls between files
+// pointed at the right function.
+
+use std::collections::HashMap;
+
+use crate::bytecode::{Chunk, Instr};
+use crate::compiler::CompiledModule;
+
+/// One compiled file and where its imports ended up.
+pub struct LinkInput {
+    /// Module name (prefixes its locals); the file being run has none.
+    pub name: Option<String>,
+    /// File name, for the chunk's file table.
+    pub file_name: String,
+    pub module: CompiledModule,
+    /// Module name used in this file -> index of that file in the inputs.
+    pub imports: HashMap<String, usize>,
+}
+
+/// `inputs[0]` is the file being run. Every instruction keeps its source line,
+/// and the index of its input as its file.
+pub fn link(inputs: &[LinkInput]) -> Result<Chunk, String> {
+    let mut out = Chunk::new();
+    out.file_names = inputs.iter().map(|input| input.file_name.clone()).collect();
+    let mut code_bases = Vec::with_capacity(inputs.len());
+
+    for (file, input) in (0u32..).zip(inputs) {
+        let chunk = &input.module.chunk;
+        let code_base = out.code.len();
+        let local_base = out.locals.len();
+        code_bases.push(code_base);
+
+        for name in &chunk.locals {
+            out.locals.push(match &input.name {
+                Some(module) => format!("{}.{}", module, name),
+                None => name.clone(),
+            });
+        }
+        let consts: Vec<usize> = chunk
+            .consts
+            .iter()
+            .map(|v| out.add_const(v.clone()))
+            .collect();
+        let c = |i: usize| consts.get(i).copied().unwrap_or(i);
+
+        for (ip, instr) in chunk.code.iter().enumerate() {
+            let moved = match *instr {
+                Instr::Jump(t) => Instr::Jump(t + code_base),
+                Instr::JumpIfFalse(t) => Instr::JumpIfFalse(t + code_base),
+                Instr::Call(t, n) => Instr::Call(t + code_base, n),
+                Instr::LoadLocal(i) => Instr::LoadLocal(i + local_base),
+                Instr::StoreLocal(i) => Instr::StoreLocal(i + local_base),
+                Instr::Enter(first, n) => Instr::Enter(first + local_base, n),
+                Instr::LoadConst(i) => Instr::LoadConst(c(i)),
+                Instr::MakeRecord(name, n) => Instr::MakeRecord(c(name), n),
+                Instr::GetField(i) => Instr::GetField(c(i)),
+                Instr::SetField(i) => Instr::SetField(c(i)),
+                Instr::MakeVariant(name, tag, n) => Instr::MakeVariant(c(name), tag, n),
+                other => other,
+            };
+            out.push_in(moved, file, chunk.lines.get(ip).copied().unwrap_or(0));
+        }
+    }
+
+    for (input, &code_base) in inputs.iter().zip(&code_bases) {
+        for (ip, name) in &input.module.imported_calls {
+            let (module, f) = name
+                .split_once('.')
+                .ok_or_else(|| format!("Linker: unknown function `{}`", name))?;
+            let target = input
+                .imports
+                .get(module)
+                .and_then(|&m| {
+                    let entry = inputs.get(m)?.module.funcs.get(f)?;
+                    Some(code_bases[m] + entry)
+                })
+                .ok_or_else(|| format!("Linker: unknown function `{}`", name))?;
+            if let Instr::Call(_, n) = out.code[code_base + ip] {
+                out.code[code_base + ip] = Instr::Call(target, n);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(name: Option<&str>, file_name: &str, code: &[(Instr, u32)]) -> LinkInput {
+        let mut chunk = Chunk::new();
+        for &(instr, line) in code {
+            chunk.push(instr, line);
+        }
+        LinkInput {
+            name: name.map(str::to_string),
+            file_name: file_name.to_string(),
+            module: CompiledModule {
+                chunk,
+                funcs: HashMap::new(),
+                imported_calls: Vec::new(),
+            },
+            imports: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn every_file_keeps_its_lines() {
+        let main = input(None, "main.a", &[(Instr::Halt, 3)]);
+        let math = input(Some("math"), "math.a", &[(Instr::Ret, 7), (Instr::Ret, 8)]);
+        let chunk = link(&[main, math]).unwrap();
+        assert_eq!(chunk.file_names, ["main.a", "math.a"]);
+        assert_eq!(chunk.lines, [3, 7, 8]);
+        assert_eq!(chunk.files, [0, 1, 1]);
+        assert_eq!(chunk.file_name(chunk.file_at(2)), Some("math.a"));
+    }
+}
@@ -0,0 +1,549 @@
+// src/lint.rs
+//
+// Warnings: code that builds and runs, but is probably not what the student
+// meant (a variable nobody reads, a branch that can never run, ...). Each
+// warning has a code (W001, ...) that can be turned off with `--allow W001`
+// or a `// a:allow(W001)` comment, or turned into an error with `--deny W001`.
+//
+// Runs after analysis succeeded, on each file's own code.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::{ADiagnostic, AError, Severity};
+use crate::ast::{block_span, expr_span, stmt_span, CmpOp, Expr, IfBranch, Program, Span, Stmt};
+use crate::i18n;
+use crate::{optimize, pipeline};
+
+/// Every warning: (code, what it is about).
+pub const LINTS: &[(&str, &str)] = &[
+    ("W001", "a variable is never used"),
+    ("W002", "a variable is given values that are never read"),
+    ("W003", "code that can never run"),
+    ("W004", "a comparison of a value with itself"),
+    ("W005", "a variable is declared again with the same name"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Not reported.
+    Allow,
+    /// Reported; the program still builds.
+    Warn,
+    /// Reported as an error; the program doesn't build.
+    Deny,
+}
+
+/// How each warning is reported. Codes not mentioned are warnings.
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<String, Level>,
+    /// Level for every code not in `levels` (`--allow all`).
+    default: Option<Level>,
+}
+
+impl LintConfig {
+    /// `code` is a warning code or `all`.
+    pub fn set(&mut self, code: &str, level: Level) {
+        if code.eq_ignore_ascii_case("all") {
+            self.levels.clear();
+            self.default = Some(level);
+        } else {
+            self.levels.insert(code.to_ascii_uppercase(), level);
+        }
+    }
+
+    pub fn level(&self, code: &str) -> Level {
+        self.levels
+            .get(code)
+            .copied()
+            .or(self.default)
+            .unwrap_or(Level::Warn)
+    }
+}
+
+/// Check a `--allow`/`--deny` value: a known warning code or `all`.
+pub fn parse_code(s: &str) -> Result<String, String> {
+    let code = s.trim().to_ascii_uppercase();
+    if code == "ALL" || LINTS.iter().any(|(c, _)| *c == code) {
+        return Ok(code);
+    }
+    let known: Vec<&str> = LINTS.iter().map(|(c, _)| *c).collect();
+    Err(format!("unknown warning `{}` (known: {}, or `all`)", s, known.join(", ")))
+}
+
+/// Warnings for one file. `src` is the original text, for `// a:allow(...)`.
+pub fn check(program: &Program, src: &str, config: &LintConfig) -> Vec<ADiagnostic> {
+    let mut w = Walker {
+        vars: Vec::new(),
+        names: HashMap::new(),
+        found: Vec::new(),
+    };
+
+    w.body(&[], &program.stmts);
+    for f in &program.funcs {
+        w.body(&f.params, &f.body);
+    }
+
+    let allowed = allow_comments(src);
+    let mut found = w.found;
+    found.sort_by_key(|e| e.span.start);
+    found
+        .into_iter()
+        .filter_map(|mut lesson| {
+            let severity = match config.level(&lesson.code) {
+                Level::Allow => return None,
+                Level::Warn => Severity::Warning,
+                Level::Deny => Severity::Error,
+            };
+            if allowed.contains(&(line_of(src, lesson.span.start), lesson.code.clone())) {
+                return None;
+            }
+            let hint = i18n::text("ui", "allow_hint", &[("code", &lesson.code)]);
+            lesson.help.push(hint);
+            Some(ADiagnostic { severity, lesson })
+        })
+        .collect()
+}
+
+/// (line, code) pairs silenced by `// a:allow(W001, ...)` comments. A comment
+/// at the end of a line covers that line; one on a line of its own covers
+/// the next line.
+fn allow_comments(src: &str) -> HashSet<(usize, String)> {
+    let mut allowed = HashSet::new();
+    for comment in pipeline::line_comments(src) {
+        let Some(list) = src[comment.start + 2..comment.end]
+            .trim()
+            .strip_prefix("a:allow(")
+            .and_then(|rest| rest.split(')').next())
+        else {
+            continue;
+        };
+        let line = line_of(src, comment.start);
+        let line_start = src[..comment.start].rfind('\n').map_or(0, |at| at + 1);
+        let target = if src[line_start..comment.start].trim().is_empty() {
+            line + 1
+        } else {
+            line
+        };
+        for code in list.split(',') {
+            allowed.insert((target, code.trim().to_ascii_uppercase()));
+        }
+    }
+    allowed
+}
+
+/// 1-based line number of a byte offset.
+fn line_of(src: &str, offset: usize) -> usize {
+    src[..offset.min(src.len())].matches('\n').count() + 1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VarKind {
+    /// `Let`, `Mute` or a first `x = ...`.
+    Variable,
+    Param,
+    LoopVar,
+    /// A name bound by a Match case.
+    Binding,
+}
+
+struct Var {
+    name: String,
+    kind: VarKind,
+    /// Where it was declared.
+    span: Span,
+    read: bool,
+    /// Given a new value (or changed in place) after its declaration.
+    written: bool,
+}
+
+struct Walker {
+    /// Every variable of the body being checked, in order of declaration.
+    vars: Vec<Var>,
+    /// Name -> indexes in `vars` of the variables the name may mean right
+    /// now (several after an If whose branches each declare it).
+    names: HashMap<String, Vec<usize>>,
+    found: Vec<AError>,
+}
+
+impl Walker {
+    /// Check `main` or one function, then report its unused variables.
+    fn body(&mut self, params: &[(String, String, Span)], stmts: &[Stmt]) {
+        self.vars.clear();
+        self.names.clear();
+        for (name, _, span) in params {
+            self.declare(name, VarKind::Param, *span);
+        }
+        self.stmts(stmts);
+
+        for v in &self.vars {
+            if v.read || v.name.starts_with('_') {
+                continue;
+            }
+            if !v.written {
+                self.found.push(w001_unused(v.span, &v.name, v.kind));
+            } else if matches!(v.kind, VarKind::Variable | VarKind::Param) {
+                self.found.push(w002_never_read(v.span, &v.name));
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &str, kind: VarKind, span: Span) {
+        if name == "_" {
+            return;
+        }
+        if let Some(&old) = self.names.get(name).and_then(|ids| ids.first()) {
+            let old = &self.vars[old];
+            // Reusing a loop variable's name for the next loop is normal.
+            let hides = kind == VarKind::Variable
+                || matches!(old.kind, VarKind::Variable | VarKind::Param);
+            if hides {
+                self.found.push(w005_shadowed(span, name, old.span));
+            }
+        }
+        self.names.insert(name.to_string(), vec![self.vars.len()]);
+        self.vars.push(Var {
+            name: name.to_string(),
+            kind,
+            span,
+            read: false,
+            written: false,
+        });
+    }
+
+    fn write(&mut self, name: &str) {
+        for &i in self.names.get(name).into_iter().flatten() {
+            self.vars[i].written = true;
+        }
+    }
+
+    fn read(&mut self, name: &str) {
+        for &i in self.names.get(name).into_iter().flatten() {
+            self.vars[i].read = true;
+        }
+    }
+
+    /// Afterwards a name means what it meant at the end of any of `paths`
+    /// (the names after each branch of an If or Match that may have run).
+    fn join(&mut self, paths: Vec<HashMap<String, Vec<usize>>>) {
+        let mut joined: HashMap<String, Vec<usize>> = HashMap::new();
+        for path in paths {
+            for (name, ids) in path {
+                let entry = joined.entry(name).or_default();
+                for i in ids {
+                    if !entry.contains(&i) {
+                        entry.push(i);
+                    }
+                }
+            }
+        }
+        self.names = joined;
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for (i, s) in stmts.iter().enumerate() {
+            self.stmt(s);
+            if matches!(s, Stmt::Return { .. }) {
+                if let (Some(first), Some(last)) = (stmts.get(i + 1), stmts.last()) {
+                    let span = Span {
+                        start: stmt_span(first).start,
+                        end: stmt_span(last).end,
+                    };
+                    self.found.push(w003_never_runs(span, "after_return"));
+                }
+                // Variables used after it still count as used.
+                for s in &stmts[i + 1..] {
+                    self.stmt(s);
+                }
+                return;
+            }
+        }
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Let { name, expr, span, .. } | Stmt::Mute { name, expr, span, .. } => {
+                self.expr(expr);
+                self.declare(name, VarKind::Variable, *span);
+            }
+            Stmt::Assign { name, expr, span } => {
+                self.expr(expr);
+                if self.names.contains_key(name) {
+                    self.write(name);
+                } else {
+                    self.declare(name, VarKind::Variable, *span);
+                }
+            }
+            Stmt::SetIndex {
+                name, index, expr, ..
+            } => {
+                self.expr(index);
+                self.expr(expr);
+                self.write(name);
+            }
+            Stmt::SetField { name, expr, .. } => {
+                self.expr(expr);
+                self.write(name);
+            }
+            Stmt::If {
+                first,
+                elseifs,
+                else_body,
+                ..
+            } => self.if_chain(first, elseifs, else_body.as_deref()),
+            Stmt::Match { expr, arms, .. } => {
+                self.expr(expr);
+                // Only one case runs, so a name declared in several isn't declared again.
+                let before = self.names.clone();
+                let mut paths = Vec::new();
+                for arm in arms {
+                    self.names = before.clone();
+                    for b in &arm.bindings {
+                        self.declare(b, VarKind::Binding, arm.span);
+                    }
+                    self.stmts(&arm.body);
+                    paths.push(std::mem::take(&mut self.names));
+                }
+                if arms.is_empty() {
+                    paths.push(before);
+                }
+                self.join(paths);
+            }
+            Stmt::For {
+                var,
+                iter,
+                body,
+                span,
+            } => {
+                self.expr(iter);
+                let header = Span {
+                    start: span.start,
+                    end: expr_span(iter).end,
+                };
+                self.declare(var, VarKind::LoopVar, header);
+                self.stmts(body);
+            }
+            Stmt::Return { expr, .. } => {
+                if let Some(e) = expr {
+                    self.expr(e);
+                }
+            }
+            Stmt::Expr(e) => self.expr(e),
+        }
+    }
+
+    fn if_chain(&mut self, first: &IfBranch, elseifs: &[IfBranch], else_body: Option<&[Stmt]>) {
+        // Once a condition is always true, nothing after it in the chain runs.
+        let branches: Vec<&IfBranch> = std::iter::once(first).chain(elseifs).collect();
+        let mut always_taken = false;
+        // Only one branch runs, so a name declared in several isn't declared again.
+        let before = self.names.clone();
+        let mut paths = Vec::new();
+        for (i, br) in branches.iter().enumerate() {
+            self.names = before.clone();
+            self.expr(&br.cond);
+            match optimize::constant_condition(&br.cond) {
+                _ if always_taken => {}
+                Some(true) => {
+                    always_taken = true;
+                    let rest = match branches.get(i + 1) {
+                        Some(next) => Some(next.span),
+                        None => else_body.and_then(block_span),
+                    };
+                    if let Some(span) = rest {
+                        self.found.push(w003_never_runs(span, "always_true"));
+                    }
+                }
+                Some(false) => {
+                    if let Some(span) = block_span(&br.body) {
+                        self.found.push(w003_never_runs(span, "always_false"));
+                    }
+                }
+                None => {}
+            }
+            self.stmts(&br.body);
+            paths.push(std::mem::take(&mut self.names));
+        }
+        self.names = before;
+        if let Some(body) = else_body {
+            self.stmts(body);
+        }
+        paths.push(std::mem::take(&mut self.names));
+        self.join(paths);
+    }
+
+    fn expr(&mut self, e: &Expr) {
+        match e {
+            Expr::Var(name, _) => self.read(name),
+            Expr::Add(a, b, _) => {
+                self.expr(a);
+                self.expr(b);
+            }
+            Expr::Cmp(a, op, b, span) => {
+                self.expr(a);
+                self.expr(b);
+                if same_value(a, b) {
+                    let always = matches!(op, CmpOp::Eq | CmpOp::Le | CmpOp::Ge);
+                    self.found.push(w004_compare_with_itself(*span, always));
+                }
+            }
+            Expr::Call(_, args, _) | Expr::List(args, _) => {
+                for a in args {
+                    self.expr(a);
+                }
+            }
+            Expr::Index(a, b, _) => {
+                self.expr(a);
+                self.expr(b);
+            }
+            Expr::Map(entries, _) => {
+                for (k, v) in entries {
+                    self.expr(k);
+                    self.expr(v);
+                }
+            }
+            Expr::Record(_, fields, _) => {
+                for (_, v, _) in fields {
+                    self.expr(v);
+                }
+            }
+            Expr::Field(target, _, _) => self.expr(target),
+            Expr::Int(..) | Expr::Float(..) | Expr::Str(..) | Expr::Char(..) | Expr::Bool(..) => {}
+        }
+    }
+}
+
+/// Whether two expressions always give the same value: the same variable,
+/// field or item (calls may give something new each time, so never count).
+fn same_value(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Var(x, _), Expr::Var(y, _)) => x == y,
+        (Expr::Field(x, f, _), Expr::Field(y, g, _)) => f == g && same_value(x, y),
+        (Expr::Index(x, i, _), Expr::Index(y, j, _)) => same_value(x, y) && same_value(i, j),
+        (Expr::Int(x, _), Expr::Int(y, _)) => x == y,
+        (Expr::Str(x, _), Expr::Str(y, _)) => x == y,
+        (Expr::Char(x, _), Expr::Char(y, _)) => x == y,
+        (Expr::Bool(x, _), Expr::Bool(y, _)) => x == y,
+        _ => false,
+    }
+}
+
+/* =========================
+   Warning lessons
+   ========================= */
+
+fn w001_unused(span: Span, name: &str, kind: VarKind) -> AError {
+    const KEY: &str = "w001_unused";
+    let what = match kind {
+        VarKind::Variable => "what_variable",
+        VarKind::Param => "what_param",
+        VarKind::LoopVar => "what_loop_var",
+        VarKind::Binding => "what_binding",
+    };
+    let what = i18n::text(KEY, what, &[]);
+    AError::lesson(span, KEY, &[("what", &what), ("name", &name)])
+}
+
+fn w002_never_read(span: Span, name: &str) -> AError {
+    AError::lesson(span, "w002_never_read", &[("name", &name)])
+}
+
+/// `why`: "after_return", "always_true" or "always_false".
+fn w003_never_runs(span: Span, why: &str) -> AError {
+    const KEY: &str = "w003_never_runs";
+    let mut e = AError::lesson(span, KEY, &[]);
+    e.mental_model = i18n::text(KEY, &format!("why_{}", why), &[]);
+    e
+}
+
+fn w004_compare_with_itself(span: Span, always: bool) -> AError {
+    AError::lesson(span, "w004_compare_with_itself", &[("value", &always)])
+}
+
+fn w005_shadowed(span: Span, name: &str, earlier: Span) -> AError {
+    const KEY: &str = "w005_shadowed";
+    let mut e = AError::lesson(span, KEY, &[("name", &name)]);
+    e.labels.push((earlier, i18n::text(KEY, "first_declared", &[("name", &name)])));
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program;
+
+    /// Codes reported for a `main` with `body`, and whether each is an error.
+    fn found(body: &str, config: &LintConfig) -> Vec<(String, bool)> {
+        let src = format!("Func main() {{\n{}}}\n", body);
+        let program = parse_program(&pipeline::strip_line_comments_preserve_len(&src))
+            .unwrap_or_else(|_| panic!("parses:\n{}", src));
+        check(&program, &src, config)
+            .into_iter()
+            .map(|d| (d.lesson.code, d.severity == Severity::Error))
+            .collect()
+    }
+
+    fn codes(body: &str) -> Vec<String> {
+        found(body, &LintConfig::default()).into_iter().map(|(c, _)| c).collect()
+    }
+
+    #[test]
+    fn w001_unused_variable() {
+        assert_eq!(codes("    x = 1\n"), ["W001"]);
+        assert!(codes("    x = 1\n    Print(x)\n").is_empty());
+        assert!(codes("    _x = 1\n").is_empty());
+    }
+
+    #[test]
+    fn w002_values_never_read() {
+        assert_eq!(codes("    Let mut x = 1\n    x = 2\n"), ["W002"]);
+        assert!(codes("    Let mut x = 1\n    x = x + 1\n    Print(x)\n").is_empty());
+    }
+
+    #[test]
+    fn w003_code_that_never_runs() {
+        assert_eq!(codes("    If 1 > 2 then {\n        Print(1)\n    }\n"), ["W003"]);
+        assert_eq!(codes("    Return\n    Print(1)\n"), ["W003"]);
+        assert!(codes("    x = ReadLine()\n    If x == \"y\" then {\n        Print(1)\n    }\n").is_empty());
+    }
+
+    #[test]
+    fn w004_comparison_with_itself() {
+        assert_eq!(codes("    x = 1\n    Print(x == x)\n"), ["W004"]);
+        assert!(codes("    x = 1\n    y = 2\n    Print(x == y)\n").is_empty());
+    }
+
+    #[test]
+    fn w005_declared_again() {
+        assert_eq!(codes("    x = 1\n    Print(x)\n    Let x = 2\n    Print(x)\n"), ["W005"]);
+        assert!(codes("    For i in [1] {\n        Print(i)\n    }\n    For i in [2] {\n        Print(i)\n    }\n").is_empty());
+    }
+
+    #[test]
+    fn allow_comments_silence_their_line() {
+        assert!(codes("    x = 1 // a:allow(W001)\n").is_empty());
+        assert!(codes("    // a:allow(w001, W004)\n    x = 1\n").is_empty());
+        // Only the next line is covered.
+        assert_eq!(codes("    // a:allow(W001)\n    Print(1)\n    x = 1\n"), ["W001"]);
+        // `//` inside a literal doesn't start a comment.
+        assert_eq!(codes("    x = \"// a:allow(W001)\"\n"), ["W001"]);
+        assert_eq!(codes("    x = '/' // a:allow(W001)\n").len(), 0);
+        let starts = |src: &str| -> Vec<usize> {
+            pipeline::line_comments(src).into_iter().map(|c| c.start).collect()
+        };
+        assert_eq!(starts("s = \"a//b\" // c\nt = 1 // e"), [11, 22]);
+        assert_eq!(starts("c = '\"' // d"), [8]);
+    }
+
+    #[test]
+    fn allow_and_deny_change_the_level() {
+        let mut config = LintConfig::default();
+        assert_eq!(found("    x = 1\n", &config), [("W001".to_string(), false)]);
+        config.set("w001", Level::Deny);
+        assert_eq!(found("    x = 1\n", &config), [("W001".to_string(), true)]);
+        config.set("all", Level::Allow);
+        assert!(found("    x = 1\n", &config).is_empty());
+        assert!(parse_code("W009").is_err());
+        assert_eq!(parse_code("all"), Ok("ALL".to_string()));
+    }
+}
@@ -1,44 +1,125 @@
 // src/main.rs
 
-use clap::{Parser as ClapParser, Subcommand};
-use std::{fs, path::PathBuf};
+use clap::{Args, Parser as ClapParser, Subcommand, ValueEnum};
+use optimize::OptLevel;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 mod analysis;
+mod asm;
 mod ast;
+mod builtins;
 mod bytecode;
 mod compiler;
+mod debugger;
 mod diag;
+mod i18n;
+mod link;
+mod lint;
+mod modules;
+mod optimize;
 mod parser;
 mod pipeline;
+mod project;
 mod update;
+mod verify;
 mod vm;
 
 #[derive(ClapParser)]
 #[command(name = "a")]
 struct Cli {
+    /// Language of error messages (e.g. `en`, `es`); defaults to `$A_LANG`
+    #[arg(long, global = true, value_name = "LANG", value_parser = i18n::parse_language)]
+    lang: Option<&'static str>,
+    /// How much to explain each error; defaults to `$A_EXPLAIN`, else `full`
+    #[arg(long, global = true, value_name = "LEVEL", value_enum)]
+    explain: Option<diag::Explain>,
     #[command(subcommand)]
     cmd: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Run a .a source file or .a.byte bytecode file
+    /// Run a .a source file or .a.byte bytecode file (default: the project's entry)
     Run {
-        input: PathBuf,
+        input: Option<PathBuf>,
         /// Force running from source even if .a.byte exists
         #[arg(long)]
         fresh: bool,
+        /// Stop after this many executed instructions (0 = no limit)
+        #[arg(long, value_name = "N")]
+        max_steps: Option<u64>,
+        /// Stop when the value stack grows past this many values (0 = no limit)
+        #[arg(long, value_name = "N")]
+        max_stack: Option<usize>,
+        /// Log every executed instruction to stderr (`--trace=json` for JSON lines)
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "text"
+        )]
+        trace: Option<vm::TraceFormat>,
+        #[command(flatten)]
+        lints: LintArgs,
     },
-    /// Build only (compile) a .a source file to .a.byte bytecode
+    /// Build only (compile) a .a source file to .a.byte bytecode (default: the project's entry)
     Build {
-        input: PathBuf,
+        input: Option<PathBuf>,
         /// Output path for bytecode
         #[arg(short, long)]
         out: Option<PathBuf>,
         /// Run after building
         #[arg(long)]
         run: bool,
+        /// Store the source text inside the bytecode (shown by `a debug`)
+        #[arg(long)]
+        embed_source: bool,
+        /// Output format: binary bytecode, or readable `.a.asm` assembly
+        #[arg(long, value_name = "FORMAT", default_value = "bytecode")]
+        emit: pipeline::EmitFormat,
+        /// Optimization level: -O0 compiles exactly what you wrote, -O1 folds constants
+        /// and removes dead branches
+        #[arg(
+            short = 'O',
+            value_name = "LEVEL",
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u8).range(0..=1)
+        )]
+        opt_level: u8,
+        #[command(flatten)]
+        lints: LintArgs,
+    },
+    /// Assemble a .a.asm file into .a.byte bytecode
+    Asm {
+        input: PathBuf,
+        /// Output path for bytecode
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        /// Run after assembling
+        #[arg(long)]
+        run: bool,
+    },
+    /// Step through a .a source file (or .a.byte) line by line (default: the project's entry)
+    Debug {
+        input: Option<PathBuf>,
+        #[command(flatten)]
+        lints: LintArgs,
     },
+    /// Run tests: each .a file must run without an error and print what its .out file
+    /// holds, if it has one (default: the project's tests folder)
+    Test {
+        path: Option<PathBuf>,
+        #[command(flatten)]
+        lints: LintArgs,
+    },
+    /// Create a project in a new folder, with a.toml, src/main.a and a sample test
+    New { name: String },
+    /// Make the current folder a project by adding a.toml
+    Init,
     /// Update A from GitHub Releases
     Update {
         /// Repo in the form owner/name (overrides A_UPDATE_REPO)
@@ -50,49 +131,180 @@ enum Commands {
     },
 }
 
+/// Which warnings to report (see `lint.rs`).
+#[derive(Args)]
+struct LintArgs {
+    /// Don't report this warning (e.g. `--allow W001`, or `--allow all`)
+    #[arg(long, value_name = "CODE", value_parser = lint::parse_code)]
+    allow: Vec<String>,
+    /// Treat this warning as an error (e.g. `--deny W003`, or `--deny all`)
+    #[arg(long, value_name = "CODE", value_parser = lint::parse_code)]
+    deny: Vec<String>,
+}
+
+impl LintArgs {
+    /// The project's `[lints]` settings, with these flags on top.
+    fn config(&self, project: Option<&project::Project>) -> lint::LintConfig {
+        let mut config = lint::LintConfig::default();
+        let mut apply = |allow: &[String], deny: &[String]| {
+            for code in allow {
+                config.set(code, lint::Level::Allow);
+            }
+            for code in deny {
+                config.set(code, lint::Level::Deny);
+            }
+        };
+        if let Some(p) = project {
+            apply(&p.lints.allow, &p.lints.deny);
+        }
+        apply(&self.allow, &self.deny);
+        config
+    }
+}
+
+/// The language `$A_LANG` asks for; English if it's unset or unknown.
+fn env_language() -> &'static str {
+    match std::env::var(i18n::LANG_ENV) {
+        Ok(name) if !name.is_empty() => i18n::parse_language(&name).unwrap_or_else(|msg| {
+            eprintln!("A_LANG: {}; using English.", msg);
+            "en"
+        }),
+        _ => "en",
+    }
+}
+
+/// The `--explain` level `$A_EXPLAIN` asks for; `full` if it's unset or unknown.
+fn env_explain() -> diag::Explain {
+    match std::env::var(diag::EXPLAIN_ENV) {
+        Ok(name) if !name.is_empty() => diag::Explain::from_str(&name, true).unwrap_or_else(|_| {
+            eprintln!("A_EXPLAIN: unknown level `{}` (available: short, normal, full); using full.", name);
+            diag::Explain::Full
+        }),
+        _ => diag::Explain::Full,
+    }
+}
+
 fn main() {
     let args = Cli::parse();
+    i18n::set_language(args.lang.unwrap_or_else(env_language));
+    diag::set_explain(args.explain.unwrap_or_else(env_explain));
 
     match args.cmd {
-        Commands::Run { input, fresh } => run_cmd(input, fresh),
-        Commands::Build { input, out, run } => build_cmd(input, out, run),
+        Commands::Run {
+            input,
+            fresh,
+            max_steps,
+            max_stack,
+            trace,
+            lints,
+        } => run_cmd(setup(input, &lints), fresh, vm_limits(max_steps, max_stack), trace),
+        Commands::Build {
+            input,
+            out,
+            run,
+            embed_source,
+            emit,
+            opt_level,
+            lints,
+        } => build_cmd(
+            setup(input, &lints),
+            out,
+            run,
+            embed_source,
+            emit,
+            OptLevel::from_level(opt_level),
+        ),
+        Commands::Asm { input, out, run } => asm_cmd(input, out, run),
+        Commands::Debug { input, lints } => debug_cmd(setup(input, &lints)),
+        Commands::Test { path, lints } => test_cmd(path, &lints),
+        Commands::New { name } => new_cmd(&name),
+        Commands::Init => init_cmd(),
         Commands::Update { repo, check } => update_cmd(repo, check),
     }
 }
 
-fn run_cmd(input: PathBuf, fresh: bool) {
+/// The project the current folder is in, if any, or exit if its `a.toml` is broken.
+fn current_project() -> Option<project::Project> {
+    project::find().unwrap_or_else(|msg| {
+        eprintln!("A_PROJECT: {}", msg);
+        std::process::exit(1);
+    })
+}
+
+/// What a command that compiles code needs, from the command line and the
+/// project it runs in (if any).
+struct Setup {
+    /// The file given, else the project's entry.
+    input: PathBuf,
+    lints: lint::LintConfig,
+    /// More folders to look for imports in.
+    paths: Vec<PathBuf>,
+}
+
+/// The project is only looked for when no file is given: a file named on the
+/// command line is run on its own, whatever `a.toml` is around it.
+fn setup(input: Option<PathBuf>, lints: &LintArgs) -> Setup {
+    let project = if input.is_none() { current_project() } else { None };
+    let input = input
+        .or_else(|| project.as_ref().map(|p| p.entry.clone()))
+        .unwrap_or_else(|| {
+            eprintln!(
+                "A_PROJECT: no file given, and no {} in this folder or above it. Name a .a file, or make a project with `a new <name>`.",
+                project::MANIFEST
+            );
+            std::process::exit(1);
+        });
+    Setup {
+        input,
+        lints: lints.config(project.as_ref()),
+        paths: project.map(|p| p.paths).unwrap_or_default(),
+    }
+}
+
+fn vm_limits(max_steps: Option<u64>, max_stack: Option<usize>) -> vm::VmLimits {
+    let mut limits = vm::VmLimits::default();
+    if let Some(n) = max_steps {
+        limits.max_steps = if n == 0 { None } else { Some(n) };
+    }
+    if let Some(n) = max_stack {
+        limits.max_stack = if n == 0 { None } else { Some(n) };
+    }
+    limits
+}
+
+fn run_cmd(setup: Setup, fresh: bool, limits: vm::VmLimits, trace: Option<vm::TraceFormat>) {
+    let Setup { input, lints, paths } = setup;
     if is_bytecode(&input) {
         if fresh {
             eprintln!("A_RUN: --fresh is ignored for bytecode inputs.");
         }
-        run_bytecode(&input);
+        run_bytecode(&input, limits, trace);
         return;
     }
 
     let bytecode_path = input.with_extension(bytecode::BYTECODE_EXT);
     if !fresh && bytecode_path.exists() {
-        run_bytecode(&bytecode_path);
+        run_bytecode(&bytecode_path, limits, trace);
         return;
     }
 
-    let src = read_text(&input);
-    let file_name = input.display().to_string();
-    let cleaned = pipeline::strip_line_comments_preserve_len(&src);
-
-    let program = match parser::parse_program(&cleaned) {
-        Ok(p) => p,
-        Err(parse_diag) => {
-            diag::render_parse_error(&src, &file_name, &parse_diag);
-            std::process::exit(1);
-        }
-    };
+    let graph = load_modules(&input, &paths);
 
-    if pipeline::compile_and_maybe_run(&src, &file_name, &program, true, None).is_err() {
+    let run = pipeline::RunOptions { limits, trace };
+    if pipeline::compile_and_maybe_run(&graph, OptLevel::O1, &lints, None, Some(run)).is_err() {
         std::process::exit(1);
     }
 }
 
-fn build_cmd(input: PathBuf, out: Option<PathBuf>, run: bool) {
+fn build_cmd(
+    setup: Setup,
+    out: Option<PathBuf>,
+    run: bool,
+    embed_source: bool,
+    emit: pipeline::EmitFormat,
+    opt: OptLevel,
+) {
+    let Setup { input, lints, paths } = setup;
     if is_bytecode(&input) {
         eprintln!(
             "A_BUILD: input is already bytecode ({}). Provide a .a source file.",
@@ -101,49 +313,133 @@ fn build_cmd(input: PathBuf, out: Option<PathBuf>, run: bool) {
         std::process::exit(1);
     }
 
-    let src = read_text(&input);
-    let file_name = input.display().to_string();
-    let cleaned = pipeline::strip_line_comments_preserve_len(&src);
+    let graph = load_modules(&input, &paths);
 
-    let program = match parser::parse_program(&cleaned) {
-        Ok(p) => p,
-        Err(parse_diag) => {
-            diag::render_parse_error(&src, &file_name, &parse_diag);
+    let out_path = out.unwrap_or_else(|| match emit {
+        pipeline::EmitFormat::Bytecode => input.with_extension(bytecode::BYTECODE_EXT),
+        pipeline::EmitFormat::Asm => input.with_extension(asm::ASM_EXT),
+    });
+    let run = run.then(|| pipeline::RunOptions {
+        limits: vm::VmLimits::default(),
+        trace: None,
+    });
+    let emit = pipeline::EmitOptions {
+        path: &out_path,
+        format: emit,
+        embed_source,
+    };
+    if pipeline::compile_and_maybe_run(&graph, opt, &lints, Some(emit), run).is_err() {
+        std::process::exit(1);
+    }
+}
+
+fn asm_cmd(input: PathBuf, out: Option<PathBuf>, run: bool) {
+    let text = read_text(&input);
+    let chunk = match asm::assemble(&text) {
+        Ok(c) => c,
+        Err(msg) => {
+            eprintln!("A_ASM: {}: {}", input.display(), msg);
             std::process::exit(1);
         }
     };
+    // Hand-written code gets the same check as a loaded `.a.byte` file.
+    if let Err(msg) = verify::verify_chunk(&chunk) {
+        eprintln!("A_ASM: {}: {}", input.display(), msg);
+        std::process::exit(1);
+    }
 
-    let out_path = out.unwrap_or_else(|| input.with_extension(bytecode::BYTECODE_EXT));
-    if pipeline::compile_and_maybe_run(&src, &file_name, &program, run, Some(&out_path)).is_err() {
+    let out_path = out.unwrap_or_else(|| {
+        let name = input.display().to_string();
+        let stem = name.strip_suffix(asm::ASM_SUFFIX).unwrap_or(&name);
+        PathBuf::from(format!("{}{}", stem, bytecode::BYTECODE_SUFFIX))
+    });
+    let file = bytecode::BytecodeFile {
+        meta: bytecode::BytecodeMeta::new(Some(input.display().to_string()), None),
+        chunk,
+    };
+    if pipeline::write_bytecode(&out_path, &file).is_err() {
+        std::process::exit(1);
+    }
+    println!("Assembled {} into {}", input.display(), out_path.display());
+
+    if run {
+        let mut m = vm::Vm::with_limits(vm::VmLimits::default());
+        if let Err(msg) = m.run(&file.chunk) {
+            eprintln!("A_VM: {}", msg);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn debug_cmd(setup: Setup) {
+    let Setup { input, lints, paths } = setup;
+    let (chunk, sources) = if is_bytecode(&input) {
+        let file = load_bytecode(&input);
+        (file.chunk, vec![file.meta.source])
+    } else {
+        let graph = load_modules(&input, &paths);
+
+        // Debug what was written: no folding, so every line is still there to step on.
+        match pipeline::compile(&graph, OptLevel::O0, &lints) {
+            Ok(chunk) => {
+                let sources = graph.modules.iter().map(|m| Some(m.src.clone())).collect();
+                (chunk, sources)
+            }
+            Err(()) => std::process::exit(1),
+        }
+    };
+
+    if let Err(msg) = debugger::run(&chunk, &sources, vm::VmLimits::default()) {
+        eprintln!("A_VM: {}", msg);
         std::process::exit(1);
     }
 }
 
-fn run_bytecode(path: &PathBuf) {
+/// Load a source file and everything it imports, or exit after reporting why not.
+fn load_modules(path: &Path, search: &[PathBuf]) -> modules::ModuleGraph {
+    modules::load(path, search).unwrap_or_else(|()| std::process::exit(1))
+}
+
+fn load_bytecode(path: &Path) -> bytecode::BytecodeFile {
     let data = read_bytes(path);
-    let chunk = match bytecode::decode_chunk(&data) {
-        Ok(c) => c,
-        Err(msg) => {
-            eprintln!("A_BYTECODE: {}", msg);
+    let file = match bytecode::decode_file(&data) {
+        Ok(f) => f,
+        Err(err) => {
+            eprintln!("A_BYTECODE: {}: {}", path.display(), err);
             std::process::exit(1);
         }
     };
 
-    let mut m = vm::Vm::new();
+    if let Err(msg) = verify::verify_chunk(&file.chunk) {
+        eprintln!(
+            "A_BYTECODE: {} is not valid bytecode: {}\nRebuild it from source with `a build`.",
+            path.display(),
+            msg
+        );
+        std::process::exit(1);
+    }
+    file
+}
+
+fn run_bytecode(path: &Path, limits: vm::VmLimits, trace: Option<vm::TraceFormat>) {
+    let chunk = load_bytecode(path).chunk;
+
+    let mut m = vm::Vm::with_limits(limits);
+    m.set_trace(trace);
     if let Err(msg) = m.run(&chunk) {
         eprintln!("A_VM: {}", msg);
         std::process::exit(1);
     }
 }
 
-fn is_bytecode(path: &PathBuf) -> bool {
+fn is_bytecode(path: &Path) -> bool {
     path.file_name()
         .and_then(|s| s.to_str())
         .map(|s| s.to_ascii_lowercase().ends_with(bytecode::BYTECODE_SUFFIX))
         .unwrap_or(false)
 }
 
-fn read_text(path: &PathBuf) -> String {
+fn read_text(path: &Path) -> String {
     match fs::read_to_string(path) {
         Ok(s) => s.replace("\r\n", "\n"),
         Err(e) => {
@@ -153,7 +449,7 @@ fn read_text(path: &PathBuf) -> String {
     }
 }
 
-fn read_bytes(path: &PathBuf) -> Vec<u8> {
+fn read_bytes(path: &Path) -> Vec<u8> {
     match fs::read(path) {
         Ok(b) => b,
         Err(e) => {
@@ -163,6 +459,116 @@ fn read_bytes(path: &PathBuf) -> Vec<u8> {
     }
 }
 
+fn test_cmd(path: Option<PathBuf>, lints: &LintArgs) {
+    // Like `setup`: tests named on the command line run on their own.
+    let project = if path.is_none() { current_project() } else { None };
+    if let Some(p) = &project {
+        println!("Testing {} {}", p.name, p.version);
+    }
+    let path = path
+        .or_else(|| project.as_ref().map(project::Project::tests_dir))
+        .unwrap_or_else(|| {
+            eprintln!(
+                "A_PROJECT: no tests given, and no {} in this folder or above it. Name a test file or folder.",
+                project::MANIFEST
+            );
+            std::process::exit(1);
+        });
+    let files = project::test_files(&path).unwrap_or_else(|msg| {
+        eprintln!("A_TEST: {}", msg);
+        std::process::exit(1);
+    });
+    if files.is_empty() {
+        println!("No tests in {}", path.display());
+        return;
+    }
+
+    let config = lints.config(project.as_ref());
+    let paths = project.as_ref().map(|p| p.paths.clone()).unwrap_or_default();
+    let mut failed = 0;
+    for file in &files {
+        match run_test(file, &config, &paths) {
+            Ok(()) => println!("test {} ... ok", file.display()),
+            Err(why) => {
+                failed += 1;
+                println!("test {} ... FAILED", file.display());
+                if !why.is_empty() {
+                    println!("{}", why);
+                }
+            }
+        }
+    }
+    println!("\n{} passed, {} failed", files.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Check and run one test. `Err` says why it failed (empty when the errors
+/// have been shown already).
+fn run_test(file: &Path, lints: &lint::LintConfig, paths: &[PathBuf]) -> Result<(), String> {
+    let graph = modules::load(file, paths).map_err(|()| String::new())?;
+    let chunk = pipeline::compile(&graph, OptLevel::O1, lints).map_err(|()| String::new())?;
+
+    // The program reads its `.in` file (or nothing) instead of stdin, and what
+    // it prints is kept to compare with its `.out` file.
+    let input = file.with_extension("in");
+    let input = if input.is_file() {
+        fs::read_to_string(&input)
+            .map_err(|e| format!("A_IO: failed to read {}: {}", input.display(), e))?
+            .replace("\r\n", "\n")
+    } else {
+        String::new()
+    };
+    let mut m = vm::Vm::with_limits(vm::VmLimits::default());
+    m.give_input(&input);
+    m.capture_output();
+    let result = m.run(&chunk);
+    let stdout = m.take_output();
+    if let Err(msg) = result {
+        return Err(format!("{}A_VM: {}", stdout, msg));
+    }
+
+    let expected_path = file.with_extension("out");
+    if expected_path.is_file() {
+        let expected = read_text(&expected_path);
+        if stdout != expected {
+            return Err(format!(
+                "expected output ({}):\n{}\nbut it printed:\n{}",
+                expected_path.display(),
+                expected,
+                stdout
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn new_cmd(name: &str) {
+    match project::create(name) {
+        Ok(dir) => println!(
+            "Created project `{}` in {}. Try `cd {}` and then `a run` or `a test`.",
+            name,
+            dir.display(),
+            dir.display()
+        ),
+        Err(msg) => {
+            eprintln!("A_PROJECT: {}", msg);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn init_cmd() {
+    match project::init() {
+        Ok(name) => println!("Created {} for project `{}`.", project::MANIFEST, name),
+        Err(msg) => {
+            eprintln!("A_PROJECT: {}", msg);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn update_cmd(repo: Option<String>, check: bool) {
     if let Err(msg) = update::run(repo, check) {
         eprintln!("A_UPDATE: {}", msg);
@@ -0,0 +1,336 @@
+// src/modules.rs
+//
+// Find and parse every file a program imports, starting from the file being
+// run. Imports are relative to the importing file, a file imported from
+// several places is loaded once, and imports that go round in a circle are
+// reported as a lesson showing the circle. An import that isn't next to the
+// importing file is looked for in the project's search folders (see
+// `project.rs`). The prelude (`prelude.a`) is loaded with every program.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::{self, AError};
+use crate::ast::{Program, Span};
+use crate::{builtins, diag, parser, pipeline};
+
+pub struct Module {
+    /// Name other files call it by (`None` for the file being run).
+    pub name: Option<String>,
+    /// Path as shown in messages.
+    pub file_name: String,
+    pub src: String,
+    pub program: Program,
+    /// Module name used in this file -> index in `ModuleGraph::modules`.
+    pub imports: HashMap<String, usize>,
+    path: PathBuf,
+}
+
+pub struct ModuleGraph {
+    /// `modules[0]` is the file being run.
+    pub modules: Vec<Module>,
+    /// Every module index, each file after the files it imports.
+    pub order: Vec<usize>,
+    /// Index of the prelude, which every other file uses without importing it.
+    pub prelude: usize,
+}
+
+/// Load `entry` and everything it imports, reporting any problem to stderr.
+/// `search`: more folders to look for imports in, after the importing file's.
+pub fn load(entry: &Path, search: &[PathBuf]) -> Result<ModuleGraph, ()> {
+    match fs::read_to_string(entry) {
+        Ok(src) => load_source(entry, src.replace("\r\n", "\n"), search),
+        Err(e) => {
+            eprintln!("A_IO: failed to read {}: {}", entry.display(), e);
+            Err(())
+        }
+    }
+}
+
+/// Like `load`, with the text of `entry` already read.
+pub fn load_source(entry: &Path, src: String, search: &[PathBuf]) -> Result<ModuleGraph, ()> {
+    let file_name = entry.display().to_string();
+    let program = parse(&src, &file_name)?;
+    if program.main.is_none() {
+        let e = analysis::a036_no_main(Span { start: 0, end: 0 });
+        diag::render_lesson_error(&src, &file_name, &e);
+        return Err(());
+    }
+
+    let mut loader = Loader {
+        modules: Vec::new(),
+        by_path: HashMap::new(),
+        order: Vec::new(),
+        stack: Vec::new(),
+        search: search.to_vec(),
+    };
+    let key = fs::canonicalize(entry).unwrap_or_else(|_| entry.to_path_buf());
+    loader.add(None, key, entry.to_path_buf(), file_name, src, program);
+
+    // The prelude imports nothing, so it can go first.
+    let program = parse(builtins::PRELUDE_SRC, builtins::PRELUDE_FILE)?;
+    let prelude = loader.add(
+        Some("prelude".to_string()),
+        PathBuf::from(builtins::PRELUDE_FILE),
+        PathBuf::new(),
+        builtins::PRELUDE_FILE.to_string(),
+        builtins::PRELUDE_SRC.to_string(),
+        program,
+    );
+    loader.order.push(prelude);
+    loader.visit(0)?;
+
+    Ok(ModuleGraph {
+        modules: loader.modules,
+        order: loader.order,
+        prelude,
+    })
+}
+
+impl ModuleGraph {
+    /// Module `i`'s program plus the structs and enums of every file it
+    /// imports (directly or not), so it can use their values. Its own
+    /// declarations win any clash of names.
+    pub fn with_shared_types(&self, i: usize) -> Program {
+        let mut program = self.modules[i].program.clone();
+        let mut seen = HashSet::from([i]);
+        let mut work: Vec<usize> = self.modules[i].imports.values().copied().collect();
+        while let Some(j) = work.pop() {
+            if !seen.insert(j) {
+                continue;
+            }
+            let other = &self.modules[j];
+            work.extend(other.imports.values().copied());
+
+            for decl in &other.program.structs {
+                if !program.structs.iter().any(|s| s.name == decl.name) {
+                    program.structs.push(decl.clone());
+                }
+            }
+            for decl in &other.program.enums {
+                let clash = program.enums.iter().any(|e| {
+                    e.name == decl.name
+                        || e.variants
+                            .iter()
+                            .any(|(v, _, _)| decl.variants.iter().any(|(w, _, _)| v == w))
+                });
+                if !clash {
+                    program.enums.push(decl.clone());
+                }
+            }
+        }
+        program
+    }
+}
+
+struct Loader {
+    modules: Vec<Module>,
+    /// Canonical path -> module index, so each file is loaded once.
+    by_path: HashMap<PathBuf, usize>,
+    order: Vec<usize>,
+    /// Files whose imports are being loaded right now (to spot circles).
+    stack: Vec<usize>,
+    /// Folders to look for imports in when they aren't next to the importer.
+    search: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn add(
+        &mut self,
+        name: Option<String>,
+        key: PathBuf,
+        path: PathBuf,
+        file_name: String,
+        src: String,
+        program: Program,
+    ) -> usize {
+        let idx = self.modules.len();
+        self.by_path.insert(key, idx);
+        self.modules.push(Module {
+            name,
+            file_name,
+            src,
+            program,
+            imports: HashMap::new(),
+            path,
+        });
+        idx
+    }
+
+    /// Load everything module `idx` imports, then list it after those files.
+    fn visit(&mut self, idx: usize) -> Result<(), ()> {
+        self.stack.push(idx);
+        let dir = self.modules[idx]
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        for import in self.modules[idx].program.imports.clone() {
+            let (path, key) = match self.find(&dir, &import.path) {
+                Ok(found) => found,
+                Err(reason) => {
+                    return self.report(idx, analysis::a026_import_not_found(import.span, &import.path, &reason));
+                }
+            };
+
+            let target = match self.by_path.get(&key) {
+                Some(&other) if self.stack.contains(&other) => {
+                    let start = self.stack.iter().position(|&m| m == other).unwrap_or(0);
+                    let cycle: Vec<String> = self.stack[start..]
+                        .iter()
+                        .chain(std::iter::once(&other))
+                        .map(|&m| self.modules[m].file_name.clone())
+                        .collect();
+                    return self.report(idx, analysis::a034_import_cycle(import.span, &cycle));
+                }
+                Some(&other) => other,
+                None => {
+                    let file_name = path.display().to_string();
+                    let src = match fs::read_to_string(&path) {
+                        Ok(s) => s.replace("\r\n", "\n"),
+                        Err(e) => {
+                            let reason = analysis::a026_looked_for(&file_name, &e);
+                            return self.report(idx, analysis::a026_import_not_found(import.span, &import.path, &reason));
+                        }
+                    };
+                    let program = parse(&src, &file_name)?;
+                    let name = self.unique_name(&import.name);
+                    let other = self.add(Some(name), key, path, file_name, src, program);
+                    self.visit(other)?;
+                    other
+                }
+            };
+
+            match self.modules[idx].imports.get(&import.name) {
+                Some(&m) if m != target => {
+                    return self.report(idx, analysis::a035_same_module_name(import.span, &import.name));
+                }
+                _ => {
+                    self.modules[idx].imports.insert(import.name.clone(), target);
+                }
+            }
+        }
+
+        self.stack.pop();
+        self.order.push(idx);
+        Ok(())
+    }
+
+    /// Where import `file` is (as written and canonical): in `dir`, the
+    /// importing file's folder, else in the first search folder that has it.
+    /// Otherwise, every place looked in.
+    fn find(&self, dir: &Path, file: &str) -> Result<(PathBuf, PathBuf), String> {
+        let mut tried = Vec::new();
+        for base in std::iter::once(dir).chain(self.search.iter().map(PathBuf::as_path)) {
+            let path = base.join(file);
+            match fs::canonicalize(&path) {
+                Ok(key) => return Ok((path, key)),
+                Err(e) => tried.push(analysis::a026_looked_for(&path.display(), &e)),
+            }
+        }
+        Err(tried.join("; "))
+    }
+
+    /// Name for a new module's locals: its module name, numbered if another
+    /// file already uses it (`util`, `util2`, ...).
+    fn unique_name(&self, name: &str) -> String {
+        let taken = |n: &str| self.modules.iter().any(|m| m.name.as_deref() == Some(n));
+        let mut candidate = name.to_string();
+        let mut i = 2;
+        while taken(&candidate) {
+            candidate = format!("{}{}", name, i);
+            i += 1;
+        }
+        candidate
+    }
+
+    fn report(&self, idx: usize, e: AError) -> Result<(), ()> {
+        let m = &self.modules[idx];
+        diag::render_lesson_error(&m.src, &m.file_name, &e);
+        Err(())
+    }
+}
+
+fn parse(src: &str, file_name: &str) -> Result<Program, ()> {
+    let cleaned = pipeline::strip_line_comments_preserve_len(src);
+    parser::parse_program(&cleaned).map_err(|d| diag::render_parse_error(src, file_name, &d))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::{self, LintConfig};
+    use crate::optimize::OptLevel;
+    use crate::vm;
+
+    /// A fresh folder holding `files` (path, text), for one test.
+    fn folder(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("a-modules-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    fn names(graph: &ModuleGraph) -> Vec<Option<&str>> {
+        graph.modules.iter().map(|m| m.name.as_deref()).collect()
+    }
+
+    #[test]
+    fn imports_load_once_before_the_importer() {
+        let dir = folder(
+            "diamond",
+            &[
+                ("main.a", "Use math\nImport \"shapes/circle.a\"\n\nFunc main() {\n}\n"),
+                ("math.a", "Func square(x: Int) -> Int {\n    Return x + x\n}\n"),
+                ("shapes/circle.a", "Import \"../math.a\"\n"),
+            ],
+        );
+        let graph = load(&dir.join("main.a"), &[]).expect("loads");
+        assert_eq!(names(&graph), [None, Some("prelude"), Some("math"), Some("circle")]);
+        assert_eq!(graph.order, [1, 2, 3, 0]);
+        assert_eq!(graph.modules[3].imports["math"], 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn imported_functions_run() {
+        let dir = folder(
+            "run",
+            &[
+                ("main.a", "Use math\n\nFunc main() {\n    Print(math.double(21))\n}\n"),
+                ("lib/math.a", "Func double(x: Int) -> Int {\n    Return x + x\n}\n"),
+            ],
+        );
+        // `math.a` is only found through the search folder.
+        assert!(load(&dir.join("main.a"), &[]).is_err());
+        let graph = load(&dir.join("main.a"), &[dir.join("lib")]).expect("loads");
+        let mut lints = LintConfig::default();
+        lints.set("all", lint::Level::Allow);
+        let chunk = pipeline::compile(&graph, OptLevel::O1, &lints).expect("compiles");
+        let mut m = vm::Vm::new();
+        m.capture_output();
+        m.run(&chunk).unwrap();
+        assert_eq!(m.take_output(), "42\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn imports_in_a_circle_are_refused() {
+        let dir = folder(
+            "cycle",
+            &[
+                ("main.a", "Use a\n\nFunc main() {\n}\n"),
+                ("a.a", "Use b\n"),
+                ("b.a", "Use a\n"),
+            ],
+        );
+        assert!(load(&dir.join("main.a"), &[]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,582 @@
+// src/optimize.rs
+//
+// Optional optimization passes (`a build -O1`, the default).
+// - AST: fold constant arithmetic/comparisons and drop If branches that can
+//   never (or always) run.
+// - Bytecode: thread jump-to-jump chains, remove jumps to the next
+//   instruction and code nothing can reach, then drop the locals, constants
+//   and files only that code used.
+//
+// Analysis always runs on the original program first, so every lesson error
+// is still reported even for code these passes would delete.
+
+use crate::ast::{CmpOp, Expr, FuncDecl, IfBranch, MatchArm, Program, Stmt};
+use crate::bytecode::{Chunk, Instr, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Compile exactly what was written.
+    O0,
+    /// Constant folding, dead-branch elimination and jump cleanup.
+    O1,
+}
+
+impl OptLevel {
+    pub fn from_level(level: u8) -> Self {
+        if level == 0 {
+            OptLevel::O0
+        } else {
+            OptLevel::O1
+        }
+    }
+}
+
+/* =========================
+   AST passes
+   ========================= */
+
+pub fn fold_program(program: &Program) -> Program {
+    Program {
+        imports: program.imports.clone(),
+        structs: program.structs.clone(),
+        enums: program.enums.clone(),
+        funcs: program
+            .funcs
+            .iter()
+            .map(|f| FuncDecl {
+                body: fold_stmts(&f.body),
+                ..f.clone()
+            })
+            .collect(),
+        stmts: fold_stmts(&program.stmts),
+        main: program.main,
+    }
+}
+
+fn fold_stmts(stmts: &[Stmt]) -> Vec<Stmt> {
+    let mut out = Vec::with_capacity(stmts.len());
+    for s in stmts {
+        fold_stmt_into(s, &mut out);
+    }
+    out
+}
+
+fn fold_stmt_into(stmt: &Stmt, out: &mut Vec<Stmt>) {
+    match stmt {
+        Stmt::Let {
+            name,
+            ty,
+            mutable,
+            expr,
+            span,
+        } => out.push(Stmt::Let {
+            name: name.clone(),
+            ty: ty.clone(),
+            mutable: *mutable,
+            expr: fold_expr(expr),
+            span: *span,
+        }),
+        Stmt::Assign { name, expr, span } => out.push(Stmt::Assign {
+            name: name.clone(),
+            expr: fold_expr(expr),
+            span: *span,
+        }),
+        Stmt::Mute {
+            name,
+            ty,
+            expr,
+            span,
+        } => out.push(Stmt::Mute {
+            name: name.clone(),
+            ty: ty.clone(),
+            expr: fold_expr(expr),
+            span: *span,
+        }),
+        Stmt::SetIndex {
+            name,
+            index,
+            expr,
+            span,
+        } => out.push(Stmt::SetIndex {
+            name: name.clone(),
+            index: fold_expr(index),
+            expr: fold_expr(expr),
+            span: *span,
+        }),
+        Stmt::SetField {
+            name,
+            field,
+            expr,
+            span,
+        } => out.push(Stmt::SetField {
+            name: name.clone(),
+            field: field.clone(),
+            expr: fold_expr(expr),
+            span: *span,
+        }),
+        Stmt::Expr(e) => out.push(Stmt::Expr(fold_expr(e))),
+        Stmt::Return { expr, span } => out.push(Stmt::Return {
+            expr: expr.as_ref().map(fold_expr),
+            span: *span,
+        }),
+        Stmt::For {
+            var,
+            iter,
+            body,
+            span,
+        } => out.push(Stmt::For {
+            var: var.clone(),
+            iter: fold_expr(iter),
+            body: fold_stmts(body),
+            span: *span,
+        }),
+        Stmt::Match { expr, arms, span } => out.push(Stmt::Match {
+            expr: fold_expr(expr),
+            arms: arms
+                .iter()
+                .map(|arm| MatchArm {
+                    variant: arm.variant.clone(),
+                    bindings: arm.bindings.clone(),
+                    body: fold_stmts(&arm.body),
+                    span: arm.span,
+                })
+                .collect(),
+            span: *span,
+        }),
+        Stmt::If {
+            first,
+            elseifs,
+            else_body,
+            span,
+        } => {
+            let mut live: Vec<IfBranch> = Vec::new();
+            let mut else_part = else_body.as_ref().map(|b| fold_stmts(b));
+
+            for br in std::iter::once(first).chain(elseifs) {
+                let cond = fold_expr(&br.cond);
+                match cond {
+                    // never taken: drop the branch
+                    Expr::Bool(false, _) => {}
+                    // always taken: it acts as the Else, later branches are dead
+                    Expr::Bool(true, _) => {
+                        else_part = Some(fold_stmts(&br.body));
+                        break;
+                    }
+                    _ => live.push(IfBranch {
+                        cond,
+                        body: fold_stmts(&br.body),
+                        span: br.span,
+                    }),
+                }
+            }
+
+            if live.is_empty() {
+                // Blocks don't open a new scope, so the chosen body can be inlined.
+                out.extend(else_part.unwrap_or_default());
+            } else {
+                let first = live.remove(0);
+                out.push(Stmt::If {
+                    first,
+                    elseifs: live,
+                    else_body: else_part,
+                    span: *span,
+                });
+            }
+        }
+    }
+}
+
+fn fold_expr(e: &Expr) -> Expr {
+    match e {
+        Expr::Add(a, b, sp) => {
+            let a = fold_expr(a);
+            let b = fold_expr(b);
+            if let (Expr::Int(x, _), Expr::Int(y, _)) = (&a, &b) {
+                // Leave overflow to the VM, which stops the program with a lesson.
+                if let Some(v) = x.checked_add(*y) {
+                    return Expr::Int(v, *sp);
+                }
+            }
+            if let (Expr::Float(x, _), Expr::Float(y, _)) = (&a, &b) {
+                return Expr::Float(x + y, *sp);
+            }
+            if let (Expr::Str(x, _), Expr::Str(y, _)) = (&a, &b) {
+                return Expr::Str(format!("{}{}", x, y), *sp);
+            }
+            Expr::Add(Box::new(a), Box::new(b), *sp)
+        }
+        Expr::Cmp(a, op, b, sp) => {
+            let a = fold_expr(a);
+            let b = fold_expr(b);
+            if let (Some(x), Some(y)) = (literal(&a), literal(&b)) {
+                if let Some(res) = fold_cmp(&x, op, &y) {
+                    return Expr::Bool(res, *sp);
+                }
+            }
+            Expr::Cmp(Box::new(a), op.clone(), Box::new(b), *sp)
+        }
+        Expr::Call(name, args, sp) => {
+            Expr::Call(name.clone(), args.iter().map(fold_expr).collect(), *sp)
+        }
+        Expr::List(items, sp) => Expr::List(items.iter().map(fold_expr).collect(), *sp),
+        Expr::Map(entries, sp) => Expr::Map(
+            entries
+                .iter()
+                .map(|(k, v)| (fold_expr(k), fold_expr(v)))
+                .collect(),
+            *sp,
+        ),
+        Expr::Record(name, fields, sp) => Expr::Record(
+            name.clone(),
+            fields
+                .iter()
+                .map(|(f, v, fsp)| (f.clone(), fold_expr(v), *fsp))
+                .collect(),
+            *sp,
+        ),
+        Expr::Field(target, name, sp) => Expr::Field(Box::new(fold_expr(target)), name.clone(), *sp),
+        Expr::Index(list, idx, sp) => {
+            Expr::Index(Box::new(fold_expr(list)), Box::new(fold_expr(idx)), *sp)
+        }
+        other => other.clone(),
+    }
+}
+
+/// The value of a condition that is the same every time it runs
+/// (`true`, `1 > 2`), or `None` when it depends on the program.
+pub fn constant_condition(e: &Expr) -> Option<bool> {
+    match fold_expr(e) {
+        Expr::Bool(b, _) => Some(b),
+        _ => None,
+    }
+}
+
+fn literal(e: &Expr) -> Option<Value> {
+    match e {
+        Expr::Int(v, _) => Some(Value::Int(*v)),
+        Expr::Float(v, _) => Some(Value::Float(*v)),
+        Expr::Bool(b, _) => Some(Value::Bool(*b)),
+        Expr::Char(c, _) => Some(Value::Char(*c)),
+        Expr::Str(s, _) => Some(Value::Str(s.clone())),
+        _ => None,
+    }
+}
+
+/// Evaluate a comparison exactly like the VM would, or `None` when the VM
+/// would raise an error (which must still happen at runtime).
+fn fold_cmp(a: &Value, op: &CmpOp, b: &Value) -> Option<bool> {
+    match op {
+        CmpOp::Eq => Some(a == b),
+        CmpOp::Ne => Some(a != b),
+        _ => match (a, b) {
+            (Value::Int(x), Value::Int(y)) => Some(match op {
+                CmpOp::Lt => x < y,
+                CmpOp::Le => x <= y,
+                CmpOp::Gt => x > y,
+                _ => x >= y,
+            }),
+            (Value::Str(x), Value::Str(y)) => Some(match op {
+                CmpOp::Lt => x < y,
+                CmpOp::Le => x <= y,
+                CmpOp::Gt => x > y,
+                _ => x >= y,
+            }),
+            (Value::Float(x), Value::Float(y)) => Some(match op {
+                CmpOp::Lt => x < y,
+                CmpOp::Le => x <= y,
+                CmpOp::Gt => x > y,
+                _ => x >= y,
+            }),
+            _ => None,
+        },
+    }
+}
+
+/* =========================
+   Bytecode passes
+   ========================= */
+
+pub fn optimize_chunk(chunk: &mut Chunk) {
+    thread_jumps(chunk);
+    remove_dead_instrs(chunk);
+    remove_unused_tables(chunk);
+}
+
+/// Point every jump straight at the end of any `Jump -> Jump -> ...` chain.
+fn thread_jumps(chunk: &mut Chunk) {
+    for i in 0..chunk.code.len() {
+        match chunk.code[i] {
+            Instr::Jump(t) => chunk.code[i] = Instr::Jump(final_target(&chunk.code, t)),
+            Instr::JumpIfFalse(t) => {
+                chunk.code[i] = Instr::JumpIfFalse(final_target(&chunk.code, t))
+            }
+            _ => {}
+        }
+    }
+}
+
+fn final_target(code: &[Instr], mut target: usize) -> usize {
+    // Bounded so a (hand-written) jump cycle can't hang the compiler.
+    for _ in 0..code.len() {
+        match code.get(target) {
+            Some(Instr::Jump(next)) if *next != target => target = *next,
+            _ => break,
+        }
+    }
+    target
+}
+
+/// Drop unreachable instructions and jumps to the very next instruction,
+/// renumbering jump targets and keeping the line table in step. The Jumps of
+/// a Switch table always stay: the Switch finds them by position.
+fn remove_dead_instrs(chunk: &mut Chunk) {
+    let len = chunk.code.len();
+    let mut keep = reachable(&chunk.code);
+    let mut in_table = 0;
+    for (i, instr) in chunk.code.iter().enumerate() {
+        match instr {
+            Instr::Jump(_) if in_table > 0 => in_table -= 1,
+            Instr::Jump(t) if *t == i + 1 => keep[i] = false,
+            Instr::Switch(n) => in_table = *n,
+            _ => {}
+        }
+    }
+    if keep.iter().all(|&k| k) {
+        return;
+    }
+
+    // new_index[i] = where instruction i (or the next kept one) ends up.
+    let mut new_index = Vec::with_capacity(len + 1);
+    let mut next = 0;
+    for &k in &keep {
+        new_index.push(next);
+        if k {
+            next += 1;
+        }
+    }
+    new_index.push(next);
+
+    let remap = |t: usize| new_index[t.min(len)];
+    let mut code = Vec::with_capacity(next);
+    let mut lines = Vec::with_capacity(next);
+    let mut files = Vec::with_capacity(next);
+    for (i, instr) in chunk.code.iter().enumerate() {
+        if !keep[i] {
+            continue;
+        }
+        code.push(match *instr {
+            Instr::Jump(t) => Instr::Jump(remap(t)),
+            Instr::JumpIfFalse(t) => Instr::JumpIfFalse(remap(t)),
+            Instr::Call(t, n) => Instr::Call(remap(t), n),
+            other => other,
+        });
+        lines.push(chunk.lines.get(i).copied().unwrap_or(0));
+        files.push(chunk.file_at(i));
+    }
+    chunk.code = code;
+    chunk.lines = lines;
+    chunk.files = files;
+}
+
+/// Drop the locals, constants and file names no instruction refers to any
+/// more (e.g. those of prelude functions nothing calls) and renumber the rest.
+/// Every slot of a function that is still entered is kept, so its locals stay
+/// one contiguous range.
+fn remove_unused_tables(chunk: &mut Chunk) {
+    let mut used_locals = vec![false; chunk.locals.len()];
+    let mut used_consts = vec![false; chunk.consts.len()];
+    let mut used_files = vec![false; chunk.file_names.len()];
+    let mark = |used: &mut [bool], i: usize| {
+        if let Some(u) = used.get_mut(i) {
+            *u = true;
+        }
+    };
+    for instr in &chunk.code {
+        match *instr {
+            Instr::LoadLocal(i) | Instr::StoreLocal(i) => mark(&mut used_locals, i),
+            Instr::Enter(first, n) => {
+                for i in first..first + n {
+                    mark(&mut used_locals, i);
+                }
+            }
+            Instr::LoadConst(i)
+            | Instr::MakeRecord(i, _)
+            | Instr::GetField(i)
+            | Instr::SetField(i)
+            | Instr::MakeVariant(i, _, _) => mark(&mut used_consts, i),
+            _ => {}
+        }
+    }
+    // The file being run stays first even if all of it was removed.
+    mark(&mut used_files, 0);
+    for &file in &chunk.files {
+        mark(&mut used_files, file as usize);
+    }
+
+    let locals = compact(&mut chunk.locals, &used_locals);
+    let consts = compact(&mut chunk.consts, &used_consts);
+    let files = compact(&mut chunk.file_names, &used_files);
+    let l = |i: usize| locals.get(i).copied().unwrap_or(i);
+    let c = |i: usize| consts.get(i).copied().unwrap_or(i);
+    for instr in &mut chunk.code {
+        *instr = match *instr {
+            Instr::LoadLocal(i) => Instr::LoadLocal(l(i)),
+            Instr::StoreLocal(i) => Instr::StoreLocal(l(i)),
+            Instr::Enter(first, n) => Instr::Enter(l(first), n),
+            Instr::LoadConst(i) => Instr::LoadConst(c(i)),
+            Instr::MakeRecord(name, n) => Instr::MakeRecord(c(name), n),
+            Instr::GetField(i) => Instr::GetField(c(i)),
+            Instr::SetField(i) => Instr::SetField(c(i)),
+            Instr::MakeVariant(name, tag, n) => Instr::MakeVariant(c(name), tag, n),
+            other => other,
+        };
+    }
+    for file in &mut chunk.files {
+        *file = files.get(*file as usize).map_or(*file, |&f| f as u32);
+    }
+}
+
+/// Keep the entries of `items` marked in `used`; returns where each old index
+/// (or the next kept entry, for removed ones and the end) ends up.
+fn compact<T>(items: &mut Vec<T>, used: &[bool]) -> Vec<usize> {
+    let mut new_index = Vec::with_capacity(items.len());
+    let mut next = 0;
+    for &u in used {
+        new_index.push(next);
+        if u {
+            next += 1;
+        }
+    }
+    new_index.push(next);
+    let mut keep = used.iter();
+    items.retain(|_| keep.next().copied().unwrap_or(true));
+    new_index
+}
+
+fn reachable(code: &[Instr]) -> Vec<bool> {
+    let mut seen = vec![false; code.len()];
+    let mut work = vec![0usize];
+    while let Some(i) = work.pop() {
+        if i >= code.len() || seen[i] {
+            continue;
+        }
+        seen[i] = true;
+        match code[i] {
+            Instr::Jump(t) => work.push(t),
+            Instr::JumpIfFalse(t) => {
+                work.push(t);
+                work.push(i + 1);
+            }
+            Instr::Switch(n) => work.extend(i + 1..=i + n),
+            Instr::Call(t, _) => {
+                work.push(t);
+                work.push(i + 1);
+            }
+            Instr::Halt | Instr::Ret => {}
+            _ => work.push(i + 1),
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Span;
+    use crate::parser::parse_program;
+
+    const SP: Span = Span { start: 0, end: 0 };
+
+    fn add(a: Expr, b: Expr) -> Expr {
+        Expr::Add(Box::new(a), Box::new(b), SP)
+    }
+
+    fn code_of(chunk: &Chunk) -> Vec<String> {
+        chunk.code.iter().map(|i| format!("{:?}", i)).collect()
+    }
+
+    fn chunk(code: Vec<Instr>) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.consts.push(Value::Int(0));
+        for (line, instr) in (1..).zip(code) {
+            chunk.push(instr, line);
+        }
+        chunk
+    }
+
+    #[test]
+    fn folds_constant_sums() {
+        assert!(matches!(fold_expr(&add(Expr::Int(1, SP), Expr::Int(2, SP))), Expr::Int(3, _)));
+        assert!(matches!(
+            fold_expr(&add(Expr::Float(0.5, SP), Expr::Float(0.25, SP))),
+            Expr::Float(f, _) if f == 0.75
+        ));
+        let joined = fold_expr(&add(Expr::Str("ab".to_string(), SP), Expr::Str("cd".to_string(), SP)));
+        assert!(matches!(joined, Expr::Str(s, _) if s == "abcd"));
+        let cmp = Expr::Cmp(Box::new(Expr::Int(1, SP)), CmpOp::Gt, Box::new(Expr::Int(2, SP)), SP);
+        assert_eq!(constant_condition(&cmp), Some(false));
+    }
+
+    #[test]
+    fn leaves_overflow_and_mixed_kinds_to_the_vm() {
+        let overflow = add(Expr::Int(i64::MAX, SP), Expr::Int(1, SP));
+        assert!(matches!(fold_expr(&overflow), Expr::Add(..)));
+        let mixed = add(Expr::Int(1, SP), Expr::Float(1.0, SP));
+        assert!(matches!(fold_expr(&mixed), Expr::Add(..)));
+    }
+
+    #[test]
+    fn drops_if_branches_that_never_run() {
+        let src = "Func main() {\n    If 1 > 2 then {\n        Print(1)\n    } ElseIf 2 > 1 then {\n        Print(2)\n    } Else {\n        Print(3)\n    }\n}\n";
+        let program = fold_program(&parse_program(src).unwrap());
+        // Only the always-taken branch is left, inlined.
+        assert!(matches!(
+            program.stmts.as_slice(),
+            [Stmt::Expr(Expr::Call(name, args, _))] if name == "Print" && matches!(args[..], [Expr::Int(2, _)])
+        ));
+    }
+
+    #[test]
+    fn threads_jump_chains_and_drops_dead_code() {
+        let mut c = chunk(vec![
+            Instr::LoadConst(0),
+            Instr::JumpIfFalse(3),
+            Instr::Halt,
+            Instr::Jump(4),
+            Instr::Halt,
+        ]);
+        optimize_chunk(&mut c);
+        assert_eq!(code_of(&c), ["LoadConst(0)", "JumpIfFalse(3)", "Halt", "Halt"]);
+        assert_eq!(c.lines, [1, 2, 3, 5]);
+        assert_eq!(c.files.len(), c.code.len());
+    }
+
+    #[test]
+    fn keeps_switch_tables_whole() {
+        // The second table entry jumps to the next instruction, which would
+        // otherwise be removed.
+        let code = vec![
+            Instr::LoadConst(0),
+            Instr::Switch(2),
+            Instr::Jump(5),
+            Instr::Jump(4),
+            Instr::Halt,
+            Instr::Halt,
+        ];
+        let mut c = chunk(code.clone());
+        optimize_chunk(&mut c);
+        assert_eq!(code_of(&c), code_of(&chunk(code)));
+    }
+
+    #[test]
+    fn drops_the_tables_of_removed_functions() {
+        let src = "Func twice(x: Int) -> Int {\n    Return x + x\n}\n\nFunc main() {\n    n = twice(2)\n    Print(n, Max(n, 3))\n}\n";
+        let full = crate::pipeline::compile_test_program(src, OptLevel::O0);
+        let chunk = crate::pipeline::compile_test_program(src, OptLevel::O1);
+        assert!(full.locals.iter().any(|l| l.starts_with("prelude.Join.")));
+        assert_eq!(chunk.locals, ["n", "twice.x", "prelude.Max.a", "prelude.Max.b"]);
+        assert!(chunk.consts.len() < full.consts.len());
+        assert_eq!(chunk.file_names, ["test.a", "<prelude>"]);
+        crate::verify::verify_chunk(&chunk).unwrap();
+    }
+}
@@ -3,14 +3,23 @@
 use chumsky::prelude::*;
 use chumsky::text;
 
-use crate::ast::{CmpOp, Expr, IfBranch, Program, Span, Stmt};
+use crate::analysis::{self, AError};
+use crate::i18n;
+use crate::ast::{
+    CmpOp, EnumDecl, Expr, FuncDecl, IfBranch, Import, MatchArm, Program, Span, Stmt, StructDecl,
+};
 
 #[derive(Debug, Clone)]
 pub struct ParseDiag {
     pub message: String,
     pub span: Span,
+    /// Set when the text parsed fine but can't be used, e.g. a number too big for A.
+    pub lesson: Option<Box<AError>>,
 }
 
+/// Reason attached to a number literal that doesn't fit its type.
+const NUMBER_OUT_OF_RANGE: &str = "number literal out of range";
+
 pub fn parse_program(src: &str) -> Result<Program, ParseDiag> {
     let parser = program_parser();
     let len = src.len();
@@ -21,35 +30,43 @@ pub fn parse_program(src: &str) -> Result<Program, ParseDiag> {
             let e = errs.into_iter().next().unwrap();
             let sp = e.span();
 
-            // Compute human-friendly line/column and extract the source line
-            let start = sp.start;
-            let end = sp.end.min(len);
-            let line_num = src[..start].chars().filter(|&c| c == '\n').count() + 1;
-            let line_start = src[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
-            let line_end = src[line_start..].find('\n').map(|i| line_start + i).unwrap_or(len);
-            let line = &src[line_start..line_end];
-            let col = start - line_start + 1; // 1-based column
-
-            // Build a caret pointer under the offending column
-            let mut pointer = String::new();
-            for _ in 0..(col.saturating_sub(1)) {
-                pointer.push(' ');
+            if let chumsky::error::SimpleReason::Custom(reason) = e.reason() {
+                if reason == NUMBER_OUT_OF_RANGE {
+                    let span = Span { start: sp.start, end: sp.end.min(len) };
+                    return Err(ParseDiag {
+                        message: reason.clone(),
+                        span,
+                        lesson: Some(Box::new(analysis::number_out_of_range(
+                            span,
+                            &src[span.start..span.end],
+                        ))),
+                    });
+                }
             }
-            pointer.push('^');
-
-            let message = format!(
-                "[A_PARSE] Error: Parse error near here.\n   at line {}:{}\n\n{}\n{}\n\nHelp: The parser couldn't match the code here to the grammar.\n      Double-check braces `{{}}`, parentheses `()` and that statements are valid.\n\nExample:\nFunc main() {{\n    x = 1\n    y = x + 2\n    Print(y)\n}}\n",
-                line_num, col, line, pointer
-            );
 
             Err(ParseDiag {
-                message,
-                span: Span { start, end },
+                message: i18n::text("a_parse", "title", &[]),
+                span: Span { start: sp.start, end: sp.end.min(len) },
+                lesson: None,
             })
         }
     }
 }
 
+/// A top-level declaration: `main` is just the function named so.
+enum Item {
+    Import(Import),
+    Struct(StructDecl),
+    Enum(EnumDecl),
+    Func(FuncDecl),
+}
+
+/// What can follow an expression: `[index]` or `.field`.
+enum Postfix {
+    Index(Expr),
+    Field(String),
+}
+
 fn program_parser() -> impl Parser<char, Program, Error = Simple<char>> {
     // ✅ CRLF FIX: include '\r' everywhere we treat whitespace/newlines
     let ws = one_of::<char, &str, Simple<char>>(" \t\r").repeated().ignored();
@@ -63,15 +80,77 @@ fn program_parser() -> impl Parser<char, Program, Error = Simple<char>> {
         .collect::<String>()
         .padded_by(ws.clone());
 
-    let type_name = just('&')
-        .or_not()
-        .then(type_word)
-        .map(|(amp, w)| if amp.is_some() { format!("&{}", w) } else { w })
-        .padded_by(ws.clone());
+    // `Int`, `&str`, or a collection like `List(Int)` / `Map(String, Int)`,
+    // kept as text (normalized to `Map(String, Int)`) for analysis to resolve.
+    let type_name = recursive(|type_name| {
+        just('&')
+            .or_not()
+            .then(type_word)
+            .then(
+                type_name
+                    .separated_by(just(',').padded_by(ws.clone()))
+                    .at_least(1)
+                    .delimited_by(just('('), just(')'))
+                    .or_not(),
+            )
+            .map(|((amp, w), args): ((Option<char>, String), Option<Vec<String>>)| {
+                let w = if amp.is_some() { format!("&{}", w) } else { w };
+                match args {
+                    Some(args) => format!("{}({})", w, args.join(", ")),
+                    None => w,
+                }
+            })
+            .padded_by(ws.clone())
+    });
 
-    let int_lit = text::int(10)
-        .padded_by(ws.clone())
-        .map(|s: String| s.parse::<i64>().unwrap());
+    // Struct names start with a capital letter; that is what tells
+    // `Point { x: 1 }` apart from a variable followed by a block.
+    let type_ident = ident.clone().try_map(|name: String, span| {
+        if name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            Ok(name)
+        } else {
+            Err(Simple::custom(span, "struct names start with a capital letter"))
+        }
+    });
+
+    // 42, 3.14, 1e-3, 2.5E10: a fraction or an exponent makes it a Float.
+    let digits = filter(|c: &char| c.is_ascii_digit())
+        .repeated()
+        .at_least(1)
+        .collect::<String>();
+    let number_lit = text::int(10)
+        .then(just('.').ignore_then(digits).or_not())
+        .then(
+            one_of("eE")
+                .ignore_then(one_of("+-").or_not())
+                .then(digits)
+                .or_not(),
+        )
+        .validate(|((int, frac), exp), span: std::ops::Range<usize>, emit| {
+            let sp = Span { start: span.start, end: span.end };
+            if frac.is_none() && exp.is_none() {
+                return match int.parse::<i64>() {
+                    Ok(v) => Expr::Int(v, sp),
+                    Err(_) => {
+                        emit(Simple::custom(span, NUMBER_OUT_OF_RANGE));
+                        Expr::Int(0, sp)
+                    }
+                };
+            }
+            let mut text = int;
+            if let Some(frac) = frac {
+                text = format!("{}.{}", text, frac);
+            }
+            if let Some((sign, exp)) = exp {
+                text = format!("{}e{}{}", text, sign.map(String::from).unwrap_or_default(), exp);
+            }
+            let v = text.parse::<f64>().unwrap_or(f64::INFINITY);
+            if !v.is_finite() {
+                emit(Simple::custom(span, NUMBER_OUT_OF_RANGE));
+            }
+            Expr::Float(v, sp)
+        })
+        .padded_by(ws.clone());
 
     let bool_lit = choice((just("true").to(true), just("false").to(false))).padded_by(ws.clone());
 
@@ -113,9 +192,41 @@ fn program_parser() -> impl Parser<char, Program, Error = Simple<char>> {
             bool_lit
                 .clone()
                 .map_with_span(|b, sp| Expr::Bool(b, Span { start: sp.start, end: sp.end })),
-            int_lit
+            number_lit.clone(),
+            type_ident
                 .clone()
-                .map_with_span(|v, sp| Expr::Int(v, Span { start: sp.start, end: sp.end })),
+                .then_ignore(just('{').padded_by(ws.clone()))
+                .then(
+                    ident
+                        .clone()
+                        .padded_by(wsnl.clone())
+                        .then_ignore(just(':').padded_by(ws.clone()))
+                        .then(expr.clone().padded_by(wsnl.clone()))
+                        .map_with_span(|(field, value), sp| {
+                            (field, value, Span { start: sp.start, end: sp.end })
+                        })
+                        .separated_by(just(',').padded_by(wsnl.clone()))
+                        .allow_trailing()
+                        .at_least(1),
+                )
+                .then_ignore(just('}').padded_by(ws.clone()))
+                .map_with_span(|(name, fields), sp| {
+                    Expr::Record(name, fields, Span { start: sp.start, end: sp.end })
+                }),
+            // A function from an imported file: math.square(x)
+            ident
+                .clone()
+                .then_ignore(just('.'))
+                .then(ident.clone())
+                .then(
+                    just('(')
+                        .padded_by(ws.clone())
+                        .ignore_then(expr.clone().separated_by(just(',').padded_by(ws.clone())))
+                        .then_ignore(just(')').padded_by(ws.clone())),
+                )
+                .map_with_span(|((module, name), args), sp| {
+                    Expr::Call(format!("{}.{}", module, name), args, Span { start: sp.start, end: sp.end })
+                }),
             ident
                 .clone()
                 .map_with_span(|name: String, sp| (name, Span { start: sp.start, end: sp.end }))
@@ -133,15 +244,63 @@ fn program_parser() -> impl Parser<char, Program, Error = Simple<char>> {
                         Expr::Var(name, name_sp)
                     }
                 }),
+            just('[')
+                .padded_by(ws.clone())
+                .ignore_then(
+                    expr.clone()
+                        .padded_by(wsnl.clone())
+                        .separated_by(just(',').padded_by(wsnl.clone()))
+                        .allow_trailing()
+                        .padded_by(wsnl.clone()),
+                )
+                .then_ignore(just(']').padded_by(ws.clone()))
+                .map_with_span(|items, sp| Expr::List(items, Span { start: sp.start, end: sp.end })),
+            just('{')
+                .padded_by(ws.clone())
+                .ignore_then(
+                    expr.clone()
+                        .padded_by(wsnl.clone())
+                        .then_ignore(just(':'))
+                        .then(expr.clone().padded_by(wsnl.clone()))
+                        .separated_by(just(',').padded_by(wsnl.clone()))
+                        .allow_trailing()
+                        .padded_by(wsnl.clone()),
+                )
+                .then_ignore(just('}').padded_by(ws.clone()))
+                .map_with_span(|entries, sp| Expr::Map(entries, Span { start: sp.start, end: sp.end })),
             just('(')
                 .padded_by(ws.clone())
                 .ignore_then(expr.clone())
                 .then_ignore(just(')').padded_by(ws.clone())),
         ));
 
-        let sum = atom
+        // Indexing and field access bind tighter than anything else: xs[i].name
+        let postfix = atom
+            .then(
+                choice((
+                    just('[')
+                        .padded_by(ws.clone())
+                        .ignore_then(expr.clone())
+                        .then_ignore(just(']').padded_by(ws.clone()))
+                        .map(Postfix::Index),
+                    just('.').ignore_then(ident.clone()).map(Postfix::Field),
+                ))
+                .map_with_span(|op, sp: std::ops::Range<usize>| (op, sp.end))
+                .repeated(),
+            )
+            .map_with_span(|(first, rest): (Expr, Vec<(Postfix, usize)>), sp| {
+                rest.into_iter().fold(first, |acc, (op, end)| {
+                    let span = Span { start: sp.start, end };
+                    match op {
+                        Postfix::Index(idx) => Expr::Index(Box::new(acc), Box::new(idx), span),
+                        Postfix::Field(name) => Expr::Field(Box::new(acc), name, span),
+                    }
+                })
+            });
+
+        let sum = postfix
             .clone()
-            .then(just('+').padded_by(ws.clone()).ignore_then(atom).repeated())
+            .then(just('+').padded_by(ws.clone()).ignore_then(postfix).repeated())
             .map_with_span(|(first, rest): (Expr, Vec<Expr>), sp| {
                 let span = Span { start: sp.start, end: sp.end };
                 rest.into_iter().fold(first, |acc, rhs| {
@@ -207,6 +366,43 @@ fn program_parser() -> impl Parser<char, Program, Error = Simple<char>> {
             span: Span { start: sp.start, end: sp.end },
         });
 
+    // Item assignment: xs[i] = v / m["key"] = v
+    let set_index_stmt = ident
+        .clone()
+        .then_ignore(just('[').padded_by(ws.clone()))
+        .then(expr.clone())
+        .then_ignore(just(']').padded_by(ws.clone()))
+        .then_ignore(just('=').padded_by(ws.clone()))
+        .then(expr.clone())
+        .map_with_span(|((name, index), expr), sp| Stmt::SetIndex {
+            name,
+            index,
+            expr,
+            span: Span { start: sp.start, end: sp.end },
+        });
+
+    // Field assignment: p.x = v
+    let set_field_stmt = ident
+        .clone()
+        .then_ignore(just('.'))
+        .then(ident.clone())
+        .then_ignore(just('=').padded_by(ws.clone()))
+        .then(expr.clone())
+        .map_with_span(|((name, field), expr), sp| Stmt::SetField {
+            name,
+            field,
+            expr,
+            span: Span { start: sp.start, end: sp.end },
+        });
+
+    // Return x / Return
+    let return_stmt = choice((text::keyword("Return"), text::keyword("return")))
+        .ignore_then(expr.clone().or_not())
+        .map_with_span(|expr, sp| Stmt::Return {
+            expr,
+            span: Span { start: sp.start, end: sp.end },
+        });
+
     // Assignment:
     let assign_stmt = ident
         .clone()
@@ -219,7 +415,9 @@ fn program_parser() -> impl Parser<char, Program, Error = Simple<char>> {
         });
 
     // ✅ CRLF FIX: statement separators should accept '\r' too
+    // (blank lines may still carry indentation, e.g. where a comment was stripped)
     let newline = one_of::<char, &str, Simple<char>>("\r\n")
+        .then(ws.clone())
         .repeated()
         .at_least(1)
         .ignored();
@@ -260,11 +458,71 @@ fn program_parser() -> impl Parser<char, Program, Error = Simple<char>> {
                 span: Span { start: sp.start, end: sp.end },
             });
 
+        let kw_for = choice((just("For"), just("for"))).padded_by(wsnl.clone());
+        let kw_in = just("in").padded_by(ws.clone());
+
+        let for_stmt = kw_for
+            .ignore_then(ident.clone())
+            .then_ignore(kw_in)
+            .then(expr.clone().padded_by(ws.clone()))
+            .then(block.clone())
+            .map_with_span(|((var, iter), body), sp| Stmt::For {
+                var,
+                iter,
+                body,
+                span: Span { start: sp.start, end: sp.end },
+            });
+
+        // Match s { Circle(r) => Print(r), Empty => { ... }, _ => ... }
+        let kw_match = choice((just("Match"), just("match"))).padded_by(wsnl.clone());
+
+        let pattern = choice((
+            type_ident
+                .clone()
+                .then(
+                    just('(')
+                        .padded_by(ws.clone())
+                        .ignore_then(ident.clone().separated_by(just(',').padded_by(ws.clone())))
+                        .then_ignore(just(')').padded_by(ws.clone()))
+                        .or_not(),
+                )
+                .map(|(name, bindings)| (Some(name), bindings.unwrap_or_default())),
+            just('_').padded_by(ws.clone()).to((None, Vec::new())),
+        ));
+
+        let arm = pattern
+            .then_ignore(just("=>").padded_by(ws.clone()))
+            .then(block.clone().or(stmt.clone().map(|s| vec![s])))
+            .map_with_span(|((variant, bindings), body), sp| MatchArm {
+                variant,
+                bindings,
+                body,
+                span: Span { start: sp.start, end: sp.end },
+            })
+            .padded_by(wsnl.clone())
+            .then_ignore(just(',').or_not());
+
+        let match_stmt = kw_match
+            .ignore_then(expr.clone().padded_by(ws.clone()))
+            .then_ignore(just('{').padded_by(wsnl.clone()))
+            .then(arm.repeated().at_least(1))
+            .then_ignore(just('}').padded_by(ws.clone()))
+            .map_with_span(|(expr, arms), sp| Stmt::Match {
+                expr,
+                arms,
+                span: Span { start: sp.start, end: sp.end },
+            });
+
         choice((
             if_stmt,
+            for_stmt,
+            match_stmt,
+            return_stmt.clone(),
             typed_decl,
             let_stmt.clone(),
             mute_stmt.clone(),
+            set_index_stmt.clone(),
+            set_field_stmt.clone(),
             assign_stmt.clone(),
             expr.clone().map(Stmt::Expr),
         ))
@@ -276,15 +534,152 @@ fn program_parser() -> impl Parser<char, Program, Error = Simple<char>> {
         .allow_trailing()
         .padded_by(ws.clone());
 
-    let func_kw = choice((just("Func"), just("func"), just("fn")));
+    // Struct Point { x: Int, y: Int } (fields split by commas and/or newlines)
+    let struct_field = ident
+        .clone()
+        .then_ignore(just(':').padded_by(ws.clone()))
+        .then(type_name.clone())
+        .map_with_span(|(name, ty), sp| (name, ty, Span { start: sp.start, end: sp.end }))
+        .padded_by(wsnl.clone())
+        .then_ignore(just(',').or_not());
+
+    let struct_decl = choice((just("Struct"), just("struct")))
+        .padded_by(wsnl.clone())
+        .ignore_then(type_ident.clone())
+        .then_ignore(just('{').padded_by(wsnl.clone()))
+        .then(struct_field.repeated().at_least(1))
+        .then_ignore(just('}').padded_by(wsnl.clone()))
+        .map_with_span(|(name, fields), sp| StructDecl {
+            name,
+            fields,
+            span: Span { start: sp.start, end: sp.end },
+        });
+
+    // Enum Shape { Circle(Int), Square(Int), Empty } (variants split by commas and/or newlines)
+    let enum_variant = type_ident
+        .clone()
+        .then(
+            just('(')
+                .padded_by(ws.clone())
+                .ignore_then(type_name.clone().separated_by(just(',').padded_by(ws.clone())))
+                .then_ignore(just(')').padded_by(ws.clone()))
+                .or_not(),
+        )
+        .map_with_span(|(name, payload), sp| {
+            (name, payload.unwrap_or_default(), Span { start: sp.start, end: sp.end })
+        })
+        .padded_by(wsnl.clone())
+        .then_ignore(just(',').or_not());
 
-    func_kw
+    let enum_decl = choice((just("Enum"), just("enum")))
         .padded_by(wsnl.clone())
-        .ignore_then(just("main").padded_by(wsnl.clone()))
-        .then_ignore(just('(').padded_by(wsnl.clone()))
-        .then_ignore(just(')').padded_by(wsnl.clone()))
+        .ignore_then(type_ident.clone())
+        .then_ignore(just('{').padded_by(wsnl.clone()))
+        .then(enum_variant.repeated().at_least(1))
+        .then_ignore(just('}').padded_by(wsnl.clone()))
+        .map_with_span(|(name, variants), sp| EnumDecl {
+            name,
+            variants,
+            span: Span { start: sp.start, end: sp.end },
+        });
+
+    // Import "lib/math.a" / Use math (short for Import "math.a")
+    let import_decl = choice((
+        choice((just("Import"), just("import")))
+            .then(ws.clone())
+            .ignore_then(string_lit.clone())
+            .map_with_span(|path: String, sp| Import {
+                name: module_name(&path),
+                path,
+                span: Span { start: sp.start, end: sp.end },
+            }),
+        choice((just("Use"), just("use")))
+            .then(ws.clone())
+            .ignore_then(ident.clone())
+            .map_with_span(|name: String, sp| Import {
+                path: format!("{}.a", name),
+                name,
+                span: Span { start: sp.start, end: sp.end },
+            }),
+    ))
+    .padded_by(wsnl.clone());
+
+    let func_kw = choice((just("Func"), just("func"), just("fn")));
+
+    // Func area(w: Int, h: Int) -> Int { ... }
+    let param = ident
+        .clone()
+        .then_ignore(just(':').padded_by(ws.clone()))
+        .then(type_name.clone())
+        .map_with_span(|(name, ty), sp| (name, ty, Span { start: sp.start, end: sp.end }))
+        .padded_by(wsnl.clone());
+
+    let func_decl = func_kw
+        .then(ws.clone())
+        .ignore_then(ident.clone())
+        .then(
+            just('(')
+                .padded_by(wsnl.clone())
+                .ignore_then(param.separated_by(just(',')))
+                .then_ignore(just(')').padded_by(ws.clone())),
+        )
+        .then(just("->").padded_by(ws.clone()).ignore_then(type_name.clone()).or_not())
         .then_ignore(just('{').padded_by(wsnl.clone()))
-        .ignore_then(stmts.padded_by(wsnl.clone()))
-        .then_ignore(just('}').padded_by(wsnl))
-        .map(|stmts| Program { stmts })
+        .then(stmts.padded_by(wsnl.clone()))
+        .then_ignore(just('}'))
+        .map_with_span(|(((name, params), ret), body), sp| FuncDecl {
+            name,
+            params,
+            ret,
+            body,
+            span: Span { start: sp.start, end: sp.end },
+        })
+        .padded_by(wsnl.clone());
+
+    let item = choice((
+        import_decl.map(Item::Import),
+        struct_decl.map(Item::Struct),
+        enum_decl.map(Item::Enum),
+        func_decl.map(Item::Func),
+    ));
+
+    // Declarations come in any order. The first plain `Func main()` is the
+    // program; any other `main` is left to analysis to report.
+    item.repeated()
+        .then_ignore(wsnl)
+        .then_ignore(end())
+        .map(|items| {
+            let mut program = Program {
+                imports: Vec::new(),
+                structs: Vec::new(),
+                enums: Vec::new(),
+                funcs: Vec::new(),
+                stmts: Vec::new(),
+                main: None,
+            };
+            for item in items {
+                match item {
+                    Item::Import(i) => program.imports.push(i),
+                    Item::Struct(s) => program.structs.push(s),
+                    Item::Enum(e) => program.enums.push(e),
+                    Item::Func(f)
+                        if f.name == "main"
+                            && f.params.is_empty()
+                            && f.ret.is_none()
+                            && program.main.is_none() =>
+                    {
+                        program.main = Some(f.span);
+                        program.stmts = f.body;
+                    }
+                    Item::Func(f) => program.funcs.push(f),
+                }
+            }
+            program
+        })
+}
+
+/// `lib/math.a` -> `math`
+fn module_name(path: &str) -> String {
+    let file = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    file.strip_suffix(".a").unwrap_or(file).to_string()
 }
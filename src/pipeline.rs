@@ -1,85 +1,278 @@
 // src/pipeline.rs
 
-use crate::{analysis, bytecode, compiler, diag, vm};
-use crate::ast::Program;
+use crate::analysis::ModuleInterface;
+use crate::compiler::CompiledModule;
+use crate::modules::ModuleGraph;
+use crate::optimize::{self, OptLevel};
+use crate::lint::{self, LintConfig};
+use crate::{analysis, asm, builtins, bytecode, compiler, diag, link, verify, vm};
+use crate::bytecode::Chunk;
+use std::collections::HashMap;
 use std::fs;
+use std::ops::Range;
 use std::path::Path;
 
 pub fn strip_line_comments_preserve_len(src: &str) -> String {
-    let bytes = src.as_bytes();
-    let mut out = String::with_capacity(src.len());
+    // Work on bytes so offsets stay byte offsets; a comment is blanked byte by
+    // byte, which keeps the rest of the text (and any non-ASCII in it) intact.
+    let mut out = src.as_bytes().to_vec();
+    for comment in line_comments(src) {
+        out[comment].fill(b' ');
+    }
+    String::from_utf8(out).expect("blanking whole comments keeps the text valid UTF-8")
+}
 
+/// Byte ranges of the `// ...` comments in `src` (up to the end of their
+/// line). A `//` inside a string (`"a // b"`) or char (`'/'`) literal isn't
+/// one.
+pub fn line_comments(src: &str) -> Vec<Range<usize>> {
+    let bytes = src.as_bytes();
+    let mut found = Vec::new();
     let mut i = 0usize;
     while i < bytes.len() {
-        if bytes[i] == b'/' && (i + 1) < bytes.len() && bytes[i + 1] == b'/' {
-            out.push(' ');
-            out.push(' ');
-            i += 2;
-            while i < bytes.len() && bytes[i] != b'\n' {
-                out.push(' ');
-                i += 1;
+        match bytes[i] {
+            // Strings have no escapes, so they end at the next quote.
+            b'"' => match src[i + 1..].find('"') {
+                Some(len) => i += len + 1,
+                None => break,
+            },
+            b'\'' => {
+                if let Some(c) = src[i + 1..].chars().next() {
+                    if src[i + 1 + c.len_utf8()..].starts_with('\'') {
+                        i += c.len_utf8() + 1;
+                    }
+                }
             }
-            continue;
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                let end = src[i..].find('\n').map_or(src.len(), |len| i + len);
+                found.push(i..end);
+                i = end;
+                continue;
+            }
+            _ => {}
         }
-        out.push(bytes[i] as char);
         i += 1;
     }
-    out
+    found
 }
 
-pub fn compile_and_maybe_run(
-    src: &str,
-    file_name: &str,
-    program: &Program,
-    mode_run: bool,
-    emit_path: Option<&Path>,
-) -> Result<(), ()> {
-    // 1) Analyze (A-native lessons)
-    match analysis::analyze(program) {
-        Ok(_ar) => {}
-        Err(errors) => {
-            for e in &errors {
-                diag::render_lesson_error(src, file_name, e);
+/// What `a build` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EmitFormat {
+    /// Binary `.a.byte` bytecode (what `a run` loads)
+    Bytecode,
+    /// Textual `.a.asm` assembly (turn it back into bytecode with `a asm`)
+    Asm,
+}
+
+/// Where (and how) to write the compiled bytecode.
+pub struct EmitOptions<'a> {
+    pub path: &'a Path,
+    pub format: EmitFormat,
+    /// Store the full source text in the file (lets `a debug` show it later).
+    pub embed_source: bool,
+}
+
+/// How to run a freshly compiled program.
+pub struct RunOptions {
+    pub limits: vm::VmLimits,
+    pub trace: Option<vm::TraceFormat>,
+}
+
+/// Analyze and compile every file of a program, then link them into one
+/// chunk, reporting any problems (and warnings, as `lints` says) to stderr.
+pub fn compile(graph: &ModuleGraph, opt: OptLevel, lints: &LintConfig) -> Result<Chunk, ()> {
+    let mut interfaces: Vec<Option<ModuleInterface>> = vec![None; graph.modules.len()];
+    let mut compiled: Vec<Option<CompiledModule>> = (0..graph.modules.len()).map(|_| None).collect();
+
+    // Files are handled after the files they import, so their interfaces are known.
+    for &i in &graph.order {
+        let module = &graph.modules[i];
+        let program = graph.with_shared_types(i);
+        let imports: HashMap<String, ModuleInterface> = module
+            .imports
+            .iter()
+            .filter_map(|(name, &m)| Some((name.clone(), interfaces[m].clone()?)))
+            .collect();
+
+        // 1) Analyze (A-native lessons)
+        let prelude = if i == graph.prelude {
+            None
+        } else {
+            interfaces[graph.prelude].as_ref()
+        };
+        let analysis = match analysis::analyze(&program, &imports, prelude) {
+            Ok(ar) => ar,
+            Err(errors) => {
+                for e in &errors {
+                    diag::render_lesson_error(&module.src, &module.file_name, e);
+                }
+                return Err(());
+            }
+        };
+
+        // Warnings don't stop the build unless they were denied.
+        if i != graph.prelude {
+            let warnings = lint::check(&module.program, &module.src, lints);
+            for w in &warnings {
+                diag::render_diagnostic(&module.src, &module.file_name, w);
+            }
+            if warnings.iter().any(|w| w.severity == analysis::Severity::Error) {
+                return Err(());
             }
-            return Err(());
         }
+
+        // 2) Compile to bytecode (optimizing on the way if asked to)
+        let program = if opt >= OptLevel::O1 {
+            optimize::fold_program(&program)
+        } else {
+            program
+        };
+
+        let prelude_funcs: Vec<String> = prelude
+            .map(|p| p.funcs.keys().cloned().collect())
+            .unwrap_or_default();
+
+        // Only the file being run runs its `main`.
+        let compiled_module = compiler::compile_to_bytecode(
+            &program,
+            &module.src,
+            &analysis.op_types,
+            i == 0,
+            &prelude_funcs,
+        );
+        match compiled_module {
+            Ok(c) => compiled[i] = Some(c),
+            Err(msg) => {
+                eprintln!("A_BACKEND: bytecode compiler error: {}", msg);
+                return Err(());
+            }
+        }
+        interfaces[i] = Some(analysis.interface);
     }
 
-    // 2) Compile to bytecode
-    let chunk = match compiler::compile_to_bytecode(program) {
+    let inputs: Vec<link::LinkInput> = graph
+        .modules
+        .iter()
+        .zip(compiled)
+        .filter_map(|(m, c)| {
+            let mut imports = m.imports.clone();
+            imports.insert(builtins::PRELUDE_MODULE.to_string(), graph.prelude);
+            Some(link::LinkInput {
+                name: m.name.clone(),
+                file_name: m.file_name.clone(),
+                module: c?,
+                imports,
+            })
+        })
+        .collect();
+    let mut chunk = match link::link(&inputs) {
         Ok(c) => c,
         Err(msg) => {
-            eprintln!("A_BACKEND: bytecode compiler error: {}", msg);
+            eprintln!("A_BACKEND: {}", msg);
             return Err(());
         }
     };
 
+    if opt >= OptLevel::O1 {
+        optimize::optimize_chunk(&mut chunk);
+    }
+
+    // Our own output must always pass the check we apply to loaded files.
+    if let Err(msg) = verify::verify_chunk(&chunk) {
+        eprintln!("A_BACKEND: the compiled bytecode is invalid: {}", msg);
+        return Err(());
+    }
+    Ok(chunk)
+}
+
+/// Compile `src` as if it were file `test.a` (with every warning allowed),
+/// for tests that need real compiler output.
+#[cfg(test)]
+pub fn compile_test_program(src: &str, opt: OptLevel) -> Chunk {
+    let graph = crate::modules::load_source(Path::new("test.a"), src.to_string(), &[])
+        .expect("test program loads");
+    let mut lints = LintConfig::default();
+    lints.set("all", lint::Level::Allow);
+    compile(&graph, opt, &lints).expect("test program compiles")
+}
+
+/// Compile and run `src` like `compile_test_program`, at -O0 and -O1 (which
+/// must agree): what it printed, or why it stopped.
+#[cfg(test)]
+pub fn run_test_program(src: &str) -> Result<String, String> {
+    let run = |opt| {
+        let chunk = compile_test_program(src, opt);
+        let mut m = vm::Vm::new();
+        m.capture_output();
+        m.run(&chunk)?;
+        Ok(m.take_output())
+    };
+    let result = run(OptLevel::O0);
+    assert_eq!(result, run(OptLevel::O1), "-O0 and -O1 differ");
+    result
+}
+
+pub fn compile_and_maybe_run(
+    graph: &ModuleGraph,
+    opt: OptLevel,
+    lints: &LintConfig,
+    emit: Option<EmitOptions>,
+    run: Option<RunOptions>,
+) -> Result<(), ()> {
+    let mut chunk = compile(graph, opt, lints)?;
+    let entry = &graph.modules[0];
+
     // 3) Emit bytecode if requested
-    if let Some(path) = emit_path {
-        let data = match bytecode::encode_chunk(&chunk) {
-            Ok(d) => d,
-            Err(msg) => {
-                eprintln!("A_BYTECODE: {}", msg);
+    let emitting = emit.is_some();
+    if let Some(emit) = emit {
+        if emit.format == EmitFormat::Asm {
+            if let Err(e) = fs::write(emit.path, asm::disassemble(&chunk)) {
+                eprintln!("A_BUILD: failed to write assembly: {}", e);
                 return Err(());
             }
-        };
-        if let Err(e) = fs::write(path, data) {
-            eprintln!("A_BUILD: failed to write bytecode: {}", e);
-            return Err(());
+            println!("Build succeeded. Wrote assembly to {}", emit.path.display());
+        } else {
+            let file = bytecode::BytecodeFile {
+                meta: bytecode::BytecodeMeta::new(
+                    Some(entry.file_name.clone()),
+                    emit.embed_source.then(|| entry.src.clone()),
+                ),
+                chunk,
+            };
+            write_bytecode(emit.path, &file)?;
+            println!("Build succeeded. Wrote bytecode to {}", emit.path.display());
+            chunk = file.chunk;
         }
-        println!("Build succeeded. Wrote bytecode to {}", path.display());
     }
 
     // 4) Run VM if requested
-    if mode_run {
-        let mut m = vm::Vm::new();
+    if let Some(run) = run {
+        let mut m = vm::Vm::with_limits(run.limits);
+        m.set_trace(run.trace);
         if let Err(msg) = m.run(&chunk) {
             eprintln!("A_VM: {}", msg);
             return Err(());
         }
-    } else if emit_path.is_none() {
+    } else if !emitting {
         println!("Build succeeded.");
     }
 
     Ok(())
 }
+
+/// Encode and write a bytecode file, reporting failures to stderr.
+pub fn write_bytecode(path: &Path, file: &bytecode::BytecodeFile) -> Result<(), ()> {
+    let data = match bytecode::encode_file(file) {
+        Ok(d) => d,
+        Err(msg) => {
+            eprintln!("A_BYTECODE: {}", msg);
+            return Err(());
+        }
+    };
+    if let Err(e) = fs::write(path, data) {
+        eprintln!("A_BUILD: failed to write bytecode: {}", e);
+        return Err(());
+    }
+    Ok(())
+}
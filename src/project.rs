@@ -0,0 +1,321 @@
+// src/project.rs
+//
+// Projects: a folder with an `a.toml` manifest that names the program, says
+// which file it starts in, which extra folders imports are looked for in and
+// how warnings are reported. Inside a project (or any folder below it),
+// `a run`, `a build` and `a test` need no file name. `a new` and `a init`
+// create one.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::lint;
+
+pub const MANIFEST: &str = "a.toml";
+/// Folder of a project's tests: every `.a` file in it is one test.
+pub const TESTS_DIR: &str = "tests";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    name: String,
+    version: String,
+    #[serde(default = "default_entry")]
+    entry: String,
+    #[serde(default)]
+    paths: Vec<String>,
+    #[serde(default)]
+    lints: LintSettings,
+}
+
+fn default_entry() -> String {
+    "src/main.a".to_string()
+}
+
+/// The `[lints]` table: warning codes (or `all`) to allow or deny.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LintSettings {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+pub struct Project {
+    pub name: String,
+    pub version: String,
+    /// Folder holding `a.toml`, relative to the current folder (empty when
+    /// it is the current folder), so paths in messages stay short.
+    pub root: PathBuf,
+    /// File `main` is in.
+    pub entry: PathBuf,
+    /// Extra folders to look for imports in.
+    pub paths: Vec<PathBuf>,
+    pub lints: LintSettings,
+}
+
+impl Project {
+    pub fn tests_dir(&self) -> PathBuf {
+        self.root.join(TESTS_DIR)
+    }
+}
+
+/// The project the current folder is in: the nearest `a.toml` here or in a
+/// folder above.
+pub fn find() -> Result<Option<Project>, String> {
+    let cwd = env::current_dir().map_err(|e| format!("can't tell the current folder: {}", e))?;
+    let mut root = PathBuf::new();
+    for dir in cwd.ancestors() {
+        if dir.join(MANIFEST).is_file() {
+            return load(root).map(Some);
+        }
+        root.push("..");
+    }
+    Ok(None)
+}
+
+fn load(root: PathBuf) -> Result<Project, String> {
+    let path = root.join(MANIFEST);
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let manifest: Manifest = toml::from_str(&text)
+        .map_err(|e| format!("{} is not a valid manifest: {}", path.display(), e))?;
+
+    check_name(&manifest.name).map_err(|msg| format!("{}: {}", path.display(), msg))?;
+    let codes = |list: Vec<String>| -> Result<Vec<String>, String> {
+        list.iter()
+            .map(|c| lint::parse_code(c).map_err(|msg| format!("{}: {}", path.display(), msg)))
+            .collect()
+    };
+    let lints = LintSettings {
+        allow: codes(manifest.lints.allow)?,
+        deny: codes(manifest.lints.deny)?,
+    };
+
+    Ok(Project {
+        name: manifest.name,
+        version: manifest.version,
+        entry: root.join(&manifest.entry),
+        paths: manifest.paths.iter().map(|p| root.join(p)).collect(),
+        lints,
+        root,
+    })
+}
+
+/// A project name is also its folder's name: letters, digits, `_` and `-`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let ok = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if ok {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{}` can't be a project name: start with a letter, then use letters, digits, `_` or `-`",
+            name
+        ))
+    }
+}
+
+/// Every test in `path`: the file itself, or the `.a` files in the folder.
+pub fn test_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let entries = fs::read_dir(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| Some(e.ok()?.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "a"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/* =========================
+   Scaffolding (`a new`, `a init`)
+   ========================= */
+
+const MAIN_SRC: &str = r#"Use greeting
+
+Func main() {
+    Print(greeting.hello("world"))
+}
+"#;
+
+const GREETING_SRC: &str = r#"Func hello(name: String) -> String {
+    Return "Hello, " + name + "!"
+}
+"#;
+
+const TEST_SRC: &str = r#"// A test passes when it runs without an error and prints exactly what
+// greeting_test.out holds.
+Use greeting
+
+Func main() {
+    Print(greeting.hello("test"))
+}
+"#;
+
+const TEST_OUT: &str = "Hello, test!\n";
+
+/// `a.toml` for project `name` starting in `entry`. Files in the same folder
+/// as `entry` can be imported from anywhere in the project.
+fn manifest_text(name: &str, entry: &str) -> String {
+    let paths = match Path::new(entry).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => format!("[\"{}\"]", dir.display()),
+        _ => "[]".to_string(),
+    };
+    format!(
+        r#"name = "{}"
+version = "0.1.0"
+entry = "{}"
+# More folders `Use` and `Import` look in, after the importing file's own.
+paths = {}
+
+[lints]
+allow = []
+deny = []
+"#,
+        name, entry, paths
+    )
+}
+
+/// Make project `name` in a new folder of that name. Returns the folder.
+pub fn create(name: &str) -> Result<PathBuf, String> {
+    check_name(name)?;
+    let dir = PathBuf::from(name);
+    if dir.exists() {
+        return Err(format!("{} already exists", dir.display()));
+    }
+    scaffold(&dir, name)?;
+    Ok(dir)
+}
+
+/// Make the current folder a project named after it. Code already there is
+/// kept (`src/main.a`, else `main.a`, becomes the entry); an empty folder
+/// gets the sample program.
+pub fn init() -> Result<String, String> {
+    let cwd = env::current_dir().map_err(|e| format!("can't tell the current folder: {}", e))?;
+    let name = cwd
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| "the current folder has no name to give the project".to_string())?
+        .to_string();
+    check_name(&name)?;
+    let manifest = Path::new(MANIFEST);
+    if manifest.exists() {
+        return Err(format!("{} already exists", MANIFEST));
+    }
+    if Path::new(&default_entry()).exists() {
+        write(manifest, &manifest_text(&name, &default_entry()))?;
+    } else if Path::new("main.a").exists() {
+        write(manifest, &manifest_text(&name, "main.a"))?;
+    } else {
+        scaffold(Path::new(""), &name)?;
+    }
+    Ok(name)
+}
+
+/// Write `a.toml` in `dir`, with the sample program and its test.
+fn scaffold(dir: &Path, name: &str) -> Result<(), String> {
+    write(&dir.join(MANIFEST), &manifest_text(name, &default_entry()))?;
+    write(&dir.join("src/main.a"), MAIN_SRC)?;
+    write(&dir.join("src/greeting.a"), GREETING_SRC)?;
+    write(&dir.join(TESTS_DIR).join("greeting_test.a"), TEST_SRC)?;
+    write(&dir.join(TESTS_DIR).join("greeting_test.out"), TEST_OUT)
+}
+
+fn write(path: &Path, text: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+    }
+    fs::write(path, text).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::{Level, LintConfig};
+    use crate::optimize::OptLevel;
+    use crate::{modules, pipeline, vm};
+
+    /// A fresh, empty folder for one test.
+    fn folder(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("a-project-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn project_names() {
+        assert!(check_name("hello").is_ok());
+        assert!(check_name("my_app-2").is_ok());
+        for bad in ["", "2fast", "my app", "-x", "a/b"] {
+            assert!(check_name(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn manifests_load_with_defaults_and_are_checked() {
+        let dir = folder("manifest");
+        write(&dir.join(MANIFEST), "name = \"demo\"\nversion = \"1.0.0\"\n").unwrap();
+        let project = load(dir.clone()).unwrap();
+        assert_eq!((project.name.as_str(), project.version.as_str()), ("demo", "1.0.0"));
+        assert_eq!(project.entry, dir.join("src/main.a"));
+        assert!(project.paths.is_empty() && project.lints.deny.is_empty());
+
+        let text = concat!(
+            "name = \"demo\"\nversion = \"1\"\npaths = [\"lib\"]\n\n",
+            "[lints]\ndeny = [\"w001\"]\n",
+        );
+        write(&dir.join(MANIFEST), text).unwrap();
+        let project = load(dir.clone()).unwrap();
+        assert_eq!(project.paths, [dir.join("lib")]);
+        assert_eq!(project.lints.deny, ["W001"]);
+
+        for bad in [
+            "name = \"demo\"\n",
+            "name = \"demo\"\nversion = \"1\"\nauthor = \"me\"\n",
+            "name = \"2demo\"\nversion = \"1\"\n",
+            "name = \"demo\"\nversion = \"1\"\n[lints]\nallow = [\"W999\"]\n",
+        ] {
+            write(&dir.join(MANIFEST), bad).unwrap();
+            assert!(load(dir.clone()).is_err(), "{}", bad);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn scaffolded_project_runs_and_passes_its_test() {
+        let dir = folder("scaffold");
+        scaffold(&dir, "demo").unwrap();
+        let project = load(dir.clone()).unwrap();
+
+        let run = |entry: &Path| {
+            let graph = modules::load(entry, &project.paths).expect("loads");
+            let mut lints = LintConfig::default();
+            lints.set("all", Level::Deny);
+            let chunk = pipeline::compile(&graph, OptLevel::O1, &lints).expect("compiles");
+            let mut m = vm::Vm::new();
+            m.capture_output();
+            m.run(&chunk).unwrap();
+            m.take_output()
+        };
+        assert_eq!(run(&project.entry), "Hello, world!\n");
+
+        let tests = test_files(&project.tests_dir()).unwrap();
+        assert_eq!(tests, [project.tests_dir().join("greeting_test.a")]);
+        assert_eq!(run(&tests[0]), TEST_OUT);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_test_file_is_its_own_test() {
+        let file = PathBuf::from("one_test.a");
+        assert_eq!(test_files(&file).unwrap(), [file]);
+    }
+}
@@ -0,0 +1,342 @@
+// src/verify.rs
+//
+// Static checks on a decoded Chunk before the VM touches it.
+// A `.a.byte` file can be corrupted or written by hand, so we make sure every
+// jump lands inside the program, every local/constant index exists, and the
+// value stack has the same, never-negative depth on every path. A function
+// body is checked on its own, starting with just its arguments on the stack.
+
+use crate::bytecode::{Chunk, Instr, Value};
+
+pub fn verify_chunk(chunk: &Chunk) -> Result<(), String> {
+    let len = chunk.code.len();
+
+    if chunk.lines.len() != len {
+        return Err(format!(
+            "line table has {} entries but there are {} instructions",
+            chunk.lines.len(),
+            len
+        ));
+    }
+
+    if chunk.files.len() != len {
+        return Err(format!(
+            "file table has {} entries but there are {} instructions",
+            chunk.files.len(),
+            len
+        ));
+    }
+    // Without file names every instruction is from the one unnamed file.
+    let file_count = chunk.file_names.len().max(1);
+    if let Some(ip) = chunk.files.iter().position(|&f| f as usize >= file_count) {
+        return Err(format!(
+            "instruction {} is from file {}, but only {} file(s) are named",
+            ip, chunk.files[ip], file_count
+        ));
+    }
+
+    for (ip, instr) in chunk.code.iter().enumerate() {
+        check_operands(chunk, ip, instr)?;
+    }
+
+    check_stack_depth(chunk)
+}
+
+fn check_operands(chunk: &Chunk, ip: usize, instr: &Instr) -> Result<(), String> {
+    let len = chunk.code.len();
+    match *instr {
+        // Jumping to `len` means "finish", like falling off the end.
+        Instr::Jump(t) | Instr::JumpIfFalse(t) if t > len => Err(format!(
+            "instruction {} ({:?}) jumps to {}, but the program only has {} instructions",
+            ip, instr, t, len
+        )),
+        Instr::LoadLocal(i) | Instr::StoreLocal(i) if i >= chunk.locals.len() => Err(format!(
+            "instruction {} ({:?}) uses local slot {}, but only {} locals are declared",
+            ip,
+            instr,
+            i,
+            chunk.locals.len()
+        )),
+        Instr::Call(t, _) if !matches!(chunk.code.get(t), Some(Instr::Enter(..))) => Err(format!(
+            "instruction {} ({:?}) calls {}, which is not the start (`Enter`) of a function",
+            ip, instr, t
+        )),
+        Instr::Enter(first, n) if first.saturating_add(n) > chunk.locals.len() => Err(format!(
+            "instruction {} ({:?}) uses local slots up to {}, but only {} locals are declared",
+            ip,
+            instr,
+            first.saturating_add(n),
+            chunk.locals.len()
+        )),
+        Instr::LoadConst(i) if i >= chunk.consts.len() => Err(format!(
+            "instruction {} ({:?}) uses constant {}, but the constant pool has {} entries",
+            ip,
+            instr,
+            i,
+            chunk.consts.len()
+        )),
+        Instr::MakeRecord(i, _)
+        | Instr::GetField(i)
+        | Instr::SetField(i)
+        | Instr::MakeVariant(i, _, _)
+            if !matches!(chunk.consts.get(i), Some(Value::Str(_))) =>
+        {
+            Err(format!(
+                "instruction {} ({:?}) needs constant {} to be a name, but it isn't",
+                ip, instr, i
+            ))
+        }
+        Instr::Switch(n)
+            if !chunk.code.get(ip + 1..=ip.saturating_add(n)).is_some_and(|table| {
+                table.iter().all(|i| matches!(i, Instr::Jump(_)))
+            }) =>
+        {
+            Err(format!(
+                "instruction {} ({:?}) must be followed by a table of {} Jump instructions",
+                ip, instr, n
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// How many values an instruction pops and then pushes.
+fn stack_effect(instr: &Instr) -> (usize, usize) {
+    match *instr {
+        Instr::LoadConst(_) | Instr::ReadLine | Instr::LoadLocal(_) => (0, 1),
+        Instr::Print(n) | Instr::Write(n) => (n, 0),
+        Instr::PrintSep(n) => (n.saturating_add(1), 0),
+        Instr::Format(n) => (n.saturating_add(1), 1),
+        Instr::Call(_, n) => (n, 1),
+        Instr::MakeList(n) | Instr::MakeVariant(_, _, n) => (n, 1),
+        Instr::MakeMap(n) | Instr::MakeRecord(_, n) => (n.saturating_mul(2), 1),
+        Instr::GetField(_) | Instr::Payload(_) => (1, 1),
+        Instr::SetField(_) => (2, 1),
+        Instr::Len
+        | Instr::Keys
+        | Instr::ToFloat
+        | Instr::ToInt
+        | Instr::Upper
+        | Instr::Lower
+        | Instr::Trim => (1, 1),
+        Instr::SetIndex | Instr::Substring => (3, 1),
+        Instr::AddInt
+        | Instr::CmpEq
+        | Instr::CmpNe
+        | Instr::CmpLt
+        | Instr::CmpLe
+        | Instr::CmpGt
+        | Instr::CmpGe
+        | Instr::AddFloat
+        | Instr::CmpLtFloat
+        | Instr::CmpLeFloat
+        | Instr::CmpGtFloat
+        | Instr::CmpGeFloat
+        | Instr::Concat
+        | Instr::Split
+        | Instr::Contains
+        | Instr::Index
+        | Instr::ListPush
+        | Instr::Has => (2, 1),
+        Instr::StoreLocal(_)
+        | Instr::JumpIfFalse(_)
+        | Instr::Switch(_)
+        | Instr::Ret
+        | Instr::Pop => (1, 0),
+        Instr::Jump(_) | Instr::Halt | Instr::Enter(..) => (0, 0),
+    }
+}
+
+/// Where control can go after `ip` (not counting "past the end").
+fn successors(ip: usize, instr: &Instr) -> Vec<usize> {
+    match *instr {
+        Instr::Jump(t) => vec![t],
+        Instr::JumpIfFalse(t) => vec![ip + 1, t],
+        Instr::Switch(n) => (ip + 1..=ip + n).collect(),
+        Instr::Halt | Instr::Ret => vec![],
+        _ => vec![ip + 1],
+    }
+}
+
+fn check_stack_depth(chunk: &Chunk) -> Result<(), String> {
+    let len = chunk.code.len();
+    let mut depth_at: Vec<Option<usize>> = vec![None; len];
+    let mut work = Vec::new();
+
+    if len > 0 {
+        depth_at[0] = Some(0);
+        work.push(0);
+    }
+    // Each function starts with its arguments on the stack; every call to it
+    // must pass the same number.
+    for (ip, instr) in chunk.code.iter().enumerate() {
+        let Instr::Call(target, argc) = *instr else {
+            continue;
+        };
+        match depth_at[target] {
+            None => {
+                depth_at[target] = Some(argc);
+                work.push(target);
+            }
+            Some(d) if d != argc => {
+                return Err(format!(
+                    "instruction {} calls the function at {} with {} argument(s), but it is also called with {}",
+                    ip, target, argc, d
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+
+    while let Some(ip) = work.pop() {
+        let instr = &chunk.code[ip];
+        let depth = depth_at[ip].unwrap_or(0);
+        let (pops, pushes) = stack_effect(instr);
+
+        if depth < pops {
+            return Err(format!(
+                "instruction {} ({:?}) needs {} value(s) on the stack, but only {} can be there",
+                ip, instr, pops, depth
+            ));
+        }
+        // The function's own values are gone by then: only the result is left.
+        if matches!(instr, Instr::Ret) && depth != 1 {
+            return Err(format!(
+                "instruction {} (Ret) needs exactly the result on the stack, but {} value(s) can be there",
+                ip, depth
+            ));
+        }
+        let after = depth - pops + pushes;
+
+        for next in successors(ip, instr) {
+            if next >= len {
+                continue;
+            }
+            match depth_at[next] {
+                None => {
+                    depth_at[next] = Some(after);
+                    work.push(next);
+                }
+                Some(d) if d != after => {
+                    return Err(format!(
+                        "instruction {} can be reached with {} or {} values on the stack; every path must agree",
+                        next, d, after
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::OptLevel;
+    use crate::pipeline::compile_test_program;
+
+    fn chunk(code: Vec<Instr>) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.locals.push("x".to_string());
+        chunk.consts.push(Value::Int(1));
+        for instr in code {
+            chunk.push(instr, 1);
+        }
+        chunk
+    }
+
+    fn rejects(code: Vec<Instr>, why: &str) {
+        let err = verify_chunk(&chunk(code)).unwrap_err();
+        assert!(err.contains(why), "expected `{}` in: {}", why, err);
+    }
+
+    #[test]
+    fn accepts_a_well_formed_chunk() {
+        let code = vec![
+            Instr::LoadConst(0),
+            Instr::StoreLocal(0),
+            Instr::LoadLocal(0),
+            Instr::Print(1),
+            Instr::Halt,
+        ];
+        assert_eq!(verify_chunk(&chunk(code)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_jumps_outside_the_program() {
+        rejects(vec![Instr::Jump(5), Instr::Halt], "jumps to 5");
+        rejects(vec![Instr::LoadConst(0), Instr::JumpIfFalse(9)], "jumps to 9");
+    }
+
+    #[test]
+    fn rejects_missing_locals_and_constants() {
+        rejects(vec![Instr::LoadLocal(1)], "local slot 1");
+        rejects(vec![Instr::LoadConst(0), Instr::StoreLocal(4)], "local slot 4");
+        rejects(vec![Instr::LoadConst(3)], "constant 3");
+    }
+
+    #[test]
+    fn rejects_calls_that_dont_start_a_function() {
+        rejects(
+            vec![Instr::Call(2, 0), Instr::Halt, Instr::LoadConst(0), Instr::Ret],
+            "not the start",
+        );
+    }
+
+    #[test]
+    fn rejects_short_switch_tables() {
+        rejects(
+            vec![Instr::LoadConst(0), Instr::Switch(2), Instr::Jump(3), Instr::Halt],
+            "table of 2 Jump",
+        );
+    }
+
+    #[test]
+    fn rejects_paths_that_disagree_on_stack_depth() {
+        // One way pushes a value before the join at 4, the other doesn't.
+        let code = vec![
+            Instr::LoadConst(0),
+            Instr::JumpIfFalse(4),
+            Instr::LoadConst(0),
+            Instr::Jump(4),
+            Instr::Halt,
+        ];
+        rejects(code, "every path must agree");
+        rejects(vec![Instr::AddInt], "needs 2 value(s)");
+    }
+
+    #[test]
+    fn rejects_ret_without_exactly_the_result() {
+        let code = vec![
+            Instr::Call(2, 0),
+            Instr::Halt,
+            Instr::Enter(0, 0),
+            Instr::LoadConst(0),
+            Instr::LoadConst(0),
+            Instr::Ret,
+        ];
+        rejects(code, "exactly the result");
+    }
+
+    #[test]
+    fn accepts_what_the_compiler_produces() {
+        let programs = [
+            "Func main() {\n    Print(\"hi\")\n}\n",
+            "Func main() {\n    x = 3\n    If x > 5 then {\n        Print(\"big\")\n    } ElseIf x == 3 then {\n        Print(\"three\")\n    } Else {\n        Print(\"small\")\n    }\n}\n",
+            "Func main() {\n    scores = [90, 75]\n    Push(scores, 60)\n    For s in scores {\n        Print(s, Len(scores), scores[0])\n    }\n}\n",
+            "Func main() {\n    ages = {\"ann\": 31}\n    ages[\"cy\"] = 40\n    If Has(ages, \"ann\") then {\n        Print(ages[\"ann\"])\n    }\n    For k in Keys(ages) {\n        Print(k)\n    }\n}\n",
+            "Struct Point { x: Int, y: Int }\n\nFunc main() {\n    p = Point { x: 1, y: 2 }\n    p.x = 10\n    Print(p.x + p.y, p)\n}\n",
+            "Enum Shape { Circle(Int), Square(Int, Int), Empty }\n\nFunc main() {\n    s = Square(2, 3)\n    Match s {\n        Circle(r) => Print(r)\n        Square(w, h) => Print(w + h)\n        Empty => Print(\"none\")\n    }\n}\n",
+            "Func main() {\n    price = 2.5\n    Print(price + ToFloat(3), ToInt(9.99), Upper(Trim(\" a \")), Substring(\"hello\", 1, 3))\n    Write(Format(\"{} + {}\", 1, 2))\n    PrintSep(\", \", \"a\", Max(1, 2), Join(Split(\"a,b\", \",\"), \"-\"))\n}\n",
+            "Func upto(n: Int) -> Int {\n    If n > 3 then {\n        Return n\n    }\n    Return upto(n + 1)\n}\n\nFunc main() {\n    Print(upto(0))\n}\n",
+        ];
+        for src in programs {
+            for opt in [OptLevel::O0, OptLevel::O1] {
+                let chunk = compile_test_program(src, opt);
+                assert_eq!(verify_chunk(&chunk), Ok(()), "{:?}:\n{}", opt, src);
+            }
+        }
+    }
+}
@@ -2,131 +2,993 @@
 //
 // Minimal stack-based VM that executes Chunk bytecode.
 
-use crate::bytecode::{Chunk, Instr, Value};
+use crate::bytecode::{map_insert, Chunk, Instr, Value};
+use crate::i18n;
+use std::cmp::Ordering;
+use std::fmt::Display;
 use std::io::{self, Write as _};
 
+/// Resource caps for one `Vm::run`. `None` means "no limit".
+///
+/// The defaults are generous enough for any sensible exercise but still stop a
+/// runaway loop or recursion before it takes the machine down with it.
+#[derive(Debug, Clone, Copy)]
+pub struct VmLimits {
+    /// Maximum number of instructions executed.
+    pub max_steps: Option<u64>,
+    /// Maximum number of values on the value stack.
+    pub max_stack: Option<usize>,
+    /// Maximum nesting of function calls.
+    pub max_call_depth: Option<usize>,
+    /// Maximum total bytes of strings created while running.
+    pub max_string_bytes: Option<usize>,
+}
+
+impl Default for VmLimits {
+    fn default() -> Self {
+        Self {
+            max_steps: Some(100_000_000),
+            max_stack: Some(100_000),
+            max_call_depth: Some(1_000),
+            max_string_bytes: Some(64 * 1024 * 1024),
+        }
+    }
+}
+
+impl VmLimits {
+    /// No caps at all (the old behaviour).
+    #[allow(dead_code)]
+    pub fn unlimited() -> Self {
+        Self {
+            max_steps: None,
+            max_stack: None,
+            max_call_depth: None,
+            max_string_bytes: None,
+        }
+    }
+}
+
+/// How `--trace` reports executed instructions (always on stderr).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceFormat {
+    /// One aligned, human-readable line per instruction.
+    Text,
+    /// One JSON object per line, for tools.
+    Json,
+}
+
+/// A function call in progress.
+struct Frame {
+    /// Where to continue once the function returns.
+    return_ip: usize,
+    /// First local slot of the function (set by its `Enter`).
+    first: usize,
+    /// The caller's values of the function's slots, put back on `Ret`.
+    saved: Vec<Value>,
+}
+
+/// Where `Print` and `Write` send the program's output.
+enum Output {
+    Stdout,
+    /// Kept for the caller (see `Vm::capture_output`).
+    Captured(Vec<u8>),
+}
+
+/// Where `ReadLine` reads from.
+enum Input {
+    Stdin,
+    /// Lines given by the caller (see `Vm::give_input`), next one first.
+    Given(std::collections::VecDeque<String>),
+}
+
 pub struct Vm {
     stack: Vec<Value>,
     locals: Vec<Value>,
+    frames: Vec<Frame>,
     ip: usize,
+    limits: VmLimits,
+    steps: u64,
+    string_bytes: usize,
+    trace: Option<TraceFormat>,
+    input: Input,
+    output: Output,
 }
 
 impl Vm {
+    #[allow(dead_code)]
     pub fn new() -> Self {
+        Self::with_limits(VmLimits::default())
+    }
+
+    pub fn with_limits(limits: VmLimits) -> Self {
         Self {
             stack: Vec::new(),
             locals: Vec::new(),
+            frames: Vec::new(),
             ip: 0,
+            limits,
+            steps: 0,
+            string_bytes: 0,
+            trace: None,
+            input: Input::Stdin,
+            output: Output::Stdout,
+        }
+    }
+
+    /// Log every executed instruction to stderr in the given format.
+    pub fn set_trace(&mut self, trace: Option<TraceFormat>) {
+        self.trace = trace;
+    }
+
+    /// Make `ReadLine` read the lines of `text` instead of stdin. Once they
+    /// run out it gives empty text, as it does at the end of stdin.
+    pub fn give_input(&mut self, text: &str) {
+        self.input = Input::Given(text.lines().map(str::to_string).collect());
+    }
+
+    /// Keep the program's output instead of printing it; `take_output`
+    /// returns what it printed so far.
+    pub fn capture_output(&mut self) {
+        self.output = Output::Captured(Vec::new());
+    }
+
+    /// The output kept since the last call (empty unless captured).
+    pub fn take_output(&mut self) -> String {
+        match &mut self.output {
+            Output::Captured(out) => String::from_utf8_lossy(&std::mem::take(out)).into_owned(),
+            Output::Stdout => String::new(),
         }
     }
 
     pub fn run(&mut self, chunk: &Chunk) -> Result<(), String> {
+        self.start(chunk);
+        let res = self.run_to_end(chunk);
+        // `Write` leaves text without a newline waiting in the buffer; show it
+        // before the program ends (or its error is printed).
+        flush_output();
+        res
+    }
+
+    fn run_to_end(&mut self, chunk: &Chunk) -> Result<(), String> {
+        while self.step(chunk)? {}
+        Ok(())
+    }
+
+    /// Reset the machine so `step` begins at the first instruction of `chunk`.
+    pub fn start(&mut self, chunk: &Chunk) {
+        self.stack.clear();
         self.locals = vec![Value::Unit; chunk.locals.len()];
+        self.frames.clear();
         self.ip = 0;
+        self.steps = 0;
+        self.string_bytes = 0;
+    }
 
-        while self.ip < chunk.code.len() {
-            let instr = chunk.code[self.ip].clone();
-            self.ip += 1;
+    /// Execute a single instruction. Returns `false` once the program has finished.
+    pub fn step(&mut self, chunk: &Chunk) -> Result<bool, String> {
+        if self.ip >= chunk.code.len() {
+            return Ok(false);
+        }
+        self.count_step()?;
 
-            match instr {
-                Instr::Const(v) => self.stack.push(v),
+        if let Some(format) = self.trace {
+            let ip = self.ip;
+            let before = self.locals.clone();
+            let res = self.exec(chunk);
+            self.trace_step(chunk, ip, &before, format);
+            return res;
+        }
+        self.exec(chunk)
+    }
 
-                Instr::ReadLine => {
-                    let mut line = String::new();
-                    // Ensure prompt flush works if user did Write("...")
-                    io::stdout().flush().map_err(|e| e.to_string())?;
-                    io::stdin()
-                        .read_line(&mut line)
-                        .map_err(|e| e.to_string())?;
-                    // Strip trailing newline(s)
-                    while line.ends_with('\n') || line.ends_with('\r') {
-                        line.pop();
+    fn exec(&mut self, chunk: &Chunk) -> Result<bool, String> {
+        let instr = chunk.code[self.ip];
+        self.ip += 1;
+
+        match instr {
+            Instr::LoadConst(idx) => {
+                let v = chunk
+                    .consts
+                    .get(idx)
+                    .ok_or_else(|| format!("Bad constant index {}", idx))?
+                    .clone();
+                self.push(v)?;
+            }
+
+            Instr::ReadLine => {
+                let mut line = String::new();
+                match &mut self.input {
+                    Input::Stdin => {
+                        // Show a prompt printed with Write("...") before waiting.
+                        flush_output();
+                        io::stdin()
+                            .read_line(&mut line)
+                            .map_err(|e| e.to_string())?;
                     }
-                    self.stack.push(Value::Str(line));
+                    Input::Given(lines) => line = lines.pop_front().unwrap_or_default(),
+                }
+                // Strip trailing newline(s)
+                while line.ends_with('\n') || line.ends_with('\r') {
+                    line.pop();
+                }
+                self.charge_string(line.len())?;
+                self.push(Value::Str(line))?;
+            }
+
+            Instr::Print(n) | Instr::Write(n) | Instr::PrintSep(n) => {
+                let vals = self.pop_values(n, "print")?;
+                let sep = match instr {
+                    Instr::PrintSep(_) => self.pop_str("PrintSep")?,
+                    _ => " ".to_string(),
+                };
+                let mut out = join_values(&vals, &sep);
+                if !matches!(instr, Instr::Write(_)) {
+                    out.push('\n');
+                }
+                if !self.write_output(&out)? {
+                    // Nobody reads the output any more (e.g. piped into `head`).
+                    self.ip = chunk.code.len();
+                    return Ok(false);
                 }
+            }
+
+            Instr::Format(n) => {
+                let vals = self.pop_values(n, "format")?;
+                let template = self.pop_str("Format")?;
+                let text = format_values(&template, &vals)?;
+                self.charge_string(text.len())?;
+                self.push(Value::Str(text))?;
+            }
 
-                Instr::Print(n) => {
-                    if self.stack.len() < n {
+            Instr::AddInt => {
+                let b = self.stack.pop().ok_or("Stack underflow on AddInt")?;
+                let a = self.stack.pop().ok_or("Stack underflow on AddInt")?;
+                match (a, b) {
+                    (Value::Int(x), Value::Int(y)) => {
+                        let sum = x.checked_add(y).ok_or_else(|| overflow_lesson(x, y))?;
+                        self.stack.push(Value::Int(sum));
+                    }
+                    (x, y) => {
                         return Err(format!(
-                            "Stack underflow: wanted to print {} values, but stack has {}",
-                            n,
-                            self.stack.len()
-                        ));
+                            "Type error on AddInt: got {} + {}",
+                            type_name(&x),
+                            type_name(&y)
+                        ))
                     }
-                    let start = self.stack.len() - n;
-                    let vals: Vec<Value> = self.stack.drain(start..).collect();
+                }
+            }
 
-                    let mut out = String::new();
-                    for (i, v) in vals.iter().enumerate() {
-                        if i > 0 {
-                            out.push(' ');
-                        }
-                        out.push_str(&value_to_string(v));
+            Instr::AddFloat => {
+                let b = self.stack.pop().ok_or("Stack underflow on AddFloat")?;
+                let a = self.stack.pop().ok_or("Stack underflow on AddFloat")?;
+                match (a, b) {
+                    (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x + y)),
+                    (x, y) => {
+                        return Err(format!(
+                            "Type error on AddFloat: got {} + {}",
+                            type_name(&x),
+                            type_name(&y)
+                        ))
                     }
-                    println!("{}", out);
                 }
+            }
 
-                Instr::AddInt => {
-                    let b = self.stack.pop().ok_or("Stack underflow on AddInt")?;
-                    let a = self.stack.pop().ok_or("Stack underflow on AddInt")?;
-                    match (a, b) {
-                        (Value::Int(x), Value::Int(y)) => self.stack.push(Value::Int(x + y)),
-                        (x, y) => {
-                            return Err(format!(
-                                "Type error on AddInt: got {} + {}",
-                                type_name(&x),
-                                type_name(&y)
-                            ))
-                        }
+            Instr::ToFloat => {
+                let v = self.stack.pop().ok_or("Stack underflow on ToFloat")?;
+                match v {
+                    Value::Int(i) => self.stack.push(Value::Float(i as f64)),
+                    Value::Float(f) => self.stack.push(Value::Float(f)),
+                    other => {
+                        return Err(format!(
+                            "Type error: ToFloat needs Int or Float, got {}",
+                            type_name(&other)
+                        ))
                     }
                 }
+            }
 
-                Instr::LoadLocal(i) => {
-                    let v = self.locals.get(i).cloned().unwrap_or(Value::Unit);
-                    self.stack.push(v);
+            Instr::ToInt => {
+                let v = self.stack.pop().ok_or("Stack underflow on ToInt")?;
+                match v {
+                    Value::Int(i) => self.stack.push(Value::Int(i)),
+                    // `as` would quietly turn NaN into 0 and clamp huge values.
+                    Value::Float(f) if f.is_finite() && f.trunc().abs() < i64::MAX as f64 => {
+                        self.stack.push(Value::Int(f.trunc() as i64))
+                    }
+                    Value::Float(f) => return Err(to_int_lesson(f)),
+                    other => {
+                        return Err(format!(
+                            "Type error: ToInt needs Int or Float, got {}",
+                            type_name(&other)
+                        ))
+                    }
                 }
+            }
 
-                Instr::StoreLocal(i) => {
-                    let v = self.stack.pop().ok_or("Stack underflow on StoreLocal")?;
-                    if i >= self.locals.len() {
-                        self.locals.resize(i + 1, Value::Unit);
+            Instr::Concat => {
+                let b = self.pop_str("Concat")?;
+                let a = self.pop_str("Concat")?;
+                let text = a + &b;
+                self.charge_string(text.len())?;
+                self.push(Value::Str(text))?;
+            }
+
+            Instr::Upper => {
+                let s = self.pop_str("Upper")?.to_uppercase();
+                self.charge_string(s.len())?;
+                self.push(Value::Str(s))?;
+            }
+
+            Instr::Lower => {
+                let s = self.pop_str("Lower")?.to_lowercase();
+                self.charge_string(s.len())?;
+                self.push(Value::Str(s))?;
+            }
+
+            Instr::Trim => {
+                let s = self.pop_str("Trim")?.trim().to_string();
+                self.charge_string(s.len())?;
+                self.push(Value::Str(s))?;
+            }
+
+            Instr::Split => {
+                let sep = self.pop_str("Split")?;
+                let s = self.pop_str("Split")?;
+                // An empty separator splits into single characters.
+                let pieces: Vec<Value> = if sep.is_empty() {
+                    s.chars().map(|c| Value::Str(c.to_string())).collect()
+                } else {
+                    s.split(sep.as_str()).map(|p| Value::Str(p.to_string())).collect()
+                };
+                // The pieces hold all of `s` but the separators; charge all of it.
+                self.charge_string(s.len())?;
+                self.push(Value::List(pieces))?;
+            }
+
+            Instr::Contains => {
+                let part = self.pop_str("Contains")?;
+                let s = self.pop_str("Contains")?;
+                self.push(Value::Bool(s.contains(part.as_str())))?;
+            }
+
+            Instr::Substring => {
+                let end = self.stack.pop().ok_or("Stack underflow on Substring")?;
+                let start = self.stack.pop().ok_or("Stack underflow on Substring")?;
+                let s = self.pop_str("Substring")?;
+                let (Value::Int(start), Value::Int(end)) = (&start, &end) else {
+                    return Err(format!(
+                        "Type error: Substring positions must be Int, got {} and {}",
+                        type_name(&start),
+                        type_name(&end)
+                    ));
+                };
+                let len = s.chars().count();
+                let range = usize::try_from(*start)
+                    .ok()
+                    .zip(usize::try_from(*end).ok())
+                    .filter(|(a, b)| a <= b && *b <= len);
+                let Some((a, b)) = range else {
+                    return Err(substring_lesson(*start, *end, len));
+                };
+                let part: String = s.chars().skip(a).take(b - a).collect();
+                self.charge_string(part.len())?;
+                self.push(Value::Str(part))?;
+            }
+
+            Instr::LoadLocal(i) => {
+                let v = self.locals.get(i).cloned().unwrap_or(Value::Unit);
+                self.push(v)?;
+            }
+
+            Instr::StoreLocal(i) => {
+                let v = self.stack.pop().ok_or("Stack underflow on StoreLocal")?;
+                if i >= self.locals.len() {
+                    self.locals.resize(i + 1, Value::Unit);
+                }
+                self.locals[i] = v;
+            }
+
+            Instr::Jump(target) => {
+                self.ip = target;
+            }
+
+            Instr::JumpIfFalse(target) => {
+                let v = self.stack.pop().ok_or("Stack underflow on JumpIfFalse")?;
+                match v {
+                    Value::Bool(false) => self.ip = target,
+                    Value::Bool(true) => {}
+                    other => {
+                        return Err(format!(
+                            "Type error: JumpIfFalse needs Bool, got {}",
+                            type_name(&other)
+                        ))
+                    }
+                }
+            }
+
+            Instr::CmpEq => cmp_bin(self, |a, b| a == b)?,
+            Instr::CmpNe => cmp_bin(self, |a, b| a != b)?,
+            Instr::CmpLt => cmp_ord(self, Ordering::is_lt)?,
+            Instr::CmpLtFloat => cmp_float(self, |a, b| a < b)?,
+            Instr::CmpLeFloat => cmp_float(self, |a, b| a <= b)?,
+            Instr::CmpGtFloat => cmp_float(self, |a, b| a > b)?,
+            Instr::CmpGeFloat => cmp_float(self, |a, b| a >= b)?,
+            Instr::CmpLe => cmp_ord(self, Ordering::is_le)?,
+            Instr::CmpGt => cmp_ord(self, Ordering::is_gt)?,
+            Instr::CmpGe => cmp_ord(self, Ordering::is_ge)?,
+
+            Instr::Halt => self.ip = chunk.code.len(),
+
+            Instr::Call(target, _) => {
+                if let Some(max) = self.limits.max_call_depth {
+                    if self.frames.len() >= max {
+                        return Err(runtime_lesson("vm_call_depth", "why", &[("max", &max)]));
+                    }
+                }
+                self.frames.push(Frame {
+                    return_ip: self.ip,
+                    first: 0,
+                    saved: Vec::new(),
+                });
+                self.ip = target;
+            }
+
+            Instr::Enter(first, n) => {
+                let frame = self
+                    .frames
+                    .last_mut()
+                    .ok_or("Enter outside of a function call")?;
+                let end = first + n;
+                if end > self.locals.len() {
+                    self.locals.resize(end, Value::Unit);
+                }
+                frame.first = first;
+                frame.saved = self.locals[first..end]
+                    .iter_mut()
+                    .map(|v| std::mem::replace(v, Value::Unit))
+                    .collect();
+            }
+
+            Instr::Ret => {
+                let result = self.stack.pop().ok_or("Stack underflow on Ret")?;
+                let frame = self.frames.pop().ok_or("Ret outside of a function call")?;
+                for (i, v) in frame.saved.into_iter().enumerate() {
+                    self.locals[frame.first + i] = v;
+                }
+                self.ip = frame.return_ip;
+                self.push(result)?;
+            }
+
+            Instr::Pop => {
+                self.stack.pop().ok_or("Stack underflow on Pop")?;
+            }
+
+            Instr::MakeList(n) => {
+                if self.stack.len() < n {
+                    return Err(format!(
+                        "Stack underflow: wanted {} list items, but stack has {}",
+                        n,
+                        self.stack.len()
+                    ));
+                }
+                let start = self.stack.len() - n;
+                let items: Vec<Value> = self.stack.drain(start..).collect();
+                self.push(Value::List(items))?;
+            }
+
+            Instr::Index => {
+                let idx = self.stack.pop().ok_or("Stack underflow on Index")?;
+                let list = self.stack.pop().ok_or("Stack underflow on Index")?;
+                let v = match (list, idx) {
+                    (Value::List(items), Value::Int(i)) => {
+                        let len = items.len();
+                        usize::try_from(i)
+                            .ok()
+                            .and_then(|i| items.into_iter().nth(i))
+                            .ok_or_else(|| index_lesson(i, len, "vm_list_index"))?
+                    }
+                    (Value::Str(s), Value::Int(i)) => {
+                        let len = s.chars().count();
+                        let c = usize::try_from(i)
+                            .ok()
+                            .and_then(|i| s.chars().nth(i))
+                            .ok_or_else(|| index_lesson(i, len, "vm_string_index"))?;
+                        Value::Char(c)
+                    }
+                    (Value::Map(entries), key) => entries
+                        .iter()
+                        .find(|(k, _)| *k == key)
+                        .map(|(_, v)| v.clone())
+                        .ok_or_else(|| missing_key_lesson(&key, &entries))?,
+                    (x, y) => {
+                        return Err(format!(
+                            "Type error on Index: got {}[{}]",
+                            type_name(&x),
+                            type_name(&y)
+                        ))
+                    }
+                };
+                self.push(v)?;
+            }
+
+            Instr::Len => {
+                let v = self.stack.pop().ok_or("Stack underflow on Len")?;
+                match v {
+                    Value::List(items) => self.push(Value::Int(items.len() as i64))?,
+                    Value::Map(entries) => self.push(Value::Int(entries.len() as i64))?,
+                    // Characters, not bytes: Len("héllo") is 5.
+                    Value::Str(s) => self.push(Value::Int(s.chars().count() as i64))?,
+                    other => {
+                        return Err(format!(
+                            "Type error: Len needs List, Map or String, got {}",
+                            type_name(&other)
+                        ))
+                    }
+                }
+            }
+
+            Instr::ListPush => {
+                let v = self.stack.pop().ok_or("Stack underflow on ListPush")?;
+                let list = self.stack.pop().ok_or("Stack underflow on ListPush")?;
+                match list {
+                    Value::List(mut items) => {
+                        items.push(v);
+                        self.push(Value::List(items))?;
+                    }
+                    other => {
+                        return Err(format!(
+                            "Type error: ListPush needs List, got {}",
+                            type_name(&other)
+                        ))
                     }
-                    self.locals[i] = v;
                 }
+            }
+
+            Instr::MakeMap(n) => {
+                let count = n.saturating_mul(2);
+                if self.stack.len() < count {
+                    return Err(format!(
+                        "Stack underflow: wanted {} map entries, but stack has {} values",
+                        n,
+                        self.stack.len()
+                    ));
+                }
+                let start = self.stack.len() - count;
+                let flat: Vec<Value> = self.stack.drain(start..).collect();
+                let mut entries = Vec::with_capacity(n);
+                let mut flat = flat.into_iter();
+                while let (Some(k), Some(v)) = (flat.next(), flat.next()) {
+                    map_insert(&mut entries, k, v);
+                }
+                self.push(Value::Map(entries))?;
+            }
 
-                Instr::Jump(target) => {
-                    self.ip = target;
+            Instr::SetIndex => {
+                let v = self.stack.pop().ok_or("Stack underflow on SetIndex")?;
+                let key = self.stack.pop().ok_or("Stack underflow on SetIndex")?;
+                let target = self.stack.pop().ok_or("Stack underflow on SetIndex")?;
+                let updated = match (target, key) {
+                    (Value::List(mut items), Value::Int(i)) => {
+                        let len = items.len();
+                        let slot = usize::try_from(i)
+                            .ok()
+                            .and_then(|i| items.get_mut(i))
+                            .ok_or_else(|| index_lesson(i, len, "vm_list_index"))?;
+                        *slot = v;
+                        Value::List(items)
+                    }
+                    (Value::Map(mut entries), key) => {
+                        map_insert(&mut entries, key, v);
+                        Value::Map(entries)
+                    }
+                    (x, y) => {
+                        return Err(format!(
+                            "Type error on SetIndex: got {}[{}]",
+                            type_name(&x),
+                            type_name(&y)
+                        ))
+                    }
+                };
+                self.push(updated)?;
+            }
+
+            Instr::Has => {
+                let key = self.stack.pop().ok_or("Stack underflow on Has")?;
+                let map = self.stack.pop().ok_or("Stack underflow on Has")?;
+                match map {
+                    Value::Map(entries) => {
+                        let found = entries.iter().any(|(k, _)| *k == key);
+                        self.push(Value::Bool(found))?;
+                    }
+                    other => {
+                        return Err(format!(
+                            "Type error: Has needs Map, got {}",
+                            type_name(&other)
+                        ))
+                    }
                 }
+            }
 
-                Instr::JumpIfFalse(target) => {
-                    let v = self.stack.pop().ok_or("Stack underflow on JumpIfFalse")?;
-                    match v {
-                        Value::Bool(false) => self.ip = target,
-                        Value::Bool(true) => {}
+            Instr::MakeRecord(name, n) => {
+                let record_name = const_str(chunk, name)?;
+                let count = n.saturating_mul(2);
+                if self.stack.len() < count {
+                    return Err(format!(
+                        "Stack underflow: wanted {} record fields, but stack has {} values",
+                        n,
+                        self.stack.len()
+                    ));
+                }
+                let start = self.stack.len() - count;
+                let mut flat = self.stack.drain(start..).collect::<Vec<_>>().into_iter();
+                let mut fields = Vec::with_capacity(n);
+                while let (Some(field), Some(v)) = (flat.next(), flat.next()) {
+                    match field {
+                        Value::Str(field) => fields.push((field, v)),
                         other => {
                             return Err(format!(
-                                "Type error: JumpIfFalse needs Bool, got {}",
+                                "Type error: MakeRecord field names must be String, got {}",
                                 type_name(&other)
                             ))
                         }
                     }
                 }
+                self.push(Value::Record(record_name.to_string(), fields))?;
+            }
+
+            Instr::GetField(idx) => {
+                let field = const_str(chunk, idx)?;
+                let record = self.stack.pop().ok_or("Stack underflow on GetField")?;
+                let v = match record {
+                    Value::Record(name, fields) => fields
+                        .into_iter()
+                        .find(|(f, _)| f == field)
+                        .map(|(_, v)| v)
+                        .ok_or_else(|| format!("Record {} has no field `{}`", name, field))?,
+                    other => {
+                        return Err(format!(
+                            "Type error: GetField needs a Record, got {}",
+                            type_name(&other)
+                        ))
+                    }
+                };
+                self.push(v)?;
+            }
+
+            Instr::SetField(idx) => {
+                let field = const_str(chunk, idx)?;
+                let v = self.stack.pop().ok_or("Stack underflow on SetField")?;
+                let record = self.stack.pop().ok_or("Stack underflow on SetField")?;
+                match record {
+                    Value::Record(name, mut fields) => {
+                        let slot = fields
+                            .iter_mut()
+                            .find(|(f, _)| f == field)
+                            .ok_or_else(|| format!("Record {} has no field `{}`", name, field))?;
+                        slot.1 = v;
+                        self.push(Value::Record(name, fields))?;
+                    }
+                    other => {
+                        return Err(format!(
+                            "Type error: SetField needs a Record, got {}",
+                            type_name(&other)
+                        ))
+                    }
+                }
+            }
 
-                Instr::CmpEq => cmp_bin(self, |a, b| a == b)?,
-                Instr::CmpNe => cmp_bin(self, |a, b| a != b)?,
-                Instr::CmpLt => cmp_int(self, |a, b| a < b)?,
-                Instr::CmpLe => cmp_int(self, |a, b| a <= b)?,
-                Instr::CmpGt => cmp_int(self, |a, b| a > b)?,
-                Instr::CmpGe => cmp_int(self, |a, b| a >= b)?,
+            Instr::MakeVariant(name, tag, n) => {
+                let variant = const_str(chunk, name)?;
+                if self.stack.len() < n {
+                    return Err(format!(
+                        "Stack underflow: wanted {} variant values, but stack has {} values",
+                        n,
+                        self.stack.len()
+                    ));
+                }
+                let start = self.stack.len() - n;
+                let values = self.stack.drain(start..).collect();
+                self.push(Value::Variant(variant.to_string(), tag, values))?;
+            }
 
-                Instr::Halt => break,
+            Instr::Switch(n) => {
+                let v = self.stack.pop().ok_or("Stack underflow on Switch")?;
+                match v {
+                    // Land on the table entry for this tag.
+                    Value::Variant(_, tag, _) if tag < n => self.ip += tag,
+                    Value::Variant(name, tag, _) => {
+                        return Err(format!(
+                            "Variant {} has tag {}, but the Switch table only has {} entries",
+                            name, tag, n
+                        ))
+                    }
+                    other => {
+                        return Err(format!(
+                            "Type error: Switch needs a Variant, got {}",
+                            type_name(&other)
+                        ))
+                    }
+                }
+            }
+
+            Instr::Payload(i) => {
+                let v = self.stack.pop().ok_or("Stack underflow on Payload")?;
+                match v {
+                    Value::Variant(name, _, values) => {
+                        let item = values
+                            .into_iter()
+                            .nth(i)
+                            .ok_or_else(|| format!("Variant {} has no value {}", name, i))?;
+                        self.push(item)?;
+                    }
+                    other => {
+                        return Err(format!(
+                            "Type error: Payload needs a Variant, got {}",
+                            type_name(&other)
+                        ))
+                    }
+                }
+            }
+
+            Instr::Keys => {
+                let map = self.stack.pop().ok_or("Stack underflow on Keys")?;
+                match map {
+                    Value::Map(entries) => {
+                        let keys = entries.into_iter().map(|(k, _)| k).collect();
+                        self.push(Value::List(keys))?;
+                    }
+                    other => {
+                        return Err(format!(
+                            "Type error: Keys needs Map, got {}",
+                            type_name(&other)
+                        ))
+                    }
+                }
             }
         }
 
+        Ok(self.ip < chunk.code.len())
+    }
+
+    /// Index of the next instruction to execute.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn locals(&self) -> &[Value] {
+        &self.locals
+    }
+
+    /// How many function calls are in progress.
+    pub fn call_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Local slots of the function running now (`None` in main).
+    pub fn frame_locals(&self) -> Option<std::ops::Range<usize>> {
+        self.frames
+            .last()
+            .map(|f| f.first..f.first + f.saved.len())
+    }
+
+    fn trace_step(&self, chunk: &Chunk, ip: usize, before: &[Value], format: TraceFormat) {
+        eprintln!("{}", self.trace_line(chunk, ip, before, format));
+    }
+
+    /// The `--trace` line for instruction `ip`, run with `before` as the locals.
+    fn trace_line(&self, chunk: &Chunk, ip: usize, before: &[Value], format: TraceFormat) -> String {
+        let instr = format!("{:?}", chunk.code[ip]);
+        let top = self.stack.last();
+        let changed: Vec<(&str, &Value)> = self
+            .locals
+            .iter()
+            .enumerate()
+            .filter(|(i, v)| before.get(*i) != Some(*v))
+            .map(|(i, v)| (chunk.locals.get(i).map(String::as_str).unwrap_or("?"), v))
+            .collect();
+
+        match format {
+            TraceFormat::Text => {
+                let mut line = format!("[{:>4}] {:<24}", ip, instr);
+                if let Some(v) = top {
+                    line.push_str(&format!(" top={}", value_repr(v)));
+                }
+                for (name, v) in &changed {
+                    line.push_str(&format!(" {}={}", name, value_repr(v)));
+                }
+                line.trim_end().to_string()
+            }
+            TraceFormat::Json => {
+                let locals: serde_json::Map<String, serde_json::Value> = changed
+                    .iter()
+                    .map(|(name, v)| (name.to_string(), value_json(v)))
+                    .collect();
+                let entry = serde_json::json!({
+                    "ip": ip,
+                    "file": chunk.file_name(chunk.file_at(ip)),
+                    "line": chunk.line_at(ip),
+                    "instr": instr,
+                    "top": top.map(value_json),
+                    "locals": locals,
+                });
+                entry.to_string()
+            }
+        }
+    }
+
+    /// Pop a String operand of `instr`.
+    fn pop_str(&mut self, instr: &str) -> Result<String, String> {
+        match self.stack.pop() {
+            Some(Value::Str(s)) => Ok(s),
+            Some(other) => Err(format!(
+                "Type error: {} needs String, got {}",
+                instr,
+                type_name(&other)
+            )),
+            None => Err(format!("Stack underflow on {}", instr)),
+        }
+    }
+
+    /// Pop the top `n` values, in the order they were pushed.
+    fn pop_values(&mut self, n: usize, what: &str) -> Result<Vec<Value>, String> {
+        if self.stack.len() < n {
+            return Err(format!(
+                "Stack underflow: wanted to {} {} values, but stack has {}",
+                what,
+                n,
+                self.stack.len()
+            ));
+        }
+        let start = self.stack.len() - n;
+        Ok(self.stack.drain(start..).collect())
+    }
+
+    fn push(&mut self, v: Value) -> Result<(), String> {
+        if let Some(max) = self.limits.max_stack {
+            if self.stack.len() >= max {
+                return Err(runtime_lesson("vm_stack", "why", &[("max", &max)]));
+            }
+        }
+        self.stack.push(v);
+        Ok(())
+    }
+
+    /// Write program output. Returns `false` when stdout has been closed.
+    fn write_output(&mut self, text: &str) -> Result<bool, String> {
+        let out = match &mut self.output {
+            Output::Captured(out) => {
+                out.extend_from_slice(text.as_bytes());
+                return Ok(true);
+            }
+            Output::Stdout => io::stdout().lock().write_all(text.as_bytes()),
+        };
+        match out {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+            Err(e) => Err(format!("Can't write the program's output: {}", e)),
+        }
+    }
+
+    fn count_step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(runtime_lesson("vm_steps", "why", &[("max", &max)]));
+            }
+        }
+        Ok(())
+    }
+
+    fn charge_string(&mut self, len: usize) -> Result<(), String> {
+        self.string_bytes += len;
+        if let Some(max) = self.limits.max_string_bytes {
+            if self.string_bytes > max {
+                return Err(runtime_lesson("vm_string_bytes", "why", &[("max", &max)]));
+            }
+        }
         Ok(())
     }
 }
 
+
+/// Push out any output still buffered. A closed stdout has nothing left to
+/// show, so errors are ignored.
+pub fn flush_output() {
+    let _ = io::stdout().flush();
+}
+
+fn join_values(vals: &[Value], sep: &str) -> String {
+    vals.iter().map(value_to_string).collect::<Vec<_>>().join(sep)
+}
+
+/// `Format("{} of {}", 1, 3)` -> "1 of 3": each `{}` takes the next value.
+fn format_values(template: &str, vals: &[Value]) -> Result<String, String> {
+    let holes = template.matches("{}").count();
+    if holes != vals.len() {
+        return Err(runtime_lesson(
+            "vm_format_values",
+            "why",
+            &[("values", &vals.len()), ("holes", &holes), ("template", &template)],
+        ));
+    }
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    for v in vals {
+        let (before, after) = rest.split_once("{}").unwrap_or((rest, ""));
+        out.push_str(before);
+        out.push_str(&value_to_string(v));
+        rest = after;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// A runtime failure (a resource limit, a bad index) told the same way lesson
+/// errors are: what happened, why, and what to try. The text is table `lesson`
+/// of the catalog; `why` picks which explanation fits.
+fn runtime_lesson(lesson: &str, why: &str, args: &[(&str, &dyn Display)]) -> String {
+    let why = i18n::text(lesson, why, args);
+    let help = i18n::text(lesson, "help", args);
+    format!(
+        "{}\n\n{}\n{}",
+        i18n::text(lesson, "title", args),
+        i18n::text("ui", "why", &[("text", &why)]),
+        i18n::text("ui", "help", &[("text", &help)])
+    )
+}
+
+/// Out-of-range `xs[i]` / `s[i]`, explained like a lesson. `lesson` is
+/// `vm_list_index` or `vm_string_index`.
+fn index_lesson(index: i64, len: usize, lesson: &str) -> String {
+    let why = if len == 0 { "why_empty" } else { "why" };
+    let last = len.saturating_sub(1);
+    runtime_lesson(lesson, why, &[("index", &index), ("len", &len), ("last", &last)])
+}
+
+/// `Substring(s, start, end)` with positions that don't fit the string.
+fn substring_lesson(start: i64, end: i64, len: usize) -> String {
+    runtime_lesson("vm_substring", "why", &[("start", &start), ("end", &end), ("len", &len)])
+}
+
+/// `ToInt(x)` for a Float with no Int equivalent.
+fn to_int_lesson(f: f64) -> String {
+    runtime_lesson("vm_to_int", "why", &[("value", &value_to_string(&Value::Float(f)))])
+}
+
+/// `x + y` for Ints whose sum doesn't fit an Int.
+fn overflow_lesson(x: i64, y: i64) -> String {
+    runtime_lesson("vm_overflow", "why", &[("left", &x), ("right", &y)])
+}
+
+/// A field or type name operand: `consts[idx]` must be a String.
+fn const_str(chunk: &Chunk, idx: usize) -> Result<&str, String> {
+    match chunk.consts.get(idx) {
+        Some(Value::Str(s)) => Ok(s),
+        _ => Err(format!("Bad name constant {}", idx)),
+    }
+}
+
+/// `m[key]` for a key the map doesn't have.
+fn missing_key_lesson(key: &Value, entries: &[(Value, Value)]) -> String {
+    if entries.is_empty() {
+        return runtime_lesson("vm_missing_key", "why_empty", &[("key", &value_repr(key))]);
+    }
+    // Listing a few keys usually shows the typo.
+    let mut keys: Vec<String> = entries.iter().take(5).map(|(k, _)| value_repr(k)).collect();
+    if entries.len() > 5 {
+        keys.push("...".to_string());
+    }
+    runtime_lesson(
+        "vm_missing_key",
+        "why",
+        &[("key", &value_repr(key)), ("keys", &keys.join(", "))],
+    )
+}
+
 fn value_to_string(v: &Value) -> String {
     match v {
         Value::Int(i) => i.to_string(),
@@ -134,6 +996,73 @@ fn value_to_string(v: &Value) -> String {
         Value::Char(c) => c.to_string(),
         Value::Str(s) => s.clone(),
         Value::Unit => "()".to_string(),
+        Value::List(items) => list_string(items),
+        Value::Map(entries) => map_string(entries),
+        Value::Record(name, fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(f, v)| format!("{}: {}", f, value_repr(v)))
+                .collect();
+            format!("{} {{ {} }}", name, fields.join(", "))
+        }
+        // Debug keeps the `.0` on whole numbers, so 2.0 doesn't print like the Int 2.
+        Value::Float(f) => format!("{:?}", f),
+        Value::Variant(name, _, values) if values.is_empty() => name.clone(),
+        Value::Variant(name, _, values) => {
+            let values: Vec<String> = values.iter().map(value_repr).collect();
+            format!("{}({})", name, values.join(", "))
+        }
+    }
+}
+
+fn list_string(items: &[Value]) -> String {
+    let items: Vec<String> = items.iter().map(value_repr).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn map_string(entries: &[(Value, Value)]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|(k, v)| format!("{}: {}", value_repr(k), value_repr(v)))
+        .collect();
+    format!("{{{}}}", entries.join(", "))
+}
+
+/// Like `value_to_string`, but quotes text so it reads as A source
+/// (used by the debugger, where `"1"` and `1` must look different).
+pub fn value_repr(v: &Value) -> String {
+    match v {
+        Value::Str(s) => format!("{:?}", s),
+        Value::Char(c) => format!("{:?}", c),
+        other => value_to_string(other),
+    }
+}
+
+fn value_json(v: &Value) -> serde_json::Value {
+    match v {
+        Value::Int(i) => serde_json::json!(i),
+        Value::Bool(b) => serde_json::json!(b),
+        Value::Char(c) => serde_json::json!(c.to_string()),
+        Value::Str(s) => serde_json::json!(s),
+        Value::Unit => serde_json::Value::Null,
+        Value::List(items) => serde_json::Value::Array(items.iter().map(value_json).collect()),
+        // JSON object keys are text, so other key types are written as A would print them.
+        Value::Map(entries) => serde_json::Value::Object(
+            entries
+                .iter()
+                .map(|(k, v)| (value_to_string(k), value_json(v)))
+                .collect(),
+        ),
+        Value::Record(_, fields) => serde_json::Value::Object(
+            fields
+                .iter()
+                .map(|(f, v)| (f.clone(), value_json(v)))
+                .collect(),
+        ),
+        Value::Float(f) => serde_json::json!(f),
+        Value::Variant(name, _, values) => serde_json::json!({
+            name.clone(): serde_json::Value::Array(values.iter().map(value_json).collect())
+        }),
     }
 }
 
@@ -144,6 +1073,11 @@ fn type_name(v: &Value) -> &'static str {
         Value::Char(_) => "Char",
         Value::Str(_) => "String",
         Value::Unit => "Unit",
+        Value::List(_) => "List",
+        Value::Map(_) => "Map",
+        Value::Record(..) => "Record",
+        Value::Variant(..) => "Variant",
+        Value::Float(_) => "Float",
     }
 }
 
@@ -157,21 +1091,180 @@ where
     Ok(())
 }
 
-fn cmp_int<F>(vm: &mut Vm, f: F) -> Result<(), String>
+fn cmp_float<F>(vm: &mut Vm, f: F) -> Result<(), String>
 where
-    F: FnOnce(i64, i64) -> bool,
+    F: FnOnce(f64, f64) -> bool,
 {
     let b = vm.stack.pop().ok_or("Stack underflow on comparison")?;
     let a = vm.stack.pop().ok_or("Stack underflow on comparison")?;
     match (a, b) {
-        (Value::Int(x), Value::Int(y)) => {
+        (Value::Float(x), Value::Float(y)) => {
             vm.stack.push(Value::Bool(f(x, y)));
             Ok(())
         }
         (x, y) => Err(format!(
-            "Type error: int comparison needs Int/Int, got {} and {}",
+            "Type error: float comparison needs Float/Float, got {} and {}",
             type_name(&x),
             type_name(&y)
         )),
     }
 }
+
+/// Ordering comparison of two Ints, two Strings (dictionary order) or two Chars.
+fn cmp_ord<F>(vm: &mut Vm, f: F) -> Result<(), String>
+where
+    F: FnOnce(Ordering) -> bool,
+{
+    let b = vm.stack.pop().ok_or("Stack underflow on comparison")?;
+    let a = vm.stack.pop().ok_or("Stack underflow on comparison")?;
+    let order = match (&a, &b) {
+        (Value::Int(x), Value::Int(y)) => x.cmp(y),
+        (Value::Str(x), Value::Str(y)) => x.cmp(y),
+        (Value::Char(x), Value::Char(y)) => x.cmp(y),
+        _ => {
+            return Err(format!(
+                "Type error: comparison needs Int/Int, String/String or Char/Char, got {} and {}",
+                type_name(&a),
+                type_name(&b)
+            ))
+        }
+    };
+    vm.stack.push(Value::Bool(f(order)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joined_text_counts_toward_the_string_limit() {
+        let mut chunk = Chunk::new();
+        let a = chunk.add_const(Value::Str("abcd".to_string()));
+        let b = chunk.add_const(Value::Str("efgh".to_string()));
+        chunk.push(Instr::LoadConst(a), 1);
+        chunk.push(Instr::LoadConst(b), 1);
+        chunk.push(Instr::Concat, 1);
+        chunk.push(Instr::Halt, 1);
+
+        let limits = |max| VmLimits { max_string_bytes: Some(max), ..VmLimits::default() };
+        assert!(Vm::with_limits(limits(8)).run(&chunk).is_ok());
+        let err = Vm::with_limits(limits(7)).run(&chunk).unwrap_err();
+        assert!(err.contains("too much text"), "{}", err);
+    }
+
+    fn chunk_of(code: &[Instr]) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.add_const(Value::Int(1));
+        for &instr in code {
+            chunk.push(instr, 1);
+        }
+        chunk
+    }
+
+    #[test]
+    fn endless_loops_stop_at_the_step_limit() {
+        let chunk = chunk_of(&[Instr::Jump(0)]);
+        let limits = VmLimits { max_steps: Some(50), ..VmLimits::default() };
+        let err = Vm::with_limits(limits).run(&chunk).unwrap_err();
+        assert!(err.contains("more than 50 steps"), "{}", err);
+    }
+
+    #[test]
+    fn piling_up_values_stops_at_the_stack_limit() {
+        let chunk = chunk_of(&[Instr::LoadConst(0), Instr::Jump(0)]);
+        let limits = VmLimits { max_stack: Some(10), ..VmLimits::default() };
+        let err = Vm::with_limits(limits).run(&chunk).unwrap_err();
+        assert!(err.contains("more than 10 values"), "{}", err);
+    }
+
+    #[test]
+    fn endless_recursion_stops_at_the_call_depth_limit() {
+        let chunk = chunk_of(&[Instr::Call(1, 0), Instr::Enter(0, 0), Instr::Call(1, 0), Instr::Ret]);
+        let limits = VmLimits { max_call_depth: Some(20), ..VmLimits::default() };
+        let err = Vm::with_limits(limits).run(&chunk).unwrap_err();
+        assert!(err.contains("more than 20 calls"), "{}", err);
+
+        // Unlimited, the step budget would be the only way out.
+        let limits = VmLimits { max_steps: Some(1_000), ..VmLimits::unlimited() };
+        let err = Vm::with_limits(limits).run(&chunk).unwrap_err();
+        assert!(err.contains("more than 1000 steps"), "{}", err);
+    }
+
+    #[test]
+    fn int_overflow_stops_the_program() {
+        let mut chunk = Chunk::new();
+        let max = chunk.add_const(Value::Int(i64::MAX));
+        let one = chunk.add_const(Value::Int(1));
+        chunk.push(Instr::LoadConst(max), 1);
+        chunk.push(Instr::LoadConst(one), 1);
+        chunk.push(Instr::AddInt, 1);
+        chunk.push(Instr::Halt, 1);
+
+        let err = Vm::new().run(&chunk).unwrap_err();
+        assert!(err.contains("doesn't fit in an Int"), "{}", err);
+    }
+
+    #[test]
+    fn given_input_and_captured_output() {
+        let mut chunk = Chunk::new();
+        chunk.push(Instr::ReadLine, 1);
+        chunk.push(Instr::ReadLine, 1);
+        chunk.push(Instr::ReadLine, 1);
+        chunk.push(Instr::Print(3), 1);
+        chunk.push(Instr::Halt, 1);
+
+        let mut vm = Vm::new();
+        vm.give_input("one\r\ntwo\n");
+        vm.capture_output();
+        vm.run(&chunk).unwrap();
+        // The third line is past the end of the input.
+        assert_eq!(vm.take_output(), "one two \n");
+        assert_eq!(vm.take_output(), "");
+    }
+
+    /// Every `--trace` line of running `chunk`.
+    fn trace(chunk: &Chunk, format: TraceFormat) -> Vec<String> {
+        let mut vm = Vm::new();
+        vm.start(chunk);
+        let mut lines = Vec::new();
+        while vm.ip < chunk.code.len() {
+            let (ip, before) = (vm.ip, vm.locals.clone());
+            vm.exec(chunk).unwrap();
+            lines.push(vm.trace_line(chunk, ip, &before, format));
+        }
+        lines
+    }
+
+    fn traced_chunk() -> Chunk {
+        let mut chunk = chunk_of(&[]);
+        chunk.locals.push("x".to_string());
+        chunk.file_names.push("main.a".to_string());
+        chunk.push(Instr::LoadConst(0), 2);
+        chunk.push(Instr::StoreLocal(0), 2);
+        chunk.push(Instr::Halt, 0);
+        chunk
+    }
+
+    #[test]
+    fn text_trace_shows_the_top_and_changed_locals() {
+        assert_eq!(
+            trace(&traced_chunk(), TraceFormat::Text),
+            ["[   0] LoadConst(0)             top=1", "[   1] StoreLocal(0)            x=1", "[   2] Halt"]
+        );
+    }
+
+    #[test]
+    fn json_trace_has_one_object_per_instruction() {
+        let lines = trace(&traced_chunk(), TraceFormat::Json);
+        let entries: Vec<serde_json::Value> = lines.iter().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(
+            entries[0],
+            serde_json::json!({
+                "ip": 0, "file": "main.a", "line": 2, "instr": "LoadConst(0)", "top": 1, "locals": {}
+            })
+        );
+        assert_eq!(entries[1]["locals"], serde_json::json!({ "x": 1 }));
+        assert_eq!(entries[2]["line"], serde_json::Value::Null);
+    }
+}